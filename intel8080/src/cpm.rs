@@ -0,0 +1,166 @@
+//! A minimal stand-in for the CP/M operating system, enough to run the classic cpu
+//! exerciser programs (TST8080, 8080PRE, CPUTEST, 8080EXM, ...).
+//!
+//! The program is loaded at 0x100. The BDOS, called at 0x0005, is a few instructions of
+//! 8080 code that implement the console output functions (2 and 9) by writing to a port,
+//! and a jump to 0x0000 (warm boot) writes to another port and halts. So the stand-in runs
+//! under any loop that drives the interpreter, like the one of the debugger.
+
+use std::fmt;
+
+use crate::intel8080::{IODevices, Memory};
+use crate::interpreter::Interpreter;
+
+/// Where CP/M loads the programs, the start of the Transient Program Area.
+pub const TPA_START: u16 = 0x0100;
+const BDOS_ENTRY: u16 = 0x0005;
+/// The top of the TPA, where the BDOS code is. Programs read it from 0x0006 to place their
+/// stack.
+const BDOS_BASE: u16 = 0xfe00;
+
+/// Each character written by the BDOS console output.
+const CONSOLE_PORT: u8 = 0;
+/// Written by the warm boot.
+const EXIT_PORT: u8 = 1;
+
+/// The BDOS at BDOS_BASE. Function 0 warm boots, 2 prints E and 9 prints the string at DE,
+/// terminated by '$'. The others do nothing.
+#[rustfmt::skip]
+const BDOS: [u8; 0x1b] = [
+    0x79,               // fe00: MOV A, C
+    0xb7,               // fe01: ORA A
+    0xca, 0x00, 0x00,   // fe02: JZ 0000
+    0xfe, 0x02,         // fe05: CPI 2
+    0xca, 0x17, 0xfe,   // fe07: JZ fe17
+    0xfe, 0x09,         // fe0a: CPI 9
+    0xc0,               // fe0c: RNZ
+    0x1a,               // fe0d: LDAX D
+    0xfe, b'$',         // fe0e: CPI '$'
+    0xc8,               // fe10: RZ
+    0xd3, CONSOLE_PORT, // fe11: OUT CONSOLE_PORT
+    0x13,               // fe13: INX D
+    0xc3, 0x0d, 0xfe,   // fe14: JMP fe0d
+    0x7b,               // fe17: MOV A, E
+    0xd3, CONSOLE_PORT, // fe18: OUT CONSOLE_PORT
+    0xc9,               // fe1a: RET
+];
+
+#[derive(Debug)]
+pub enum CpmError {
+    /// The program does not fit in the TPA.
+    ProgramTooBig(usize),
+    /// The program didn't exit in the given number of clocks. Contains the output until then.
+    Timeout(String),
+    /// The cpu halted with interrupts disabled. Contains the output until then.
+    Halted(String),
+}
+
+impl fmt::Display for CpmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpmError::ProgramTooBig(len) => write!(f, "program of {} bytes is too big", len),
+            CpmError::Timeout(output) => write!(f, "program timed out, output:\n{}", output),
+            CpmError::Halted(output) => write!(f, "cpu halted, output:\n{}", output),
+        }
+    }
+}
+
+impl std::error::Error for CpmError {}
+
+pub struct CpmMemory {
+    memory: Box<[u8]>,
+}
+impl Memory for CpmMemory {
+    #[inline]
+    fn read(&self, adress: u16) -> u8 {
+        self.memory[adress as usize]
+    }
+
+    #[inline]
+    fn write(&mut self, adress: u16, value: u8) {
+        self.memory[adress as usize] = value;
+    }
+
+    fn get_rom(&mut self) -> Vec<u8> {
+        self.memory.to_vec()
+    }
+}
+
+/// The ports written by the BDOS. The programs themselves don't use any port.
+#[derive(Default)]
+pub struct CpmDevices {
+    output: String,
+    exited: bool,
+}
+impl CpmDevices {
+    /// Take the console output written since the last call.
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+
+    /// If the program has ended, with a warm boot.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+}
+impl IODevices for CpmDevices {
+    fn read(&mut self, _: u8) -> u8 {
+        0
+    }
+
+    fn write(&mut self, device: u8, value: u8) {
+        match device {
+            CONSOLE_PORT => self.output.push(value as char),
+            EXIT_PORT => self.exited = true,
+            _ => {}
+        }
+    }
+
+    fn print_state(&self, w: &mut dyn fmt::Write) {
+        writeln!(w, "exited: {}", self.exited).unwrap();
+    }
+}
+
+/// Create a interpreter with the program loaded in the TPA, and the page zero set up.
+pub fn create_interpreter(program: &[u8]) -> Result<Interpreter<CpmMemory, CpmDevices>, CpmError> {
+    let start = TPA_START as usize;
+    if program.len() > (BDOS_BASE - TPA_START) as usize {
+        return Err(CpmError::ProgramTooBig(program.len()));
+    }
+
+    let mut memory = vec![0; 0x10000].into_boxed_slice();
+    memory[start..start + program.len()].copy_from_slice(program);
+
+    // warm boot
+    memory[0x0000..0x0003].copy_from_slice(&[0xd3, EXIT_PORT, 0x76]); // OUT EXIT_PORT; HLT
+    // JMP BDOS_BASE
+    memory[BDOS_ENTRY as usize] = 0xc3;
+    memory[0x0006..0x0008].copy_from_slice(&BDOS_BASE.to_le_bytes());
+    let bdos = BDOS_BASE as usize;
+    memory[bdos..bdos + BDOS.len()].copy_from_slice(&BDOS);
+
+    let mut interpreter =
+        Interpreter::new(CpmDevices::default(), CpmMemory { memory }, &[TPA_START]);
+
+    // the CCP calls the program, so a RET from it is a warm boot
+    let sp = BDOS_BASE - 2;
+    interpreter.memory.write(sp, 0x00);
+    interpreter.memory.write(sp + 1, 0x00);
+    interpreter.state_mut().set_SP(sp);
+
+    Ok(interpreter)
+}
+
+/// Run a CP/M program until it warm boots, returning everything it printed to the console.
+pub fn run(program: &[u8], max_clocks: u64) -> Result<String, CpmError> {
+    let mut interpreter = create_interpreter(program)?;
+    while !interpreter.devices.has_exited() {
+        if interpreter.clock_count() >= max_clocks {
+            return Err(CpmError::Timeout(interpreter.devices.take_output()));
+        }
+        if !interpreter.step() {
+            return Err(CpmError::Halted(interpreter.devices.take_output()));
+        }
+    }
+    Ok(interpreter.devices.take_output())
+}
@@ -0,0 +1,473 @@
+//! The state of the debugger, driven by a frontend (a terminal, a GUI, a remote client)
+//! through `Interpreter::debug_command`. The debugger never blocks: a run stopped by it
+//! returns a `StopReason`, and the cpu stays stopped until a command resumes it.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::{Range, RangeInclusive},
+    vec::Drain,
+};
+
+use crate::{
+    I8080State, Memory,
+    expression::{Context, Expression, Message},
+    interpreter::StopReason,
+};
+
+/// A register that can be written by `Command::WriteRegister`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    Flags,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    SP,
+    PC,
+}
+
+/// The accesses that trigger a watchpoint. For the ports, a read is a IN and a write is a
+/// OUT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+impl Access {
+    fn matches(self, write: bool) -> bool {
+        match self {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchTarget {
+    Memory(RangeInclusive<u16>),
+    Port(u8),
+}
+
+/// Stop the cpu after a instruction that accesses a adress range or a I/O port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub target: WatchTarget,
+    pub access: Access,
+    /// Only trigger when this value is read or written.
+    pub value: Option<u8>,
+    /// Only trigger when this is true, after the instruction.
+    pub condition: Option<Expression>,
+}
+impl Watchpoint {
+    pub fn memory(range: RangeInclusive<u16>, access: Access) -> Self {
+        Self {
+            target: WatchTarget::Memory(range),
+            access,
+            value: None,
+            condition: None,
+        }
+    }
+
+    pub fn port(port: u8, access: Access) -> Self {
+        Self {
+            target: WatchTarget::Port(port),
+            access,
+            value: None,
+            condition: None,
+        }
+    }
+
+    pub fn with_value(self, value: u8) -> Self {
+        Self {
+            value: Some(value),
+            ..self
+        }
+    }
+
+    pub fn with_condition(self, condition: Expression) -> Self {
+        Self {
+            condition: Some(condition),
+            ..self
+        }
+    }
+
+    fn triggers(&self, value: u8, write: bool) -> bool {
+        self.access.matches(write) && self.value.is_none_or(|v| v == value)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match (&self.target, self.access) {
+            (WatchTarget::Memory(_), Access::Read) => "r",
+            (WatchTarget::Memory(_), Access::Write) => "w",
+            (WatchTarget::Memory(_), Access::ReadWrite) => "rw",
+            (WatchTarget::Port(_), Access::Read) => "in",
+            (WatchTarget::Port(_), Access::Write) => "out",
+            (WatchTarget::Port(_), Access::ReadWrite) => "inout",
+        };
+        match &self.target {
+            WatchTarget::Memory(range) if range.start() == range.end() => {
+                write!(f, "{:04x} {}", range.start(), access)?
+            }
+            WatchTarget::Memory(range) => {
+                write!(f, "{:04x}-{:04x} {}", range.start(), range.end(), access)?
+            }
+            WatchTarget::Port(port) => write!(f, "port {:02x} {}", port, access)?,
+        }
+        if let Some(value) = self.value {
+            write!(f, " ={:02x}", value)?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+/// A breakpoint, that can have a condition, ignore its first hits, or only log a message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Breakpoint {
+    /// Only hit when this is true.
+    pub condition: Option<Expression>,
+    /// The number of hits ignored before it triggers.
+    pub ignore: u32,
+    /// Log this message, instead of stopping the cpu: a tracepoint.
+    pub log: Option<Message>,
+    /// The number of times it was hit, counting the ignored ones.
+    pub hits: u32,
+}
+impl Breakpoint {
+    pub fn with_condition(self, condition: Expression) -> Self {
+        Self {
+            condition: Some(condition),
+            ..self
+        }
+    }
+
+    pub fn with_ignore(self, ignore: u32) -> Self {
+        Self { ignore, ..self }
+    }
+
+    pub fn with_log(self, log: Message) -> Self {
+        Self {
+            log: Some(log),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hits", self.hits)?;
+        if let Some(condition) = &self.condition {
+            write!(f, ", if {}", condition)?;
+        }
+        if self.ignore > 0 {
+            write!(f, ", after {}", self.ignore)?;
+        }
+        if let Some(log) = &self.log {
+            write!(f, ", log {}", log)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Execute a single instruction.
+    Step,
+    /// Execute a single instruction, but run a CALL or RST until it returns.
+    StepOver,
+    /// Run until the current subroutine returns.
+    StepOut,
+    Continue,
+    /// Stop the cpu at the next instruction.
+    Pause,
+    AddBreakpoint(u16),
+    /// Add a conditional breakpoint or a tracepoint, replacing the one at the adress.
+    SetBreakpoint(u16, Breakpoint),
+    RemoveBreakpoint(u16),
+    AddWatchpoint(Watchpoint),
+    RemoveWatchpoint(Watchpoint),
+    /// Request a interrupt, with the opcode placed in the data bus.
+    Interrupt(u8),
+    ReadRegisters,
+    WriteRegister(Register, u16),
+    ReadMemory {
+        adress: u16,
+        len: u16,
+    },
+    WriteMemory {
+        adress: u16,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// The command was executed.
+    Done,
+    /// The cpu runs again, in the next run of the interpreter.
+    Resumed,
+    /// The cpu executed the instruction, and is stopped.
+    Stopped(StopReason),
+    Registers(I8080State),
+    Memory(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Running,
+    Stopped(StopReason),
+    /// Running until the PC reaches 'adress', with the stack at or above 'sp'.
+    StepOver {
+        adress: u16,
+        sp: u16,
+    },
+    /// Running until the stack is popped above 'sp'.
+    StepOut {
+        sp: u16,
+    },
+}
+
+/// A data access or a IN/OUT of the current instruction, checked against the watchpoints
+/// after it.
+#[derive(Debug, Clone, Copy)]
+struct WatchedAccess {
+    port: bool,
+    adress: u16,
+    value: u8,
+    write: bool,
+}
+
+pub struct Debugger {
+    mode: Mode,
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    accesses: Vec<WatchedAccess>,
+    /// The messages of the tracepoints, not taken yet by the frontend.
+    log: Vec<String>,
+    /// The ranges of the ROM traced as code.
+    traced: Vec<Range<u16>>,
+}
+impl Debugger {
+    pub fn new(traced: Vec<Range<u16>>) -> Self {
+        Self {
+            mode: Mode::Running,
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            accesses: Vec::new(),
+            log: Vec::new(),
+            traced,
+        }
+    }
+
+    /// If the cpu is stopped, waiting for a command to resume it.
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.mode, Mode::Stopped(_))
+    }
+
+    /// Why the cpu is stopped, if it is.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        match self.mode {
+            Mode::Stopped(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Stop the cpu at the next instruction.
+    pub fn pause(&mut self) {
+        if !self.is_stopped() {
+            self.mode = Mode::Stopped(StopReason::Paused);
+        }
+    }
+
+    pub(crate) fn stop(&mut self, reason: StopReason) {
+        self.mode = Mode::Stopped(reason);
+    }
+
+    pub(crate) fn resume(&mut self) {
+        self.mode = Mode::Running;
+    }
+
+    pub(crate) fn step_over(&mut self, adress: u16, sp: u16) {
+        self.mode = Mode::StepOver { adress, sp };
+    }
+
+    pub(crate) fn step_out(&mut self, sp: u16) {
+        self.mode = Mode::StepOut { sp };
+    }
+
+    /// Add a unconditional breakpoint, keeping the one already at the adress.
+    pub fn add_breakpoint(&mut self, adress: u16) {
+        self.breakpoints.entry(adress).or_default();
+    }
+
+    pub fn set_breakpoint(&mut self, adress: u16, breakpoint: Breakpoint) {
+        self.breakpoints.insert(adress, breakpoint);
+    }
+
+    pub fn remove_breakpoint(&mut self, adress: u16) {
+        self.breakpoints.remove(&adress);
+    }
+
+    pub fn has_breakpoint(&self, adress: u16) -> bool {
+        self.breakpoints.contains_key(&adress)
+    }
+
+    pub fn breakpoint(&self, adress: u16) -> Option<&Breakpoint> {
+        self.breakpoints.get(&adress)
+    }
+
+    /// The adresses of the breakpoints, in ascending order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    /// Take the messages logged by the tracepoints.
+    pub fn drain_log(&mut self) -> Drain<'_, String> {
+        self.log.drain(..)
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) {
+        self.watchpoints.retain(|w| w != watchpoint);
+    }
+
+    /// The watchpoints, in the order they were added.
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Record a data access of the cpu: not the opcode fetches and the immediates.
+    pub(crate) fn on_memory_access(&mut self, adress: u16, value: u8, write: bool) {
+        if !self.watchpoints.is_empty() {
+            self.accesses.push(WatchedAccess {
+                port: false,
+                adress,
+                value,
+                write,
+            });
+        }
+    }
+
+    /// Record a IN or OUT instruction.
+    pub(crate) fn on_port_access(&mut self, port: u8, value: u8, write: bool) {
+        if !self.watchpoints.is_empty() {
+            self.accesses.push(WatchedAccess {
+                port: true,
+                adress: port as u16,
+                value,
+                write,
+            });
+        }
+    }
+
+    /// Check the accesses of the last instruction, stopping the cpu if they trigger a
+    /// watchpoint.
+    pub(crate) fn check_watchpoints<M: Memory>(&mut self, state: &I8080State, memory: &M) {
+        for access in std::mem::take(&mut self.accesses) {
+            if self.is_stopped() {
+                break;
+            }
+            let context = Context {
+                state,
+                memory,
+                access: Some((access.adress, access.value)),
+            };
+            let hit = self.watchpoints.iter().any(|w| {
+                let target = match &w.target {
+                    WatchTarget::Memory(range) => !access.port && range.contains(&access.adress),
+                    WatchTarget::Port(port) => access.port && *port as u16 == access.adress,
+                };
+                target
+                    && w.triggers(access.value, access.write)
+                    && w.condition.as_ref().is_none_or(|c| c.is_true(&context))
+            });
+            if hit {
+                self.mode = Mode::Stopped(if access.port {
+                    StopReason::PortWatch {
+                        port: access.adress as u8,
+                        value: access.value,
+                        write: access.write,
+                    }
+                } else {
+                    StopReason::MemoryWatch {
+                        adress: access.adress,
+                        value: access.value,
+                        write: access.write,
+                    }
+                });
+            }
+        }
+    }
+
+    /// The ranges of the ROM traced as code, for `dissasembler::dissasembly_around`.
+    pub fn traced(&self) -> &[Range<u16>] {
+        &self.traced
+    }
+
+    /// Check the cpu after each instruction of a run, stopping it when needed.
+    pub(crate) fn after_step<M: Memory>(&mut self, state: &I8080State, memory: &M) {
+        self.check_watchpoints(state, memory);
+        if self.is_stopped() {
+            return;
+        }
+        let pc = state.get_PC();
+        if let Some(breakpoint) = self.breakpoints.get_mut(&pc) {
+            let context = Context {
+                state,
+                memory,
+                access: None,
+            };
+            if breakpoint
+                .condition
+                .as_ref()
+                .is_none_or(|c| c.is_true(&context))
+            {
+                breakpoint.hits += 1;
+                if breakpoint.hits > breakpoint.ignore {
+                    match &breakpoint.log {
+                        Some(log) => self.log.push(log.format(&context)),
+                        None => {
+                            self.mode = Mode::Stopped(StopReason::Breakpoint(pc));
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+        match self.mode {
+            Mode::StepOver { adress, sp } if pc == adress && state.get_SP() >= sp => {
+                self.mode = Mode::Stopped(StopReason::Step);
+            }
+            Mode::StepOut { sp } if state.get_SP() > sp => {
+                self.mode = Mode::Stopped(StopReason::Step);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The size of the instruction if it is a CALL, a conditional call or a RST.
+pub(crate) fn call_size(opcode: u8) -> Option<u16> {
+    if opcode & 0b11_001_111 == 0b11_001_101 || opcode & 0b11_000_111 == 0b11_000_100 {
+        Some(3)
+    } else if opcode & 0b11_000_111 == 0b11_000_111 {
+        Some(1)
+    } else {
+        None
+    }
+}
@@ -195,7 +195,22 @@ fn jump_instruction<W: Write>(
 
 // return the next offset and jump destiny, if any
 fn trace_opcode(pc: u16, rom: &[u8]) -> (u8, Option<u16>) {
-    match rom[pc as usize] {
+    // a opcode, or its operands, past the end of the rom ends the trace
+    let Some(&opcode) = rom.get(pc as usize) else {
+        return (8, None);
+    };
+    let size = match opcode {
+        0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e | 0xc6 | 0xce | 0xd3 | 0xd6
+        | 0xdb | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe => 2,
+        0x01 | 0x11 | 0x21 | 0x22 | 0x2a | 0x31 | 0x32 | 0x3a | 0xc2 | 0xc3 | 0xc4 | 0xca
+        | 0xcc | 0xcd | 0xd2 | 0xd4 | 0xda | 0xdc | 0xe2 | 0xe4 | 0xea | 0xec | 0xf2 | 0xf4
+        | 0xfa | 0xfc => 3,
+        _ => 1,
+    };
+    if pc as usize + size > rom.len() {
+        return (8, None);
+    }
+    match opcode {
         0x00 | 0x02 | 0x03 | 0x04 | 0x05 | 0x07 | 0x09 | 0x0a | 0x0b | 0x0c | 0x0d | 0x0f
         | 0x12 | 0x13 | 0x14 | 0x15 | 0x17 | 0x19 | 0x1a | 0x1b | 0x1c | 0x1d | 0x1f | 0x23
         | 0x24 | 0x25 | 0x27 | 0x29 | 0x2b | 0x2c | 0x2d | 0x2f | 0x33 | 0x34 | 0x35 | 0x37
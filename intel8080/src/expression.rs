@@ -0,0 +1,466 @@
+//! A small expression language over the registers, the flags and the memory, used by the
+//! conditions of the breakpoints and watchpoints, and by the messages of the tracepoints.
+//!
+//! The numbers are hexadecimal, like everywhere in the debugger: `20ea`, or `0x20ea`. A
+//! number that starts with a letter needs a leading 0 (`0c0`), because `c` is a register.
+//! A decimal number starts with `#`, like `#16`.
+//!
+//! - registers: `A B C D E H L F BC DE HL SP PC PSW`
+//! - flags, that are 0 or 1: `S Z AC P CY`
+//! - `[<EXPR>]` reads a byte, and `w[<EXPR>]` reads a little endian word of the memory
+//! - `value` and `adress` are the value and adress (or port) of the access that triggered a
+//!   watchpoint, and are 0 elsewhere
+//! - the operators, from the lowest precedence: `||`, `&&`, `|`, `^`, `&`, `== !=`,
+//!   `< <= > >=`, `<< >>`, `+ -`, and the unary `! ~ -`
+//!
+//! The comparisons and the logical operators result in 1 or 0, and any non-zero value is
+//! true.
+
+use std::fmt;
+
+use crate::{I8080State, Memory};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    UnexpectedEnd,
+    /// A unexpected character, at the given byte position.
+    Unexpected(usize, char),
+    UnknownName(String),
+    InvalidNumber(String),
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of the expression"),
+            ParseError::Unexpected(position, c) => {
+                write!(f, "unexpected '{}' at position {}", c, position)
+            }
+            ParseError::UnknownName(name) => write!(f, "unknown name '{}'", name),
+            ParseError::InvalidNumber(number) => write!(f, "invalid number '{}'", number),
+        }
+    }
+}
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Name {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    F,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    Psw,
+    Sign,
+    Zero,
+    AuxCarry,
+    Parity,
+    Carry,
+    Value,
+    Adress,
+}
+impl Name {
+    fn parse(name: &str) -> Option<Name> {
+        Some(match name.to_ascii_uppercase().as_str() {
+            "A" => Name::A,
+            "B" => Name::B,
+            "C" => Name::C,
+            "D" => Name::D,
+            "E" => Name::E,
+            "H" => Name::H,
+            "L" => Name::L,
+            "F" => Name::F,
+            "BC" => Name::BC,
+            "DE" => Name::DE,
+            "HL" => Name::HL,
+            "SP" => Name::SP,
+            "PC" => Name::PC,
+            "PSW" => Name::Psw,
+            "S" => Name::Sign,
+            "Z" => Name::Zero,
+            "AC" => Name::AuxCarry,
+            "P" => Name::Parity,
+            "CY" => Name::Carry,
+            "VALUE" => Name::Value,
+            "ADRESS" => Name::Adress,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Not,
+    Complement,
+    Negate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Sub,
+}
+impl BinaryOp {
+    /// The operators of each precedence level, from the lowest.
+    const LEVELS: &'static [&'static [(&'static str, BinaryOp)]] = &[
+        &[("||", BinaryOp::Or)],
+        &[("&&", BinaryOp::And)],
+        &[("|", BinaryOp::BitOr)],
+        &[("^", BinaryOp::BitXor)],
+        &[("&", BinaryOp::BitAnd)],
+        &[("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual)],
+        &[
+            ("<=", BinaryOp::LessEqual),
+            (">=", BinaryOp::GreaterEqual),
+            ("<", BinaryOp::Less),
+            (">", BinaryOp::Greater),
+        ],
+        &[("<<", BinaryOp::ShiftLeft), (">>", BinaryOp::ShiftRight)],
+        &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    ];
+
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            BinaryOp::Or => (a != 0 || b != 0) as i64,
+            BinaryOp::And => (a != 0 && b != 0) as i64,
+            BinaryOp::BitOr => a | b,
+            BinaryOp::BitXor => a ^ b,
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::Equal => (a == b) as i64,
+            BinaryOp::NotEqual => (a != b) as i64,
+            BinaryOp::Less => (a < b) as i64,
+            BinaryOp::LessEqual => (a <= b) as i64,
+            BinaryOp::Greater => (a > b) as i64,
+            BinaryOp::GreaterEqual => (a >= b) as i64,
+            BinaryOp::ShiftLeft => a.wrapping_shl(b as u32),
+            BinaryOp::ShiftRight => a.wrapping_shr(b as u32),
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Name(Name),
+    Byte(Box<Node>),
+    Word(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+/// What a expression can read while it is evaluated.
+pub struct Context<'a, M: Memory> {
+    pub state: &'a I8080State,
+    pub memory: &'a M,
+    /// The adress (or port) and the value of the access that triggered a watchpoint.
+    pub access: Option<(u16, u8)>,
+}
+impl<M: Memory> Context<'_, M> {
+    fn name(&self, name: Name) -> i64 {
+        let state = self.state;
+        let flag = |mask: u8| (state.Flags & mask != 0) as i64;
+        (match name {
+            Name::A => state.A as u16,
+            Name::B => state.B as u16,
+            Name::C => state.C as u16,
+            Name::D => state.D as u16,
+            Name::E => state.E as u16,
+            Name::H => state.H as u16,
+            Name::L => state.L as u16,
+            Name::F => state.Flags as u16,
+            Name::BC => u16::from_le_bytes([state.C, state.B]),
+            Name::DE => u16::from_le_bytes([state.E, state.D]),
+            Name::HL => u16::from_le_bytes([state.L, state.H]),
+            Name::SP => state.get_SP(),
+            Name::PC => state.get_PC(),
+            Name::Psw => u16::from_le_bytes([state.Flags, state.A]),
+            Name::Sign => return flag(0b1000_0000),
+            Name::Zero => return flag(0b0100_0000),
+            Name::AuxCarry => return flag(0b0001_0000),
+            Name::Parity => return flag(0b0000_0100),
+            Name::Carry => return flag(0b0000_0001),
+            Name::Value => self.access.map_or(0, |(_, value)| value as u16),
+            Name::Adress => self.access.map_or(0, |(adress, _)| adress),
+        }) as i64
+    }
+
+    fn eval(&self, node: &Node) -> i64 {
+        match node {
+            Node::Number(n) => *n,
+            Node::Name(name) => self.name(*name),
+            Node::Byte(adress) => self.memory.read(self.eval(adress) as u16) as i64,
+            Node::Word(adress) => {
+                let adress = self.eval(adress) as u16;
+                u16::from_le_bytes([
+                    self.memory.read(adress),
+                    self.memory.read(adress.wrapping_add(1)),
+                ]) as i64
+            }
+            Node::Unary(op, a) => {
+                let a = self.eval(a);
+                match op {
+                    UnaryOp::Not => (a == 0) as i64,
+                    UnaryOp::Complement => !a,
+                    UnaryOp::Negate => a.wrapping_neg(),
+                }
+            }
+            Node::Binary(op, a, b) => op.apply(self.eval(a), self.eval(b)),
+        }
+    }
+}
+
+/// A parsed expression. It displays as the text it was parsed from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    source: String,
+    node: Node,
+}
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            source,
+            position: 0,
+        };
+        let node = parser.expression(0)?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.peek() {
+            return Err(ParseError::Unexpected(parser.position, c));
+        }
+        Ok(Self {
+            source: source.trim().to_string(),
+            node,
+        })
+    }
+
+    pub fn eval<M: Memory>(&self, context: &Context<M>) -> i64 {
+        context.eval(&self.node)
+    }
+
+    pub fn is_true<M: Memory>(&self, context: &Context<M>) -> bool {
+        self.eval(context) != 0
+    }
+}
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Expression(Expression),
+}
+
+/// A message with expressions between braces, formatted in hexadecimal, like
+/// `"score: {w[20f8]}, A = {A}"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    source: String,
+    segments: Vec<Segment>,
+}
+impl Message {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let offset = source.len() - rest.len();
+            let end = rest[start..].find('}').ok_or(ParseError::UnexpectedEnd)?;
+            let expression =
+                Expression::parse(&rest[start + 1..start + end]).map_err(|err| match err {
+                    ParseError::Unexpected(position, c) => {
+                        ParseError::Unexpected(offset + start + 1 + position, c)
+                    }
+                    err => err,
+                })?;
+            segments.push(Segment::Expression(expression));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    pub fn format<M: Memory>(&self, context: &Context<M>) -> String {
+        let mut message = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => message.push_str(text),
+                Segment::Expression(expression) => {
+                    message.push_str(&format!("{:02x}", expression.eval(context)))
+                }
+            }
+        }
+        message
+    }
+}
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.source[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    /// Consume 'token', if it is the next one.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(c) => Err(ParseError::Unexpected(self.position, c)),
+            None => Err(ParseError::UnexpectedEnd),
+        }
+    }
+
+    /// Consume a binary operator of the given precedence level.
+    fn binary_op(&mut self, level: usize) -> Option<BinaryOp> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let &(token, op) = BinaryOp::LEVELS[level]
+            .iter()
+            .find(|(token, _)| rest.starts_with(token))?;
+        // don't take the '|' of a '||', or the '<' of a '<<'
+        let longer = ["||", "&&", "<<", ">>", "<=", ">="]
+            .iter()
+            .any(|other| other.len() > token.len() && rest.starts_with(other));
+        if longer {
+            return None;
+        }
+        self.position += token.len();
+        Some(op)
+    }
+
+    fn expression(&mut self, level: usize) -> Result<Node, ParseError> {
+        if level == BinaryOp::LEVELS.len() {
+            return self.unary();
+        }
+        let mut node = self.expression(level + 1)?;
+        while let Some(op) = self.binary_op(level) {
+            let rhs = self.expression(level + 1)?;
+            node = Node::Binary(op, Box::new(node), Box::new(rhs));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        let op = if self.eat("!") {
+            UnaryOp::Not
+        } else if self.eat("~") {
+            UnaryOp::Complement
+        } else if self.eat("-") {
+            UnaryOp::Negate
+        } else {
+            return self.primary();
+        };
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, ParseError> {
+        self.skip_whitespace();
+        let start = self.position;
+        let Some(c) = self.peek() else {
+            return Err(ParseError::UnexpectedEnd);
+        };
+        if c == '(' {
+            self.position += 1;
+            let node = self.expression(0)?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        if c == '[' {
+            self.position += 1;
+            let node = self.expression(0)?;
+            self.expect("]")?;
+            return Ok(Node::Byte(Box::new(node)));
+        }
+        if c == '#' || c.is_ascii_alphanumeric() || c == '_' {
+            self.position += 1;
+            let len = self
+                .rest()
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(self.rest().len());
+            self.position += len;
+            let word = &self.source[start..self.position];
+            if let Some(decimal) = word.strip_prefix('#') {
+                return decimal
+                    .parse()
+                    .map(Node::Number)
+                    .map_err(|_| ParseError::InvalidNumber(word.to_string()));
+            }
+            if c.is_ascii_digit() {
+                let digits = word
+                    .strip_prefix("0x")
+                    .or_else(|| word.strip_prefix("0X"))
+                    .unwrap_or(word);
+                return i64::from_str_radix(digits, 16)
+                    .map(Node::Number)
+                    .map_err(|_| ParseError::InvalidNumber(word.to_string()));
+            }
+            if word.eq_ignore_ascii_case("w") && self.eat("[") {
+                let node = self.expression(0)?;
+                self.expect("]")?;
+                return Ok(Node::Word(Box::new(node)));
+            }
+            return Name::parse(word)
+                .map(Node::Name)
+                .ok_or_else(|| ParseError::UnknownName(word.to_string()));
+        }
+        Err(ParseError::Unexpected(start, c))
+    }
+}
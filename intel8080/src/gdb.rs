@@ -0,0 +1,470 @@
+//! A stub of the GDB remote serial protocol, over TCP, so RSP clients can attach to the
+//! interpreter. It is a frontend of the `Debugger`: it never blocks, and `GdbStub::poll`
+//! is called between the runs of the interpreter.
+//!
+//! The registers are sent as 16 bits little endian values, in the order AF, BC, DE, HL,
+//! SP and PC. No target description is sent: gdb has no 8080 architecture for one to
+//! name, so the client must know this layout of the `g` packet. The watchpoints of the
+//! client are memory watchpoints of the debugger, and a stop on one is reported with its
+//! adress. A stop for a event of the machine, like a reset by its watchdog, is written to
+//! the console of the client before the stop reply.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    IODevices, Memory,
+    debugger::{Access, Command, Register, Response, WatchTarget, Watchpoint},
+    interpreter::{Interpreter, StopReason},
+};
+
+const REGISTERS: usize = 6;
+
+/// The signals of the stop replies.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+struct Client {
+    stream: TcpStream,
+    /// The bytes received, not parsed yet.
+    input: Vec<u8>,
+    /// The cpu was resumed by the client, that waits for a stop reply.
+    running: bool,
+    /// The client sent a interrupt (Ctrl-C).
+    interrupted: bool,
+}
+impl Client {
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut data = data;
+        while !data.is_empty() {
+            match self.stream.write(data) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => data = &data[n..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn send_packet(&mut self, body: &str) -> io::Result<()> {
+        let checksum = body.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.send(format!("${}#{:02x}", body, checksum).as_bytes())
+    }
+
+    /// Read what is available in the socket. Return false if the client disconnected.
+    fn receive(&mut self) -> io::Result<bool> {
+        let mut buffer = [0; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.input.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Take the next complete packet of the input. Acknowledgments are skipped, and a
+    /// interrupt is returned as the packet "\x03".
+    fn next_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let Some(&first) = self.input.first() else {
+                return Ok(None);
+            };
+            match first {
+                0x03 => {
+                    self.input.remove(0);
+                    return Ok(Some("\x03".to_string()));
+                }
+                b'$' => {}
+                _ => {
+                    self.input.remove(0);
+                    continue;
+                }
+            }
+            let Some(end) = self.input.iter().position(|&b| b == b'#') else {
+                return Ok(None);
+            };
+            if self.input.len() < end + 3 {
+                return Ok(None);
+            }
+            let body = self.input[1..end].to_vec();
+            let checksum = std::str::from_utf8(&self.input[end + 1..end + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            self.input.drain(..end + 3);
+            if checksum != Some(body.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))) {
+                self.send(b"-")?;
+                continue;
+            }
+            self.send(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+        }
+    }
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+impl GdbStub {
+    /// Listen for a client at 'adress', like `127.0.0.1:1234`.
+    pub fn bind<A: ToSocketAddrs>(adress: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(adress)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Accept a client, handle the packets received and send the stop reply when the cpu
+    /// stops. The cpu is paused when a client attaches, and resumed when it detaches.
+    pub fn poll<M: Memory, I: IODevices>(&mut self, interpreter: &mut Interpreter<M, I>) {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, adress)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        println!("gdb: failed to configure the connection: {}", e);
+                        return;
+                    }
+                    println!("gdb: {} attached", adress);
+                    interpreter.debug_command(Command::Pause);
+                    self.client = Some(Client {
+                        stream,
+                        input: Vec::new(),
+                        running: false,
+                        interrupted: false,
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    println!("gdb: failed to accept a connection: {}", e);
+                    return;
+                }
+            }
+        }
+
+        if let Err(e) = self.handle(interpreter) {
+            println!("gdb: connection lost: {}", e);
+            self.detach(interpreter);
+        }
+    }
+
+    fn detach<M: Memory, I: IODevices>(&mut self, interpreter: &mut Interpreter<M, I>) {
+        self.client = None;
+        interpreter.debug_command(Command::Continue);
+    }
+
+    fn handle<M: Memory, I: IODevices>(
+        &mut self,
+        interpreter: &mut Interpreter<M, I>,
+    ) -> io::Result<()> {
+        let Some(client) = &mut self.client else {
+            return Ok(());
+        };
+        if !client.receive()? {
+            println!("gdb: detached");
+            self.detach(interpreter);
+            return Ok(());
+        }
+        while let Some(packet) = client.next_packet()? {
+            match packet.as_str() {
+                "\x03" => {
+                    client.interrupted = true;
+                    interpreter.debug_command(Command::Pause);
+                }
+                "D" | "k" => {
+                    if packet == "D" {
+                        client.send_packet("OK")?;
+                    }
+                    println!("gdb: detached");
+                    self.detach(interpreter);
+                    return Ok(());
+                }
+                _ => {
+                    if let Some(reply) = handle_packet(client, &packet, interpreter) {
+                        client.send_packet(&reply)?;
+                    }
+                }
+            }
+        }
+        if client.running && interpreter.is_stopped() {
+            client.running = false;
+            if interpreter.debugger().stop_reason() == Some(StopReason::Watchdog) {
+                // shown in the console of the client, before the stop
+                client.send_packet(&format!("O{}", hex_bytes(b"reset by the watchdog\n")))?;
+            }
+            let reply = stop_reply(interpreter, client.interrupted);
+            client.interrupted = false;
+            client.send_packet(&reply)?;
+        }
+        Ok(())
+    }
+}
+
+/// The stop reply of the stopped cpu: the watchpoint that stopped it, or the signal.
+fn stop_reply<M: Memory, I: IODevices>(
+    interpreter: &Interpreter<M, I>,
+    interrupted: bool,
+) -> String {
+    match interpreter.debugger().stop_reason() {
+        Some(StopReason::Paused) if interrupted => format!("S{:02x}", SIGINT),
+        Some(StopReason::MemoryWatch { adress, write, .. }) => {
+            let kind = watch_kind(interpreter.debugger().watchpoints(), adress, write);
+            format!("T{:02x}{}:{:04x};", SIGTRAP, kind, adress)
+        }
+        _ => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+/// The stop reason of the gdb watchpoint triggered by a access: `watch` and `rwatch` for
+/// the watchpoints of the writes or the reads, and `awatch` for the ones of both.
+fn watch_kind(watchpoints: &[Watchpoint], adress: u16, write: bool) -> &'static str {
+    let watched = |access| {
+        watchpoints
+            .iter()
+            .any(|watchpoint| match &watchpoint.target {
+                WatchTarget::Memory(range) => {
+                    watchpoint.access == access && range.contains(&adress)
+                }
+                WatchTarget::Port(_) => false,
+            })
+    };
+    match write {
+        true if watched(Access::Write) => "watch",
+        false if watched(Access::Read) => "rwatch",
+        _ if watched(Access::ReadWrite) => "awatch",
+        true => "watch",
+        false => "rwatch",
+    }
+}
+
+/// The reply of a packet, or None if it is only sent when the cpu stops.
+fn handle_packet<M: Memory, I: IODevices>(
+    client: &mut Client,
+    packet: &str,
+    interpreter: &mut Interpreter<M, I>,
+) -> Option<String> {
+    let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+    let reply = match command {
+        "?" => format!("S{:02x}", SIGTRAP),
+        "g" => {
+            let mut reply = String::new();
+            for i in 0..REGISTERS {
+                let value = read_register(interpreter, i);
+                write!(reply, "{:02x}{:02x}", value as u8, (value >> 8) as u8).unwrap();
+            }
+            reply
+        }
+        "G" => {
+            let values: Vec<u16> = (0..REGISTERS)
+                .map_while(|i| parse_le_u16(args.get(i * 4..i * 4 + 4)?))
+                .collect();
+            if values.len() != REGISTERS {
+                return Some("E01".to_string());
+            }
+            for (i, value) in values.into_iter().enumerate() {
+                write_register(interpreter, i, value);
+            }
+            "OK".to_string()
+        }
+        "p" => match usize::from_str_radix(args, 16) {
+            Ok(i) if i < REGISTERS => {
+                let value = read_register(interpreter, i);
+                format!("{:02x}{:02x}", value as u8, (value >> 8) as u8)
+            }
+            _ => "E01".to_string(),
+        },
+        "P" => {
+            let register = args
+                .split_once('=')
+                .and_then(|(i, value)| Some((usize::from_str_radix(i, 16).ok()?, value)));
+            match register {
+                Some((i, value)) if i < REGISTERS => match parse_le_u16(value) {
+                    Some(value) => {
+                        write_register(interpreter, i, value);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                },
+                _ => "E01".to_string(),
+            }
+        }
+        "m" => match parse_range(args) {
+            Some((adress, len)) => {
+                match interpreter.debug_command(Command::ReadMemory { adress, len }) {
+                    Response::Memory(data) => hex_bytes(&data),
+                    _ => "E01".to_string(),
+                }
+            }
+            None => "E01".to_string(),
+        },
+        "M" => {
+            let write = args.split_once(':').and_then(|(range, data)| {
+                let (adress, len) = parse_range(range)?;
+                let data = parse_hex_bytes(data)?;
+                (data.len() == len as usize).then_some((adress, data))
+            });
+            match write {
+                Some((adress, data)) => {
+                    interpreter.debug_command(Command::WriteMemory { adress, data });
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            }
+        }
+        "Z" | "z" => {
+            let mut fields = args.split(',');
+            let kind = fields.next();
+            let adress = fields.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+            let len = fields.next().and_then(|l| u16::from_str_radix(l, 16).ok());
+            let access = match kind {
+                Some("2") => Some(Access::Write),
+                Some("3") => Some(Access::Read),
+                Some("4") => Some(Access::ReadWrite),
+                _ => None,
+            };
+            let add = command == "Z";
+            match (kind, adress, len, access) {
+                // software and hardware breakpoints are the same
+                (Some("0" | "1"), Some(adress), _, _) => {
+                    interpreter.debug_command(if add {
+                        Command::AddBreakpoint(adress)
+                    } else {
+                        Command::RemoveBreakpoint(adress)
+                    });
+                    "OK".to_string()
+                }
+                (_, Some(adress), Some(len @ 1..), Some(access)) => {
+                    let end = adress.saturating_add(len - 1);
+                    let watchpoint = Watchpoint::memory(adress..=end, access);
+                    interpreter.debug_command(if add {
+                        Command::AddWatchpoint(watchpoint)
+                    } else {
+                        Command::RemoveWatchpoint(watchpoint)
+                    });
+                    "OK".to_string()
+                }
+                (Some("0" | "1" | "2" | "3" | "4"), _, _, _) => "E01".to_string(),
+                _ => String::new(),
+            }
+        }
+        "s" | "c" => {
+            if let Ok(adress) = u16::from_str_radix(args, 16) {
+                interpreter.debug_command(Command::WriteRegister(Register::PC, adress));
+            }
+            if command == "s" {
+                interpreter.debug_command(Command::Step);
+                stop_reply(interpreter, false)
+            } else {
+                interpreter.debug_command(Command::Continue);
+                client.running = true;
+                return None;
+            }
+        }
+        "H" => "OK".to_string(),
+        "q" => {
+            if args.starts_with("Supported") {
+                "PacketSize=1000".to_string()
+            } else if args == "Attached" {
+                "1".to_string()
+            } else if args == "C" {
+                "QC1".to_string()
+            } else if args == "fThreadInfo" {
+                "m1".to_string()
+            } else if args == "sThreadInfo" {
+                "l".to_string()
+            } else {
+                String::new()
+            }
+        }
+        // unsupported packets are replied with a empty packet
+        _ => String::new(),
+    };
+    Some(reply)
+}
+
+fn read_register<M: Memory, I: IODevices>(interpreter: &Interpreter<M, I>, i: usize) -> u16 {
+    let state = interpreter.state();
+    match i {
+        0 => state.get_PSW(),
+        1 => state.get_BC(),
+        2 => state.get_DE(),
+        3 => state.get_HL(),
+        4 => state.get_SP(),
+        _ => state.get_PC(),
+    }
+}
+
+fn write_register<M: Memory, I: IODevices>(
+    interpreter: &mut Interpreter<M, I>,
+    i: usize,
+    value: u16,
+) {
+    let pairs = [
+        (Register::A, Register::Flags),
+        (Register::B, Register::C),
+        (Register::D, Register::E),
+        (Register::H, Register::L),
+    ];
+    match i {
+        0..=3 => {
+            let (high, low) = pairs[i];
+            interpreter.debug_command(Command::WriteRegister(high, value >> 8));
+            interpreter.debug_command(Command::WriteRegister(low, value & 0xff));
+        }
+        4 => {
+            interpreter.debug_command(Command::WriteRegister(Register::SP, value));
+        }
+        _ => {
+            interpreter.debug_command(Command::WriteRegister(Register::PC, value));
+        }
+    }
+}
+
+fn hex_bytes(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut s, b| {
+        write!(s, "{:02x}", b).unwrap();
+        s
+    })
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A 16 bits value, sent as little endian bytes.
+fn parse_le_u16(hex: &str) -> Option<u16> {
+    match parse_hex_bytes(hex)?[..] {
+        [low, high] => Some(u16::from_le_bytes([low, high])),
+        _ => None,
+    }
+}
+
+/// The 'adress,len' of the memory packets.
+fn parse_range(range: &str) -> Option<(u16, u16)> {
+    let (adress, len) = range.split_once(',')?;
+    Some((
+        u16::from_str_radix(adress, 16).ok()?,
+        u16::from_str_radix(len, 16).ok()?,
+    ))
+}
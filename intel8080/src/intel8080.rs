@@ -1,6 +1,32 @@
+use crate::save_state::{self, SaveStateError};
+
 pub trait IODevices: Send {
     fn read(&mut self, device: u8) -> u8;
     fn write(&mut self, device: u8, value: u8);
+
+    /// Called before each IN and OUT instruction with the current clock count, for
+    /// devices that timestamp their accesses.
+    fn set_clock_count(&mut self, _clock: u64) {}
+
+    /// Print the state of the devices, for the `devices` command of the debugger.
+    fn print_state(&self, _w: &mut dyn std::fmt::Write) {}
+
+    /// Write the devices state to the "IO" section of a save state.
+    fn save_state(&self, _out: &mut Vec<u8>) {}
+
+    /// Restore the devices state from the "IO" section of a save state.
+    fn load_state(&mut self, _section: &[u8]) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
+
+/// A machine without I/O devices: the inputs read 0, and the outputs are ignored.
+pub struct NoDevices;
+impl IODevices for NoDevices {
+    fn read(&mut self, _device: u8) -> u8 {
+        0
+    }
+    fn write(&mut self, _device: u8, _value: u8) {}
 }
 
 pub trait Memory: Send {
@@ -9,6 +35,14 @@ pub trait Memory: Send {
 
     fn get_rom(&mut self) -> Vec<u8>;
 
+    /// Write the writable memory to the "MEM" section of a save state.
+    fn save_state(&self, _out: &mut Vec<u8>) {}
+
+    /// Restore the writable memory from the "MEM" section of a save state.
+    fn load_state(&mut self, _section: &[u8]) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+
     #[inline]
     fn read_u16(&self, adress: u16) -> u16 {
         u16::from_le_bytes([self.read(adress), self.read(adress + 1)])
@@ -16,6 +50,7 @@ pub trait Memory: Send {
 }
 
 #[repr(C)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[allow(non_snake_case)]
 pub struct I8080State {
     pub A: u8,
@@ -174,6 +209,33 @@ impl I8080State {
         (self.Flags & 0b0000_0100) != 0
     }
 
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[
+            self.A, self.Flags, self.B, self.C, self.D, self.E, self.H, self.L,
+        ]);
+        save_state::write_u16(out, self.get_SP());
+        save_state::write_u16(out, self.get_PC());
+        save_state::write_bool(out, self.interrupt_enabled);
+        save_state::write_bool(out, self.halt);
+    }
+
+    pub fn load_state(&mut self, input: &mut &[u8]) -> Result<(), SaveStateError> {
+        let regs = save_state::read_bytes(input, 8)?;
+        self.A = regs[0];
+        self.Flags = regs[1];
+        self.B = regs[2];
+        self.C = regs[3];
+        self.D = regs[4];
+        self.E = regs[5];
+        self.H = regs[6];
+        self.L = regs[7];
+        self.set_SP(save_state::read_u16(input)?);
+        self.set_PC(save_state::read_u16(input)?);
+        self.interrupt_enabled = save_state::read_bool(input)?;
+        self.halt = save_state::read_bool(input)?;
+        Ok(())
+    }
+
     pub fn print_state<W: std::fmt::Write>(&self, w: &mut W) {
         writeln!(w, "B  C  D  E  H  L  A  SZ_A_P_C <- Flags").unwrap();
         writeln!(
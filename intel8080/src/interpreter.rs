@@ -1,12 +1,54 @@
 use crate::intel8080::{I8080State, IODevices, Memory};
+use crate::save_state::{self, SaveStateError};
+
+use std::{fmt, fs, io, path::Path};
 
 #[cfg(feature = "debug")]
-use {
-    crate::dissasembler, crate::dissasembler::dissasembly_around,
-    crate::write_adapter::WriteAdapter, std::collections::HashSet, std::fmt::Write, std::io,
-    std::ops::Range,
+use crate::{
+    debugger::{self, Command, Debugger, Register, Response},
+    dissasembler,
 };
 
+/// Read a byte of data, not a opcode or a immediate, that the watchpoints can see.
+macro_rules! read_data {
+    ($self:ident, $adress:expr) => {{
+        let adress: u16 = $adress;
+        let value = $self.memory.read(adress);
+        #[cfg(feature = "debug")]
+        $self.debugger.on_memory_access(adress, value, false);
+        value
+    }};
+}
+
+/// Write a byte of data, that the watchpoints can see.
+macro_rules! write_data {
+    ($self:ident, $adress:expr, $value:expr) => {{
+        let adress: u16 = $adress;
+        let value: u8 = $value;
+        $self.memory.write(adress, value);
+        #[cfg(feature = "debug")]
+        $self.debugger.on_memory_access(adress, value, true);
+    }};
+}
+
+macro_rules! push_stack {
+    ($self:ident, $value:expr) => {{
+        let value: u16 = $value;
+        let sp = $self.state.get_SP();
+        write_data!($self, sp.wrapping_sub(2), value as u8);
+        write_data!($self, sp.wrapping_sub(1), (value >> 8) as u8);
+        $self.state.set_SP(sp.wrapping_sub(2));
+    }};
+}
+
+macro_rules! pop_stack {
+    ($self:ident) => {{
+        let sp = $self.state.get_SP();
+        $self.state.set_SP(sp.wrapping_add(2));
+        read_data!($self, sp) as u16 | (read_data!($self, sp.wrapping_add(1)) as u16) << 8
+    }};
+}
+
 macro_rules! as_expr {
     ($x:expr) => {
         $x
@@ -225,46 +267,212 @@ macro_rules! ops {
 
 // const TARGET_FREQ: u64 = 2_000_000; //Hz
 
+/// Why a run of the interpreter returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The clock of the run was reached.
+    ClockReached,
+    /// The PC reached a breakpoint, at the given adress.
+    Breakpoint(u16),
+    /// A step, step over or step out was finished.
+    Step,
+    /// The debugger was paused, by `Command::Pause` or `enter_debug_mode`.
+    Paused,
+    /// A watchpoint was triggered by a data access of the last instruction.
+    MemoryWatch { adress: u16, value: u8, write: bool },
+    /// A watchpoint was triggered by the IN or OUT of the last instruction.
+    PortWatch { port: u8, value: u8, write: bool },
+    /// The watchdog of the machine reset the cpu, see `Interpreter::stop_debugger`.
+    Watchdog,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            StopReason::ClockReached => write!(f, "clock reached"),
+            StopReason::Breakpoint(adress) => write!(f, "breakpoint at {:04x}", adress),
+            StopReason::Step => write!(f, "step"),
+            StopReason::Paused => write!(f, "paused"),
+            StopReason::MemoryWatch {
+                adress,
+                value,
+                write: true,
+            } => write!(f, "write of {:02x} to {:04x}", value, adress),
+            StopReason::MemoryWatch { adress, value, .. } => {
+                write!(f, "read of {:02x} from {:04x}", value, adress)
+            }
+            StopReason::PortWatch {
+                port,
+                value,
+                write: true,
+            } => write!(f, "OUT {:02x}, value {:02x}", port, value),
+            StopReason::PortWatch { port, value, .. } => {
+                write!(f, "IN {:02x}, value {:02x}", port, value)
+            }
+            StopReason::Watchdog => write!(f, "reset by the watchdog"),
+        }
+    }
+}
+
 pub struct Interpreter<M: Memory, I: IODevices> {
     state: I8080State,
+    /// The adress where the cpu starts, after a reset.
+    entry: u16,
     pub devices: I,
     pub memory: M,
-    clock_count: u32,
-    target_clock: u32,
+    clock_count: u64,
+    target_clock: u64,
+    /// Opcode latched by the interrupt line, waiting for interrupts to be enabled.
+    pending_interrupt: Option<u8>,
+    /// Set by EI, interrupts are only accepted after the instruction that follows it.
+    ei_delay: bool,
     #[cfg(feature = "debug")]
-    debug: bool,
-    #[cfg(feature = "debug")]
-    breakpoints: HashSet<u16>,
-    #[cfg(feature = "debug")]
-    traced: Vec<Range<u16>>,
+    debugger: Debugger,
 }
 impl<M: Memory, I: IODevices> Interpreter<M, I> {
     pub fn new(devices: I, memory: M, entries: &[u16]) -> Self {
         let mut state = I8080State::new();
 
         state.set_PC(entries[0]);
+        let entry = entries[0];
 
         #[cfg(feature = "debug")]
         let mut memory = memory;
 
         Self {
             #[cfg(feature = "debug")]
-            debug: false,
-            #[cfg(feature = "debug")]
-            breakpoints: HashSet::new(),
-            #[cfg(feature = "debug")]
-            traced: dissasembler::trace(&memory.get_rom(), entries),
+            debugger: Debugger::new(dissasembler::trace(&memory.get_rom(), entries)),
             state,
+            entry,
             devices,
             memory,
             clock_count: 0,
             target_clock: 0,
+            pending_interrupt: None,
+            ei_delay: false,
         }
     }
 
+    /// Reset the cpu and the clock, keeping the memory, the devices and the debugger.
+    pub fn reset(&mut self) {
+        self.state = I8080State::new();
+        self.state.set_PC(self.entry);
+        self.clock_count = 0;
+        self.target_clock = 0;
+        self.pending_interrupt = None;
+        self.ei_delay = false;
+    }
+
+    pub fn state(&self) -> &I8080State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut I8080State {
+        &mut self.state
+    }
+
+    /// Stop the cpu at the next instruction, until the debugger resumes it.
     #[cfg(feature = "debug")]
     pub fn enter_debug_mode(&mut self) {
-        self.debug = true;
+        self.debugger.pause();
+    }
+
+    /// Stop the cpu at the next instruction, for a event of the machine around it, like a
+    /// reset by its watchdog.
+    #[cfg(feature = "debug")]
+    pub fn stop_debugger(&mut self, reason: StopReason) {
+        self.debugger.stop(reason);
+    }
+
+    /// If the last run was stopped by the debugger, before reaching its clock.
+    pub fn is_stopped(&self) -> bool {
+        #[cfg(feature = "debug")]
+        return self.debugger.is_stopped();
+        #[cfg(not(feature = "debug"))]
+        false
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Execute a command of a debugger frontend. The commands that resume the cpu only
+    /// take effect in the next run.
+    #[cfg(feature = "debug")]
+    pub fn debug_command(&mut self, command: Command) -> Response {
+        match command {
+            Command::Step => {
+                self.debugger.resume();
+                self.step();
+                self.debugger.check_watchpoints(&self.state, &self.memory);
+                if !self.debugger.is_stopped() {
+                    self.debugger.stop(StopReason::Step);
+                }
+                return Response::Stopped(self.debugger.stop_reason().unwrap());
+            }
+            Command::StepOver => {
+                let pc = self.state.get_PC();
+                match debugger::call_size(self.memory.read(pc)) {
+                    Some(size) => {
+                        let sp = self.state.get_SP();
+                        self.debugger.step_over(pc.wrapping_add(size), sp);
+                        return Response::Resumed;
+                    }
+                    None => return self.debug_command(Command::Step),
+                }
+            }
+            Command::StepOut => {
+                self.debugger.step_out(self.state.get_SP());
+                return Response::Resumed;
+            }
+            Command::Continue => {
+                self.debugger.resume();
+                return Response::Resumed;
+            }
+            Command::Pause => self.debugger.pause(),
+            Command::AddBreakpoint(adress) => self.debugger.add_breakpoint(adress),
+            Command::SetBreakpoint(adress, breakpoint) => {
+                self.debugger.set_breakpoint(adress, breakpoint)
+            }
+            Command::RemoveBreakpoint(adress) => self.debugger.remove_breakpoint(adress),
+            Command::AddWatchpoint(watchpoint) => self.debugger.add_watchpoint(watchpoint),
+            Command::RemoveWatchpoint(watchpoint) => self.debugger.remove_watchpoint(&watchpoint),
+            Command::Interrupt(opcode) => self.interrupt(opcode),
+            Command::ReadRegisters => return Response::Registers(self.state.clone()),
+            Command::WriteRegister(register, value) => {
+                let state = &mut self.state;
+                match register {
+                    Register::A => state.A = value as u8,
+                    Register::Flags => state.Flags = value as u8,
+                    Register::B => state.B = value as u8,
+                    Register::C => state.C = value as u8,
+                    Register::D => state.D = value as u8,
+                    Register::E => state.E = value as u8,
+                    Register::H => state.H = value as u8,
+                    Register::L => state.L = value as u8,
+                    Register::SP => state.set_SP(value),
+                    Register::PC => state.set_PC(value),
+                }
+            }
+            Command::ReadMemory { adress, len } => {
+                let data = (0..len)
+                    .map(|i| self.memory.read(adress.wrapping_add(i)))
+                    .collect();
+                return Response::Memory(data);
+            }
+            Command::WriteMemory { adress, data } => {
+                for (i, value) in data.into_iter().enumerate() {
+                    self.memory.write(adress.wrapping_add(i as u16), value);
+                }
+            }
+        }
+        Response::Done
     }
 
     fn get_opcode_size_and_clock(opcode: u8) -> (u8, u8) {
@@ -529,115 +737,47 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
         SIZE_AND_CLOCKS[opcode as usize]
     }
 
+    /// Number of clocks elapsed since the interpreter was created.
+    pub fn clock_count(&self) -> u64 {
+        self.clock_count
+    }
+
+    /// The clock where the last run stopped, not including the overshoot of the last
+    /// instruction, that is discounted from the next run.
+    pub fn target_clock(&self) -> u64 {
+        self.target_clock
+    }
+
     /// run for 'number_of_clocks' clocks
-    pub fn run(&mut self, number_of_clocks: u32) {
-        if self.state.halt {
-            return;
-        }
+    pub fn run(&mut self, number_of_clocks: u32) -> StopReason {
+        self.run_until(self.target_clock + number_of_clocks as u64)
+    }
 
-        self.target_clock += number_of_clocks;
+    /// run until the clock count reaches 'clock', or the debugger stops the cpu
+    pub fn run_until(&mut self, clock: u64) -> StopReason {
+        self.target_clock = clock;
         while self.clock_count < self.target_clock {
             #[cfg(feature = "debug")]
-            {
-                if self.debug {
-                    let stdout = std::io::stdout();
-                    let mut w = WriteAdapter(io::BufWriter::new(stdout.lock()));
-                    writeln!(w).unwrap();
-                    dissasembly_around(
-                        &mut w,
-                        &self.traced,
-                        &self.memory.get_rom(),
-                        self.state.get_PC(),
-                    )
-                    .unwrap();
-                    writeln!(w).unwrap();
-                    self.state.print_state(&mut w);
-                    drop(w);
-                    loop {
-                        let mut input = String::new();
-                        io::stdin().read_line(&mut input).unwrap();
-                        let mut input = input
-                            .trim()
-                            .split_ascii_whitespace()
-                            .filter(|s| !s.trim().is_empty());
-                        if let Some(command) = input.next() {
-                            if command.starts_with("runto") {
-                                if let Some(adress) = input.next() {
-                                    if let Ok(adress) = u16::from_str_radix(adress, 16) {
-                                        let mut safety = 0;
-                                        while self.state.get_PC() != adress {
-                                            safety += 1;
-                                            if safety > 100_000 {
-                                                println!(
-                                                    "safety: after 100_000 steps, it don't reach the adress {:04x} yet",
-                                                    adress
-                                                );
-                                                break;
-                                            }
-                                            let opcode = self.memory.read(self.state.get_PC());
-                                            let (offset, clock) =
-                                                Self::get_opcode_size_and_clock(opcode);
-                                            self.clock_count += clock as u32;
-                                            self.state.set_PC(self.state.get_PC() + offset as u16);
-                                            self.interpret_opcode(opcode);
-                                        }
-                                    } else {
-                                        println!("error: invalid adress");
-                                    }
-                                } else {
-                                    println!(
-                                        "use 'runto <ADRESS>', where <ADRESS> is the hexadecimal adress of the opcode it will stop when reached."
-                                    );
-                                }
-                            } else if command.starts_with("interrupt") {
-                                if let Some(opcode) = input.next() {
-                                    if let Ok(opcode) = u8::from_str_radix(opcode, 16) {
-                                        self.interrupt(opcode);
-                                        break;
-                                    } else {
-                                        println!("error: invalid opcode");
-                                    }
-                                } else {
-                                    println!(
-                                        "use 'interrupt <OPCODE>', where <OPCODE> is the hexadecimal that opcode will be run."
-                                    );
-                                }
-                            } else if command.starts_with("run") {
-                                self.debug = false;
-                                break;
-                            } else if command.starts_with("bp") {
-                                if let Some(adress) = input.next() {
-                                    if let Ok(adress) = u16::from_str_radix(adress, 16) {
-                                        self.breakpoints.insert(adress);
-                                    } else {
-                                        println!("error: invalid adress");
-                                    }
-                                } else {
-                                    println!(
-                                        "use 'bp <ADRESS>', where <ADRESS> is the hexadecimal adress of the opcode it will break when reached."
-                                    );
-                                }
-                            }
-                        } else {
-                            break; // do one step
-                        }
-                    }
-                }
+            if let Some(reason) = self.debugger.stop_reason() {
+                // the run continues from here when resumed
+                self.target_clock = self.clock_count;
+                return reason;
             }
 
-            let opcode = self.memory.read(self.state.get_PC());
-            let (offset, clock) = Self::get_opcode_size_and_clock(opcode);
-            self.state.set_PC(self.state.get_PC() + offset as u16);
-            self.clock_count += clock as u32;
-            self.interpret_opcode(opcode);
+            if !self.step() {
+                // halted, nothing happens until the next interrupt
+                self.clock_count = self.target_clock;
+                break;
+            }
 
             #[cfg(feature = "debug")]
-            {
-                if self.breakpoints.contains(&self.state.get_PC()) {
-                    self.enter_debug_mode();
-                }
-            }
+            self.debugger.after_step(&self.state, &self.memory);
         }
+        #[cfg(feature = "debug")]
+        if let Some(reason) = self.debugger.stop_reason() {
+            return reason;
+        }
+        StopReason::ClockReached
     }
 
     /// block the current thread, running the interpreter forever.
@@ -645,19 +785,114 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
     #[inline]
     pub fn run_forever(&mut self) -> ! {
         loop {
-            let opcode = self.memory.read(self.state.get_PC());
-            let (offset, clock) = Self::get_opcode_size_and_clock(opcode);
-            self.state.set_PC(self.state.get_PC() + offset as u16);
-            self.clock_count += clock as u32;
-            self.interpret_opcode(opcode);
+            self.step();
         }
     }
 
+    /// Request a interrupt, where 'opcode' is the instruction placed in the data bus
+    /// (normally a RST). The request is latched until the cpu accepts it, what only
+    /// happens when interrupts are enabled. A new request replaces a pending one.
     pub fn interrupt(&mut self, opcode: u8) {
-        self.state.halt = false;
-        let (_, clock) = Self::get_opcode_size_and_clock(opcode);
-        self.clock_count += clock as u32;
+        self.pending_interrupt = Some(opcode);
+    }
+
+    /// Execute a single instruction, or accept a pending interrupt.
+    /// Return false if the cpu is halted, and nothing was executed.
+    pub fn step(&mut self) -> bool {
+        if let Some(opcode) = self.pending_interrupt
+            && self.state.interrupt_enabled
+            && !self.ei_delay
+        {
+            self.pending_interrupt = None;
+            self.state.interrupt_enabled = false;
+            self.state.halt = false;
+            let (_, clock) = Self::get_opcode_size_and_clock(opcode);
+            self.clock_count += clock as u64;
+            self.interpret_opcode(opcode);
+            return true;
+        }
+        self.ei_delay = false;
+
+        if self.state.halt {
+            return false;
+        }
+
+        let opcode = self.memory.read(self.state.get_PC());
+        let (offset, clock) = Self::get_opcode_size_and_clock(opcode);
+        self.state.set_PC(self.state.get_PC() + offset as u16);
+        self.clock_count += clock as u64;
         self.interpret_opcode(opcode);
+        true
+    }
+
+    /// Serialize the entire machine state: the cpu, the clock, the memory and the devices.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        save_state::write_header(&mut out);
+        save_state::write_section(&mut out, save_state::CPU_TAG, |out| {
+            self.state.save_state(out);
+            save_state::write_u64(out, self.clock_count);
+            save_state::write_u64(out, self.target_clock);
+            save_state::write_bool(out, self.pending_interrupt.is_some());
+            save_state::write_u8(out, self.pending_interrupt.unwrap_or(0));
+            save_state::write_bool(out, self.ei_delay);
+        });
+        save_state::write_section(&mut out, save_state::MEMORY_TAG, |out| {
+            self.memory.save_state(out)
+        });
+        save_state::write_section(&mut out, save_state::DEVICES_TAG, |out| {
+            self.devices.save_state(out)
+        });
+        out
+    }
+
+    /// Restore a machine state previously created by `save_state`. On error, the machine
+    /// is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        // the devices may reject their section after the memory was loaded, so keep the
+        // current state to put it back
+        let backup = self.save_state();
+        self.load_sections(data).inspect_err(|_| {
+            self.load_sections(&backup)
+                .expect("the current state must load back");
+        })
+    }
+
+    fn load_sections(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut input = data;
+        save_state::read_header(&mut input)?;
+
+        // parse the cpu first, so a truncated file don't leave the cpu half loaded
+        let mut cpu = save_state::read_section(&mut input, save_state::CPU_TAG)?;
+        let mut state = I8080State::new();
+        state.load_state(&mut cpu)?;
+        let clock_count = save_state::read_u64(&mut cpu)?;
+        let target_clock = save_state::read_u64(&mut cpu)?;
+        let has_pending_interrupt = save_state::read_bool(&mut cpu)?;
+        let pending_interrupt = save_state::read_u8(&mut cpu)?;
+        let ei_delay = save_state::read_bool(&mut cpu)?;
+
+        let memory = save_state::read_section(&mut input, save_state::MEMORY_TAG)?;
+        let devices = save_state::read_section(&mut input, save_state::DEVICES_TAG)?;
+
+        self.memory.load_state(memory)?;
+        self.devices.load_state(devices)?;
+        self.state = state;
+        self.clock_count = clock_count;
+        self.target_clock = target_clock;
+        self.pending_interrupt = has_pending_interrupt.then_some(pending_interrupt);
+        self.ei_delay = ei_delay;
+
+        Ok(())
+    }
+
+    pub fn save_state_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.save_state())
+    }
+
+    pub fn load_state_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SaveStateError> {
+        let data = fs::read(path)?;
+        self.load_state(&data)
     }
 
     #[allow(non_snake_case)]
@@ -669,17 +904,14 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
             },
             r 0b01110000 => { // MOV  M, r  | Move register to memory              | 01110SSS        |  7
                 let m = self.state.get_HL();
-                self.memory.write(m, r);
+                write_data!(self, m, r);
             },
             r | 0b01000110 => { // MOV  r, M  | Move memory to register              | 01DDD110        |  7
                 let m = self.state.get_HL();
-                unsafe { *r = self.memory.read(m); }
+                unsafe { *r = read_data!(self, m); }
             },
             0b01110110 => { // HLT        | Halt                                 | 01110110        |  7
                 self.state.halt = true;
-                if opcode == 0b01110110 { // if HLT
-                    self.target_clock = self.clock_count;
-                }
             },
             r | 0b00000110 => { // MVI  r     | Move immediate to register              | 00DDD110        |  7
                 let immediate = self.memory.read(self.state.get_PC()-1);
@@ -688,7 +920,7 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
             0b00110110 => { // MVI  M     | Move immediate to memory                | 00110110        | 10
                 let immediate = self.memory.read(self.state.get_PC()-1);
                 let m = self.state.get_HL();
-                self.memory.write(m, immediate);
+                write_data!(self, m, immediate);
             },
             r | 0b00000100 => { // INR  r     | Increment register                   | 00DDD100        |  5
                 unsafe {
@@ -706,19 +938,19 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
             },
             0b00110100 => { // INR  M     | Increment memory                     | 00110100        | 10
                 let m = self.state.get_HL();
-                let value = self.memory.read(m);
+                let value = read_data!(self, m);
 
                 let (sum, _) = value.overflowing_add(1);
                 self.state.set_flags_ex(sum, value & 0xf == 0xf);
-                self.memory.write(m, sum);
+                write_data!(self, m, sum);
             },
             0b00110101 => { // DCR  M     | Decrement memory                     | 00110101        | 10
                 let m = self.state.get_HL();
-                let value = self.memory.read(m);
+                let value = read_data!(self, m);
 
                 let (sum, _) = value.overflowing_sub(1);
                 self.state.set_flags_ex(sum, value & 0xf == 0x0);
-                self.memory.write(m, sum);
+                write_data!(self, m, sum);
             },
             r 0b10000000 => { // ADD  r     | Add register to A                    | 10000SSS        |  4
                 let (sum, carry) = self.state.A.overflowing_add(r);
@@ -758,46 +990,46 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
                 self.state.set_flags(sum, carry, ((self.state.A & 0xf) + ((!r).wrapping_add(1) & 0xf)) > 0xf );
             },
             0b10000110 => { // ADD  M     | Add memory to A                      | 10000110        |  7
-                let value = self.memory.read(self.state.get_HL());
+                let value = read_data!(self, self.state.get_HL());
                 let (sum, carry) = self.state.A.overflowing_add(value);
                 self.state.set_flags(sum, carry, (self.state.A & 0xf) + (value & 0xf) > 0xf);
                 self.state.A = sum;
             },
             0b10001110 => { // ADC  M     | Add memory to A with carry           | 10001110        |  7
-                let value = self.memory.read(self.state.get_HL());
+                let value = read_data!(self, self.state.get_HL());
                 let (sum, carry) = self.state.A.overflowing_add(value.wrapping_add(self.state.on_carry() as u8));
                 self.state.set_flags(sum, carry, (self.state.A & 0xf) + (value & 0xf) + self.state.on_carry() as u8 > 0xf);
                 self.state.A = sum;
             },
             0b10010110 => { // SUB  M     | Subtract memory from A               | 10010110        |  7
-                let value = self.memory.read(self.state.get_HL());
+                let value = read_data!(self, self.state.get_HL());
                 let (sum, carry) = self.state.A.overflowing_sub(value);
                 self.state.set_flags(sum, carry, (self.state.A & 0xf) + ((!value).wrapping_add(1) & 0xf) > 0xf);
                 self.state.A = sum;
             },
             0b10011110 => { // SBB  M     | Subtract memory from A with borrow   | 10011110        |  7
-                let value = self.memory.read(self.state.get_HL()).wrapping_add(self.state.on_carry() as u8);
+                let value = read_data!(self, self.state.get_HL()).wrapping_add(self.state.on_carry() as u8);
                 let (sum, carry) = self.state.A.overflowing_sub(value);
                 self.state.set_flags(sum, carry, (self.state.A & 0xf) + ((!value).wrapping_add(1) & 0xf) > 0xf);
                 self.state.A = sum;
             },
             0b10100110 => { // ANA  M     | And memory with A                    | 10100110        |  7
-                let value = self.memory.read(self.state.get_HL());
+                let value = read_data!(self, self.state.get_HL());
                 self.state.A &= value;
                 self.state.set_flags(self.state.A, false, self.state.on_aux_carry());
             },
             0b10101110 => { // XRA  M     | Exclusive Or memory with A           | 10101110        |  7
-                let value = self.memory.read(self.state.get_HL());
+                let value = read_data!(self, self.state.get_HL());
                 self.state.A ^= value;
                 self.state.set_flags(self.state.A, false, self.state.on_aux_carry());
             },
             0b10110110 => { // ORA  M     | Or memory with A                     | 10110110        |  7
-                let value = self.memory.read(self.state.get_HL());
+                let value = read_data!(self, self.state.get_HL());
                 self.state.A |= value;
                 self.state.set_flags(self.state.A, false, self.state.on_aux_carry());
             },
             0b10111110 => { // CMP  M     | Compare memory with A                | 10111110        |  7
-                let value = self.memory.read(self.state.get_HL());
+                let value = read_data!(self, self.state.get_HL());
                 let (sum, carry) = self.state.A.overflowing_sub(value);
                 self.state.set_flags(sum, carry, (self.state.A & 0xf) + ((!value).wrapping_add(1) & 0xf) > 0xf );
             },
@@ -917,129 +1149,135 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
             },
             0b11001101 => { // CALL       | Call unconditional                   | 11001101        | 17
                 let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                push_stack!(self, self.state.get_PC());
                 self.state.set_PC(adress);
             },
             0b11011100 => { // CC         | Call on carry                        | 11011100        | 11/17
                 if self.state.on_carry() {
                     let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                    self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                    push_stack!(self, self.state.get_PC());
                     self.state.set_PC(adress);
                 }
             },
             0b11010100 => { // CNC        | Call on no carry        | 11010100        | 11/17
                 if !self.state.on_carry() {
                     let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                    self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                    push_stack!(self, self.state.get_PC());
                     self.state.set_PC(adress);
                 }
             },
             0b11001100 => { // CZ         | Call on zero                         | 11001100        | 11/17
                 if self.state.on_zero() {
                     let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                    self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                    push_stack!(self, self.state.get_PC());
                     self.state.set_PC(adress);
                 }
             },
             0b11000100 => { // CNZ        | Call on no zero                      | 11000100        | 11/17
                 if !self.state.on_zero() {
                     let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                    self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                    push_stack!(self, self.state.get_PC());
                     self.state.set_PC(adress);
                 }
             },
             0b11110100 => { // CP         | Call on positive                     | 11110100        | 11/17
                 if self.state.on_positive() {
                     let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                    self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                    push_stack!(self, self.state.get_PC());
                     self.state.set_PC(adress);
                 }
             },
             0b11111100 => { // CM         | Call on minus                        | 11111100        | 11/17
                 if !self.state.on_positive() {
                     let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                    self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                    push_stack!(self, self.state.get_PC());
                     self.state.set_PC(adress);
                 }
             },
             0b11101100 => { // CPE        | Call on parity even                  | 11101100        | 11/17
                 if self.state.on_parity_even() {
                     let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                    self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                    push_stack!(self, self.state.get_PC());
                     self.state.set_PC(adress);
                 }
             },
             0b11100100 => { // CPO        | Call on parity odd                   | 11100100        | 11/17
                 if !self.state.on_parity_even() {
                     let adress = self.memory.read_u16(self.state.get_PC() - 2);
-                    self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                    push_stack!(self, self.state.get_PC());
                     self.state.set_PC(adress);
                 }
             },
             0b11001001 => { // RET        | Return                               | 11001001        | 10
-                let adress = self.state.pop_stack(&self.memory);
+                let adress = pop_stack!(self);
                 self.state.set_PC(adress);
             },
             0b11011000 => { // RC         | Return on carry                      | 11011000        | 5/11
                 if self.state.on_carry() {
-                    let adress = self.state.pop_stack(&self.memory);
+                    let adress = pop_stack!(self);
                     self.state.set_PC(adress);
                 }
             },
             0b11010000 => { // RNC        | Return on no carry                   | 11010000        | 5/11
                 if !self.state.on_carry() {
-                    let adress = self.state.pop_stack(&self.memory);
+                    let adress = pop_stack!(self);
                     self.state.set_PC(adress);
                 }
             },
             0b11001000 => { // RZ         | Return on zero                       | 11001000        | 5/11
                 if self.state.on_zero() {
-                    let adress = self.state.pop_stack(&self.memory);
+                    let adress = pop_stack!(self);
                     self.state.set_PC(adress);
                 }
             },
             0b11000000 => { // RNZ        | Return on no zero                    | 11000000        | 5/11
                 if !self.state.on_zero() {
-                    let adress = self.state.pop_stack(&self.memory);
+                    let adress = pop_stack!(self);
                     self.state.set_PC(adress);
                 }
             },
             0b11110000 => { // RP         | Return on positive                   | 11110000        | 5/11
                 if self.state.on_positive() {
-                    let adress = self.state.pop_stack(&self.memory);
+                    let adress = pop_stack!(self);
                     self.state.set_PC(adress);
                 }
             },
             0b11111000 => { // RM         | Return on minus                      | 11111000        | 5/11
                 if !self.state.on_positive() {
-                    let adress = self.state.pop_stack(&self.memory);
+                    let adress = pop_stack!(self);
                     self.state.set_PC(adress);
                 }
             },
             0b11101000 => { // RPE        | Return on parity even                | 11101000        | 5/11
                 if self.state.on_parity_even() {
-                    let adress = self.state.pop_stack(&self.memory);
+                    let adress = pop_stack!(self);
                     self.state.set_PC(adress);
                 }
             },
             0b11100000 => { // RPO        | Return on parity odd                 | 11100000        | 5/11
                 if !self.state.on_parity_even() {
-                    let adress = self.state.pop_stack(&self.memory);
+                    let adress = pop_stack!(self);
                     self.state.set_PC(adress);
                 }
             },
             _ if opcode & 0b11000111 == 0b11000111 => { // RST        | Restart                              | 11AAA111        | 11
                 let adress = opcode & 0b00111000;
-                self.state.push_stack(self.state.get_PC(), &mut self.memory);
+                push_stack!(self, self.state.get_PC());
                 self.state.set_PC(adress as u16);
             },
             0b11011011 => { // IN         | Input                                | 11011011        | 10
                 let device = self.memory.read(self.state.get_PC()-1);
+                self.devices.set_clock_count(self.clock_count);
                 self.state.A = self.devices.read(device);
+                #[cfg(feature = "debug")]
+                self.debugger.on_port_access(device, self.state.A, false);
             },
             0b11010011 => { // OUT        | Output                               | 11010011        | 10
                 let device = self.memory.read(self.state.get_PC()-1);
+                self.devices.set_clock_count(self.clock_count);
                 self.devices.write(device, self.state.A);
+                #[cfg(feature = "debug")]
+                self.debugger.on_port_access(device, self.state.A, true);
             },
             0b00000001 => { // LXI  B     | Load immediate register Pair B & C   | 00000001        | 10
                 let immediate = self.memory.read_u16(self.state.get_PC() - 2);
@@ -1058,40 +1296,40 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
                 self.state.set_SP(immediate);
             },
             0b11000101 => { // PUSH B     | Push register Pair B & C on stack    | 11000101        | 11
-                self.state.push_stack(self.state.get_BC(), &mut self.memory);
+                push_stack!(self, self.state.get_BC());
             },
             0b11010101 => { // PUSH D     | Push register Pair D & E on stack    | 11010101        | 11
-                self.state.push_stack(self.state.get_DE(), &mut self.memory);
+                push_stack!(self, self.state.get_DE());
             },
             0b11100101 => { // PUSH H     | Push register Pair H & L on stack    | 11100101        | 11
-                self.state.push_stack(self.state.get_HL(), &mut self.memory);
+                push_stack!(self, self.state.get_HL());
             },
             0b11110101 => { // PUSH PSW   | Push A and Flags on stack            | 11110001        | 11
-                self.state.push_stack(self.state.get_PSW(), &mut self.memory);
+                push_stack!(self, self.state.get_PSW());
             },
             0b11000001 => { // POP  B     | Pop register pair B & C off stack    | 11000001        | 10
-                let value = self.state.pop_stack(&self.memory);
+                let value = pop_stack!(self);
                 self.state.set_BC(value);
             },
             0b11010001 => { // POP  D     | Pop register pair D & E off stack    | 11010001        | 10
-                let value = self.state.pop_stack(&self.memory);
+                let value = pop_stack!(self);
                 self.state.set_DE(value);
             },
             0b11100001 => { // POP  H     | Pop register pair H & L off stick    | 11100001        | 10
-                let value = self.state.pop_stack(&self.memory);
+                let value = pop_stack!(self);
                 self.state.set_HL(value);
             },
             0b11110001 => { // POP  PSW   | Pop A and Flags off stack            | 11110001        | 10
-                let value = self.state.pop_stack(&self.memory);
+                let value = pop_stack!(self);
                 self.state.set_PSW(value);
             },
             0b00110010 => { // STA        | Store A direct                       | 00110010        | 13
                 let immediate = self.memory.read_u16(self.state.get_PC() - 2);
-                self.memory.write(immediate, self.state.A);
+                write_data!(self, immediate, self.state.A);
             },
             0b00111010 => { // LDA        | Load A direct                        | 00111010        | 13
                 let immediate = self.memory.read_u16(self.state.get_PC() - 2);
-                self.state.A = self.memory.read(immediate);
+                self.state.A = read_data!(self, immediate);
             },
             0b11101011 => { // XCHG       | Exchange D & E, H & L Registers      | 11101011        | 4
                 let de = self.state.get_DE();
@@ -1099,10 +1337,10 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
                 self.state.set_HL(de);
             },
             0b11100011 => { // XTHL       | Exchange top of stack, H & L         | 11100011        | 18
-                let b2 = self.memory.read(self.state.get_SP() + 1);
-                let b1 = self.memory.read(self.state.get_SP());
-                self.memory.write(self.state.get_SP() + 1, self.state.H);
-                self.memory.write(self.state.get_SP(), self.state.L);
+                let b2 = read_data!(self, self.state.get_SP() + 1);
+                let b1 = read_data!(self, self.state.get_SP());
+                write_data!(self, self.state.get_SP() + 1, self.state.H);
+                write_data!(self, self.state.get_SP(), self.state.L);
                 self.state.H = b2;
                 self.state.L = b1;
             },
@@ -1134,19 +1372,19 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
             },
             0b00000010 => { // STAX B     | Store A indirect                     | 00000010        | 7
                 let adress = self.state.get_BC();
-                self.memory.write(adress, self.state.A);
+                write_data!(self, adress, self.state.A);
             },
             0b00010010 => { // STAX D     | Store A Indirect                     | 00010010        | 7
                 let adress = self.state.get_DE();
-                self.memory.write(adress, self.state.A);
+                write_data!(self, adress, self.state.A);
             },
             0b00001010 => { // LDAX B     | Load A indirect                      | 00001010        | 7
                 let adress = self.state.get_BC();
-                self.state.A = self.memory.read(adress);
+                self.state.A = read_data!(self, adress);
             },
             0b00011010 => { // LDAX D     | Load A indirect                      | 00011010        | 7
                 let adress = self.state.get_DE();
-                self.state.A = self.memory.read(adress);
+                self.state.A = read_data!(self, adress);
             },
             0b00000011 => { // INX  B     | Increment B & C registers            | 00000011        | 5
                 self.state.set_BC(self.state.get_BC().wrapping_add(1));
@@ -1197,16 +1435,17 @@ impl<M: Memory, I: IODevices> Interpreter<M, I> {
             },
             0b00100010 => { // SHLD       | Store H & L direct                   | 00100010        | 16
                 let adr = self.memory.read_u16(self.state.get_PC() - 2);
-                self.memory.write(adr, self.state.L);
-                self.memory.write(adr+1, self.state.H);
+                write_data!(self, adr, self.state.L);
+                write_data!(self, adr+1, self.state.H);
             },
             0b00101010 => { // LHLD       | Load H & L direct                    | 00101010        | 16
                 let adr = self.memory.read_u16(self.state.get_PC() - 2);
-                self.state.L = self.memory.read(adr);
-                self.state.H = self.memory.read(adr+1);
+                self.state.L = read_data!(self, adr);
+                self.state.H = read_data!(self, adr+1);
             },
             0b11111011 => { // EI         | Enable Interrupts                    | 11111011        | 4
                 self.state.interrupt_enabled = true;
+                self.ei_delay = true;
             },
             0b11110011 => { // DI         | Disable Interrupts                   | 11110011        | 4
                 self.state.interrupt_enabled = false;
@@ -1,8 +1,15 @@
+pub mod cpm;
+#[cfg(feature = "debug")]
+pub mod debugger;
 pub mod dissasembler;
+#[cfg(feature = "debug")]
+pub mod expression;
+#[cfg(feature = "debug")]
+pub mod gdb;
 mod intel8080;
 pub mod interpreter;
-
-#[cfg(feature = "debug")]
-mod write_adapter;
+pub mod memory_map;
+pub mod save_state;
+pub mod scheduler;
 
 pub use intel8080::*;
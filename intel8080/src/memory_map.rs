@@ -0,0 +1,408 @@
+//! A `Memory` built from declarative regions, so a machine can describe its address space
+//! instead of writing its own address decoding.
+//!
+//! ```
+//! use intel8080::memory_map::MemoryMap;
+//!
+//! let rom = vec![0; 0x2000];
+//! let memory = MemoryMap::builder()
+//!     .rom(0x0000, rom)
+//!     .ram(0x2000, 0x2000)
+//!     // the RAM repeats every 0x2000 bytes in the upper half
+//!     .mirror(0x4000, 0xc000, 0x2000, 0x2000)
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! The adresses not covered by any region are unmapped: reads return the open bus value,
+//! and writes are ignored. Writes to ROM and accesses to unmapped adresses are faults,
+//! recorded in the map and optionally logged.
+
+use std::{cell::Cell, fmt};
+
+use crate::intel8080::Memory;
+use crate::save_state::{self, SaveStateError};
+
+/// A memory mapped device.
+pub trait Mmio: Send {
+    /// Read the register at 'offset' from the start of the region.
+    fn read(&self, offset: u16) -> u8;
+    fn write(&mut self, offset: u16, value: u8);
+}
+
+enum RegionKind {
+    Rom(Box<[u8]>),
+    Ram(Box<[u8]>),
+    /// Repeats the `size` bytes starting at `target`.
+    Mirror {
+        target: u16,
+        size: u32,
+    },
+    Mmio(Box<dyn Mmio>),
+}
+
+struct Region {
+    start: u16,
+    len: u32,
+    kind: RegionKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// Read from a adress with no region.
+    UnmappedRead,
+    /// Write to a adress with no region.
+    UnmappedWrite,
+    /// Write to a ROM region.
+    RomWrite,
+}
+
+/// A invalid access to the memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryFault {
+    pub kind: FaultKind,
+    pub adress: u16,
+}
+
+impl fmt::Display for MemoryFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FaultKind::UnmappedRead => write!(f, "read from unmapped adress {:04x}", self.adress),
+            FaultKind::UnmappedWrite => write!(f, "write to unmapped adress {:04x}", self.adress),
+            FaultKind::RomWrite => write!(f, "write to ROM at {:04x}", self.adress),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MemoryMapError {
+    /// The region goes past the end of the address space.
+    OutOfRange { start: u16, len: u32 },
+    /// The region overlaps a previous one.
+    Overlap { adress: u16 },
+    /// The mirror target is not a ROM or RAM region.
+    BadMirrorTarget { target: u16 },
+    /// More regions than the adress table can index.
+    TooManyRegions { count: usize },
+}
+
+impl fmt::Display for MemoryMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryMapError::OutOfRange { start, len } => {
+                write!(
+                    f,
+                    "region at {:04x} of {} bytes is out of range",
+                    start, len
+                )
+            }
+            MemoryMapError::Overlap { adress } => {
+                write!(f, "regions overlap at {:04x}", adress)
+            }
+            MemoryMapError::BadMirrorTarget { target } => {
+                write!(f, "mirror target {:04x} is not a ROM or RAM region", target)
+            }
+            MemoryMapError::TooManyRegions { count } => {
+                write!(
+                    f,
+                    "{} regions, at most {} are supported",
+                    count, MAX_REGIONS
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MemoryMapError {}
+
+/// No region in this adress.
+const UNMAPPED: u8 = u8::MAX;
+/// The number of regions a map can have: the indexes of the table, but `UNMAPPED`.
+pub const MAX_REGIONS: usize = UNMAPPED as usize;
+
+pub struct MemoryMapBuilder {
+    regions: Vec<Region>,
+    open_bus: u8,
+    log_faults: bool,
+}
+impl MemoryMapBuilder {
+    fn region(mut self, start: u16, len: u32, kind: RegionKind) -> Self {
+        self.regions.push(Region { start, len, kind });
+        self
+    }
+
+    /// A read only region with the given content.
+    pub fn rom(self, start: u16, data: impl Into<Box<[u8]>>) -> Self {
+        let data = data.into();
+        self.region(start, data.len() as u32, RegionKind::Rom(data))
+    }
+
+    /// A zeroed read and write region.
+    pub fn ram(self, start: u16, len: u32) -> Self {
+        self.ram_from(start, vec![0; len as usize])
+    }
+
+    /// A read and write region with the given initial content.
+    pub fn ram_from(self, start: u16, data: impl Into<Box<[u8]>>) -> Self {
+        let data = data.into();
+        self.region(start, data.len() as u32, RegionKind::Ram(data))
+    }
+
+    /// A region of 'len' bytes that repeats the 'size' bytes starting at 'target', which
+    /// must be in a ROM or RAM region.
+    pub fn mirror(self, start: u16, len: u32, target: u16, size: u32) -> Self {
+        self.region(start, len, RegionKind::Mirror { target, size })
+    }
+
+    /// A region handled by a memory mapped device.
+    pub fn mmio(self, start: u16, len: u32, device: impl Mmio + 'static) -> Self {
+        self.region(start, len, RegionKind::Mmio(Box::new(device)))
+    }
+
+    /// The value read from unmapped adresses. 0 by default.
+    pub fn open_bus(mut self, value: u8) -> Self {
+        self.open_bus = value;
+        self
+    }
+
+    /// Print each fault to stdout. Disabled by default.
+    pub fn log_faults(mut self, log: bool) -> Self {
+        self.log_faults = log;
+        self
+    }
+
+    pub fn build(self) -> Result<MemoryMap, MemoryMapError> {
+        if self.regions.len() > MAX_REGIONS {
+            return Err(MemoryMapError::TooManyRegions {
+                count: self.regions.len(),
+            });
+        }
+        let mut table = vec![UNMAPPED; 0x10000].into_boxed_slice();
+        for (i, region) in self.regions.iter().enumerate() {
+            let end = region.start as u32 + region.len;
+            if end > 0x10000 {
+                return Err(MemoryMapError::OutOfRange {
+                    start: region.start,
+                    len: region.len,
+                });
+            }
+            for adress in region.start as usize..end as usize {
+                if table[adress] != UNMAPPED {
+                    return Err(MemoryMapError::Overlap {
+                        adress: adress as u16,
+                    });
+                }
+                table[adress] = i as u8;
+            }
+        }
+
+        let map = MemoryMap {
+            regions: self.regions,
+            table,
+            open_bus: self.open_bus,
+            log_faults: self.log_faults,
+            last_fault: Cell::new(None),
+            fault_count: Cell::new(0),
+        };
+        for region in &map.regions {
+            if let RegionKind::Mirror { target, size } = region.kind {
+                let valid = size > 0
+                    && match map.region_at(target) {
+                        Some(r) => {
+                            matches!(r.kind, RegionKind::Rom(_) | RegionKind::Ram(_))
+                                && target as u32 + size <= r.start as u32 + r.len
+                        }
+                        None => false,
+                    };
+                if !valid {
+                    return Err(MemoryMapError::BadMirrorTarget { target });
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+pub struct MemoryMap {
+    regions: Vec<Region>,
+    /// The index of the region of each adress.
+    table: Box<[u8]>,
+    open_bus: u8,
+    log_faults: bool,
+    last_fault: Cell<Option<MemoryFault>>,
+    fault_count: Cell<u64>,
+}
+impl MemoryMap {
+    pub fn builder() -> MemoryMapBuilder {
+        MemoryMapBuilder {
+            regions: Vec::new(),
+            open_bus: 0,
+            log_faults: false,
+        }
+    }
+
+    fn region_at(&self, adress: u16) -> Option<&Region> {
+        match self.table[adress as usize] {
+            UNMAPPED => None,
+            i => Some(&self.regions[i as usize]),
+        }
+    }
+
+    /// Resolve the mirrors, returning the region index and the adress in it.
+    #[inline]
+    fn resolve(&self, adress: u16) -> Option<(usize, u16)> {
+        let i = self.table[adress as usize];
+        if i == UNMAPPED {
+            return None;
+        }
+        let region = &self.regions[i as usize];
+        match region.kind {
+            RegionKind::Mirror { target, size } => {
+                let adress = target + ((adress - region.start) as u32 % size) as u16;
+                Some((self.table[adress as usize] as usize, adress))
+            }
+            _ => Some((i as usize, adress)),
+        }
+    }
+
+    fn fault(&self, kind: FaultKind, adress: u16) {
+        let fault = MemoryFault { kind, adress };
+        if self.log_faults {
+            println!("memory fault: {}", fault);
+        }
+        self.last_fault.set(Some(fault));
+        self.fault_count.set(self.fault_count.get() + 1);
+    }
+
+    /// Print each fault to stdout, like `MemoryMapBuilder::log_faults`.
+    pub fn set_log_faults(&mut self, log: bool) {
+        self.log_faults = log;
+    }
+
+    /// The last invalid access, if any.
+    pub fn last_fault(&self) -> Option<MemoryFault> {
+        self.last_fault.get()
+    }
+
+    /// The number of invalid accesses since the map was created.
+    pub fn fault_count(&self) -> u64 {
+        self.fault_count.get()
+    }
+
+    /// Read a adress without side effects: no fault is recorded, and the memory mapped
+    /// devices are not read. None if it is not ROM or RAM.
+    pub fn peek(&self, adress: u16) -> Option<u8> {
+        self.bytes(adress, 1).map(|bytes| bytes[0])
+    }
+
+    /// The 'len' bytes starting at 'start', if they are all in the same ROM or RAM region.
+    pub fn bytes(&self, start: u16, len: usize) -> Option<&[u8]> {
+        let (i, adress) = self.resolve(start)?;
+        let region = &self.regions[i];
+        let offset = (adress - region.start) as usize;
+        match &region.kind {
+            RegionKind::Rom(data) | RegionKind::Ram(data) => data.get(offset..offset + len),
+            _ => None,
+        }
+    }
+
+    /// The mutable version of `bytes`, that can also write to the ROM.
+    pub fn bytes_mut(&mut self, start: u16, len: usize) -> Option<&mut [u8]> {
+        let (i, adress) = self.resolve(start)?;
+        let region = &mut self.regions[i];
+        let offset = (adress - region.start) as usize;
+        match &mut region.kind {
+            RegionKind::Rom(data) | RegionKind::Ram(data) => data.get_mut(offset..offset + len),
+            _ => None,
+        }
+    }
+
+    fn ram_regions(&self) -> impl Iterator<Item = &[u8]> {
+        self.regions.iter().filter_map(|r| match &r.kind {
+            RegionKind::Ram(data) => Some(&data[..]),
+            _ => None,
+        })
+    }
+}
+
+impl Memory for MemoryMap {
+    #[inline]
+    fn read(&self, adress: u16) -> u8 {
+        let Some((i, resolved)) = self.resolve(adress) else {
+            self.fault(FaultKind::UnmappedRead, adress);
+            return self.open_bus;
+        };
+        let region = &self.regions[i];
+        let offset = resolved - region.start;
+        match &region.kind {
+            RegionKind::Rom(data) | RegionKind::Ram(data) => data[offset as usize],
+            RegionKind::Mmio(device) => device.read(offset),
+            RegionKind::Mirror { .. } => unreachable!("mirror of a mirror"),
+        }
+    }
+
+    #[inline]
+    fn write(&mut self, adress: u16, value: u8) {
+        let Some((i, resolved)) = self.resolve(adress) else {
+            self.fault(FaultKind::UnmappedWrite, adress);
+            return;
+        };
+        let region = &mut self.regions[i];
+        let offset = resolved - region.start;
+        match &mut region.kind {
+            RegionKind::Ram(data) => data[offset as usize] = value,
+            RegionKind::Mmio(device) => device.write(offset, value),
+            RegionKind::Rom(_) => self.fault(FaultKind::RomWrite, adress),
+            RegionKind::Mirror { .. } => unreachable!("mirror of a mirror"),
+        };
+    }
+
+    /// The adress space from 0 to the end of the last ROM region.
+    fn get_rom(&mut self) -> Vec<u8> {
+        let end = self
+            .regions
+            .iter()
+            .filter(|r| matches!(r.kind, RegionKind::Rom(_)))
+            .map(|r| r.start as u32 + r.len)
+            .max()
+            .unwrap_or(0);
+        (0..end)
+            .map(|adress| match self.resolve(adress as u16) {
+                Some((i, resolved)) => match &self.regions[i].kind {
+                    RegionKind::Rom(data) | RegionKind::Ram(data) => {
+                        data[(resolved - self.regions[i].start) as usize]
+                    }
+                    _ => self.open_bus,
+                },
+                None => self.open_bus,
+            })
+            .collect()
+    }
+
+    /// The RAM regions, in the order they were added.
+    fn save_state(&self, out: &mut Vec<u8>) {
+        for data in self.ram_regions() {
+            out.extend_from_slice(data);
+        }
+    }
+
+    fn load_state(&mut self, section: &[u8]) -> Result<(), SaveStateError> {
+        let expected = self.ram_regions().map(<[u8]>::len).sum();
+        if section.len() != expected {
+            return Err(SaveStateError::BadSectionLength {
+                tag: save_state::MEMORY_TAG,
+                expected,
+                found: section.len(),
+            });
+        }
+        let mut input = section;
+        for region in &mut self.regions {
+            if let RegionKind::Ram(data) = &mut region.kind {
+                let (bytes, rest) = input.split_at(data.len());
+                data.copy_from_slice(bytes);
+                input = rest;
+            }
+        }
+        Ok(())
+    }
+}
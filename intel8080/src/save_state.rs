@@ -0,0 +1,193 @@
+//! Binary save state format.
+//!
+//! A save state is a small header followed by a sequence of sections. Each
+//! section is a 4 byte tag, a little endian `u32` length and the payload, so a
+//! machine can add or grow its own section without breaking the others.
+//!
+//! ```text
+//! "I8080SAV" | version: u16 | "CPU " section | "MEM " section | "IO  " section
+//! ```
+//!
+//! A machine built around the interpreter can append its own sections after these, that
+//! `Interpreter::load_state` ignores.
+
+use std::fmt;
+use std::io;
+
+pub const MAGIC: &[u8; 8] = b"I8080SAV";
+pub const VERSION: u16 = 4;
+
+pub const CPU_TAG: &[u8; 4] = b"CPU ";
+pub const MEMORY_TAG: &[u8; 4] = b"MEM ";
+pub const DEVICES_TAG: &[u8; 4] = b"IO  ";
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    /// The data does not start with the save state magic.
+    BadMagic,
+    /// The save state was written by a incompatible version.
+    UnsupportedVersion(u16),
+    /// Expected a section with the given tag, but found other thing.
+    MissingSection(&'static [u8; 4]),
+    /// The data ended before the expected.
+    UnexpectedEof,
+    /// A section has more or less data than the machine expected.
+    BadSectionLength {
+        tag: &'static [u8; 4],
+        expected: usize,
+        found: usize,
+    },
+    /// A value of a section is out of range for the machine.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::Io(e) => write!(f, "io error: {}", e),
+            SaveStateError::BadMagic => write!(f, "not a save state file"),
+            SaveStateError::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "unsupported save state version {} (expected {})",
+                    v, VERSION
+                )
+            }
+            SaveStateError::MissingSection(tag) => {
+                write!(f, "missing section '{}'", String::from_utf8_lossy(*tag))
+            }
+            SaveStateError::UnexpectedEof => write!(f, "unexpected end of save state"),
+            SaveStateError::BadSectionLength {
+                tag,
+                expected,
+                found,
+            } => write!(
+                f,
+                "section '{}' has {} bytes, expected {}",
+                String::from_utf8_lossy(*tag),
+                found,
+                expected
+            ),
+            SaveStateError::Invalid(what) => write!(f, "invalid save state: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(e: io::Error) -> Self {
+        SaveStateError::Io(e)
+    }
+}
+
+pub fn write_u8(out: &mut Vec<u8>, value: u8) {
+    out.push(value);
+}
+
+pub fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_bool(out: &mut Vec<u8>, value: bool) {
+    out.push(value as u8);
+}
+
+/// Write the magic and the version.
+pub fn write_header(out: &mut Vec<u8>) {
+    out.extend_from_slice(MAGIC);
+    write_u16(out, VERSION);
+}
+
+/// Write a section, whose payload is written by `f`.
+pub fn write_section(out: &mut Vec<u8>, tag: &[u8; 4], f: impl FnOnce(&mut Vec<u8>)) {
+    out.extend_from_slice(tag);
+    let len_pos = out.len();
+    write_u32(out, 0);
+    f(out);
+    let len = (out.len() - len_pos - 4) as u32;
+    out[len_pos..len_pos + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+pub fn read_bytes<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], SaveStateError> {
+    if input.len() < len {
+        return Err(SaveStateError::UnexpectedEof);
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+pub fn read_u8(input: &mut &[u8]) -> Result<u8, SaveStateError> {
+    Ok(read_bytes(input, 1)?[0])
+}
+
+pub fn read_u16(input: &mut &[u8]) -> Result<u16, SaveStateError> {
+    let bytes = read_bytes(input, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn read_u32(input: &mut &[u8]) -> Result<u32, SaveStateError> {
+    let bytes = read_bytes(input, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+pub fn read_u64(input: &mut &[u8]) -> Result<u64, SaveStateError> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(read_bytes(input, 8)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_bool(input: &mut &[u8]) -> Result<bool, SaveStateError> {
+    Ok(read_u8(input)? != 0)
+}
+
+/// Check the magic and the version.
+pub fn read_header(input: &mut &[u8]) -> Result<(), SaveStateError> {
+    if read_bytes(input, MAGIC.len()).map_err(|_| SaveStateError::BadMagic)? != MAGIC {
+        return Err(SaveStateError::BadMagic);
+    }
+    let version = read_u16(input)?;
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    Ok(())
+}
+
+/// Read a section with the given tag, returning its payload.
+pub fn read_section<'a>(
+    input: &mut &'a [u8],
+    tag: &'static [u8; 4],
+) -> Result<&'a [u8], SaveStateError> {
+    if read_bytes(input, 4).map_err(|_| SaveStateError::MissingSection(tag))? != tag {
+        return Err(SaveStateError::MissingSection(tag));
+    }
+    let len = read_u32(input)? as usize;
+    read_bytes(input, len)
+}
+
+/// Copy a section payload into `buf`, that must have exactly the same length.
+pub fn read_exact_section(
+    section: &[u8],
+    tag: &'static [u8; 4],
+    buf: &mut [u8],
+) -> Result<(), SaveStateError> {
+    if section.len() != buf.len() {
+        return Err(SaveStateError::BadSectionLength {
+            tag,
+            expected: buf.len(),
+            found: section.len(),
+        });
+    }
+    buf.copy_from_slice(section);
+    Ok(())
+}
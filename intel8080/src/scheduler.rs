@@ -0,0 +1,107 @@
+use crate::intel8080::{IODevices, Memory};
+use crate::interpreter::{Interpreter, StopReason};
+use crate::save_state::{self, SaveStateError};
+
+/// Schedule events at fixed clock offsets inside a frame, like the interrupts that a
+/// video circuit generates when the beam reach a given scanline.
+///
+/// The clocks are absolute, so when a instruction overshoots a event, the extra clocks
+/// are discounted from the next slice, and no time is lost between frames.
+#[derive(Clone)]
+pub struct Scheduler<E> {
+    frame_length: u32,
+    /// sorted by clock
+    events: Vec<(u32, E)>,
+    /// The start of the frame and the next event, when the last run was stopped by the
+    /// debugger in the middle of a frame.
+    resume: Option<(u64, usize)>,
+}
+impl<E> Scheduler<E> {
+    /// Create a scheduler for frames of 'frame_length' clocks.
+    pub fn new(frame_length: u32) -> Self {
+        Self {
+            frame_length,
+            events: Vec::new(),
+            resume: None,
+        }
+    }
+
+    pub fn frame_length(&self) -> u32 {
+        self.frame_length
+    }
+
+    /// Register a event that happens 'clock' clocks after the start of each frame.
+    pub fn add_event(&mut self, clock: u32, event: E) {
+        assert!(
+            clock <= self.frame_length,
+            "event at clock {} is out of the frame of {} clocks",
+            clock,
+            self.frame_length
+        );
+        let i = self.events.partition_point(|(c, _)| *c <= clock);
+        self.events.insert(i, (clock, event));
+    }
+
+    /// If the last frame was stopped by the debugger, and the next run continues it.
+    pub fn is_mid_frame(&self) -> bool {
+        self.resume.is_some()
+    }
+
+    /// Serialize the frame stopped by the debugger, if any. The events are not saved, they
+    /// are part of the machine.
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        let (frame_start, next_event) = self.resume.unwrap_or((0, 0));
+        save_state::write_bool(out, self.resume.is_some());
+        save_state::write_u64(out, frame_start);
+        save_state::write_u32(out, next_event as u32);
+    }
+
+    pub fn load_state(&mut self, input: &mut &[u8]) -> Result<(), SaveStateError> {
+        let mid_frame = save_state::read_bool(input)?;
+        let frame_start = save_state::read_u64(input)?;
+        let next_event = save_state::read_u32(input)? as usize;
+        if next_event > self.events.len() {
+            return Err(SaveStateError::Invalid(
+                "the stopped frame has too many events",
+            ));
+        }
+        self.resume = mid_frame.then_some((frame_start, next_event));
+        Ok(())
+    }
+
+    /// Run the interpreter for one frame, calling 'on_event' when each event is reached.
+    /// Return false if the interpreter was stopped by the debugger before the end of the
+    /// frame; the next call continues the same frame.
+    pub fn run_frame<M, I, F>(
+        &mut self,
+        interpreter: &mut Interpreter<M, I>,
+        mut on_event: F,
+    ) -> bool
+    where
+        M: Memory,
+        I: IODevices,
+        F: FnMut(&mut Interpreter<M, I>, &E),
+    {
+        let frame_length = self.frame_length as u64;
+        let (frame_start, next_event) = self
+            .resume
+            .take()
+            // the clock jumps when a state is loaded, then the stopped frame is dropped
+            .filter(|&(start, _)| {
+                (start..=start + frame_length).contains(&interpreter.clock_count())
+            })
+            .unwrap_or((interpreter.target_clock(), 0));
+        for (i, (clock, event)) in self.events.iter().enumerate().skip(next_event) {
+            if interpreter.run_until(frame_start + *clock as u64) != StopReason::ClockReached {
+                self.resume = Some((frame_start, i));
+                return false;
+            }
+            on_event(interpreter, event);
+        }
+        if interpreter.run_until(frame_start + frame_length) != StopReason::ClockReached {
+            self.resume = Some((frame_start, self.events.len()));
+            return false;
+        }
+        true
+    }
+}
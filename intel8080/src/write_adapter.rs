@@ -1,18 +0,0 @@
-use std::fmt;
-use std::io;
-
-/// adapter from fmt::Write to io::Write
-pub struct WriteAdapter<W>(pub W);
-
-impl<W> fmt::Write for WriteAdapter<W>
-where
-    W: io::Write,
-{
-    fn write_str(&mut self, s: &str) -> Result<(), fmt::Error> {
-        self.0.write_all(s.as_bytes()).map_err(|_| fmt::Error)
-    }
-
-    fn write_fmt(&mut self, args: fmt::Arguments) -> Result<(), fmt::Error> {
-        self.0.write_fmt(args).map_err(|_| fmt::Error)
-    }
-}
@@ -0,0 +1,113 @@
+//! Run the classic cpu exerciser programs in the CP/M stand-in.
+//!
+//! TST8080 is in `rom/cpu_tests/`, and runs by default. The others are not distributed
+//! with this repository, so their tests are ignored: place them in `rom/cpu_tests/`, with
+//! the names below, and run them with
+//! `cargo test --release -p intel8080 --test cpu_exercisers -- --ignored`. A missing
+//! program fails its test. 8080EXM takes a few billion clocks, so it needs the release
+//! build.
+
+use std::{fs, path::Path};
+
+use intel8080::cpm;
+
+fn load_program(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../rom/cpu_tests")
+        .join(name);
+    fs::read(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e))
+}
+
+fn run_program(name: &str, max_clocks: u64) -> String {
+    let program = load_program(name);
+    let output = cpm::run(&program, max_clocks).unwrap_or_else(|e| panic!("{}: {}", name, e));
+    println!("{}", output);
+    output
+}
+
+#[test]
+fn bdos_console_output() {
+    let program = [
+        0x0e, 0x09, // MVI C, 9
+        0x11, 0x10, 0x01, // LXI D, 0110
+        0xcd, 0x05, 0x00, // CALL 0005
+        0x0e, 0x02, // MVI C, 2
+        0x1e, b'!', // MVI E, '!'
+        0xcd, 0x05, 0x00, // CALL 0005
+        0xc9, // RET
+        b'H', b'E', b'L', b'L', b'O', b'$',
+    ];
+    assert_eq!(cpm::run(&program, 1_000).unwrap(), "HELLO!");
+}
+
+#[test]
+fn warm_boot_by_jump() {
+    let program = [
+        0x0e, 0x02, // MVI C, 2
+        0x1e, b'A', // MVI E, 'A'
+        0xcd, 0x05, 0x00, // CALL 0005
+        0xc3, 0x00, 0x00, // JMP 0000
+        0x0e, 0x02, // MVI C, 2
+        0x1e, b'B', // MVI E, 'B'
+        0xcd, 0x05, 0x00, // CALL 0005
+    ];
+    assert_eq!(cpm::run(&program, 1_000).unwrap(), "A");
+}
+
+#[test]
+fn other_bdos_functions_are_ignored() {
+    let program = [
+        0x0e, 0x01, // MVI C, 1 (console input)
+        0xcd, 0x05, 0x00, // CALL 0005
+        0x0e, 0x02, // MVI C, 2
+        0x1e, b'A', // MVI E, 'A'
+        0xcd, 0x05, 0x00, // CALL 0005
+        0x0e, 0x00, // MVI C, 0 (system reset)
+        0xcd, 0x05, 0x00, // CALL 0005
+        0x0e, 0x02, // MVI C, 2
+        0x1e, b'B', // MVI E, 'B'
+        0xcd, 0x05, 0x00, // CALL 0005
+    ];
+    assert_eq!(cpm::run(&program, 1_000).unwrap(), "A");
+}
+
+#[test]
+fn timeout() {
+    let program = [0xc3, 0x00, 0x01]; // JMP 0100
+    assert!(matches!(
+        cpm::run(&program, 1_000),
+        Err(cpm::CpmError::Timeout(_))
+    ));
+}
+
+#[test]
+fn tst8080() {
+    let output = run_program("TST8080.COM", 10_000_000);
+    assert!(output.contains("CPU IS OPERATIONAL"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs rom/cpu_tests/8080PRE.COM"]
+fn i8080pre() {
+    let output = run_program("8080PRE.COM", 10_000_000);
+    assert!(
+        output.contains("8080 Preliminary tests complete"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+#[ignore = "needs rom/cpu_tests/CPUTEST.COM"]
+fn cputest() {
+    let output = run_program("CPUTEST.COM", 1_000_000_000);
+    assert!(output.contains("CPU TESTS OK"), "{}", output);
+}
+
+#[test]
+#[ignore = "needs rom/cpu_tests/8080EXM.COM, and takes billions of clocks, run it in release"]
+fn i8080exm() {
+    let output = run_program("8080EXM.COM", 50_000_000_000);
+    assert!(output.contains("Tests complete"), "{}", output);
+    assert!(!output.contains("ERROR"), "{}", output);
+}
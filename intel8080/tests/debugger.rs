@@ -0,0 +1,306 @@
+#![cfg(feature = "debug")]
+
+use intel8080::{
+    NoDevices,
+    debugger::{Access, Breakpoint, Command, Register, Response, Watchpoint},
+    expression::{Expression, Message},
+    interpreter::{Interpreter, StopReason},
+    memory_map::MemoryMap,
+    scheduler::Scheduler,
+};
+
+/// `NOP; JMP 0000`, forever.
+fn looping_interpreter() -> Interpreter<MemoryMap, NoDevices> {
+    let memory = MemoryMap::builder()
+        .rom(0x0000, vec![0x00, 0xc3, 0x00, 0x00])
+        .build()
+        .unwrap();
+    Interpreter::new(NoDevices, memory, &[0x0000])
+}
+
+#[test]
+fn breakpoint_stops_the_frame_and_resume_continues_it() {
+    let mut interpreter = looping_interpreter();
+    let mut scheduler = Scheduler::new(100);
+    scheduler.add_event(50, ());
+    interpreter.debug_command(Command::AddBreakpoint(0x0001));
+
+    let mut events = 0;
+    assert!(!scheduler.run_frame(&mut interpreter, |_, _| events += 1));
+    assert_eq!(
+        interpreter.debugger().stop_reason(),
+        Some(StopReason::Breakpoint(0x0001))
+    );
+    assert!(scheduler.is_mid_frame());
+    assert_eq!(interpreter.state().get_PC(), 0x0001);
+    assert_eq!(events, 0);
+
+    // stepping while stopped don't lose the frame
+    interpreter.debug_command(Command::Step);
+    assert_eq!(interpreter.state().get_PC(), 0x0000);
+
+    interpreter.debug_command(Command::RemoveBreakpoint(0x0001));
+    interpreter.debug_command(Command::Continue);
+    assert!(scheduler.run_frame(&mut interpreter, |_, _| events += 1));
+    assert!(!scheduler.is_mid_frame());
+    assert_eq!(events, 1);
+    assert!(interpreter.clock_count() >= 100);
+}
+
+#[test]
+fn stopped_interpreter_does_not_run() {
+    let mut interpreter = looping_interpreter();
+    interpreter.debug_command(Command::Pause);
+    assert_eq!(interpreter.run_until(1000), StopReason::Paused);
+    assert_eq!(interpreter.clock_count(), 0);
+
+    interpreter.debug_command(Command::Continue);
+    assert_eq!(interpreter.run_until(1000), StopReason::ClockReached);
+    assert!(interpreter.clock_count() >= 1000);
+}
+
+/// `CALL 0010; NOP; HLT`, with a subroutine `NOP; NOP; RET` at 0010.
+fn calling_interpreter() -> Interpreter<MemoryMap, NoDevices> {
+    let mut rom = vec![0; 0x20];
+    rom[..5].copy_from_slice(&[0xcd, 0x10, 0x00, 0x00, 0x76]);
+    rom[0x10..0x13].copy_from_slice(&[0x00, 0x00, 0xc9]);
+    let memory = MemoryMap::builder()
+        .rom(0x0000, rom)
+        .ram(0x1000, 0x100)
+        .build()
+        .unwrap();
+    let mut interpreter = Interpreter::new(NoDevices, memory, &[0x0000]);
+    interpreter.state_mut().set_SP(0x1100);
+    interpreter
+}
+
+#[test]
+fn step_over_runs_the_call() {
+    let mut interpreter = calling_interpreter();
+    assert_eq!(
+        interpreter.debug_command(Command::StepOver),
+        Response::Resumed
+    );
+    assert_eq!(interpreter.run_until(1000), StopReason::Step);
+    assert_eq!(interpreter.state().get_PC(), 0x0003);
+    assert_eq!(interpreter.state().get_SP(), 0x1100);
+
+    // a instruction that is not a call is only stepped
+    assert_eq!(
+        interpreter.debug_command(Command::StepOver),
+        Response::Stopped(StopReason::Step)
+    );
+    assert_eq!(interpreter.state().get_PC(), 0x0004);
+}
+
+#[test]
+fn step_out_returns_from_the_subroutine() {
+    let mut interpreter = calling_interpreter();
+    interpreter.debug_command(Command::Step);
+    interpreter.debug_command(Command::Step);
+    assert_eq!(interpreter.state().get_PC(), 0x0011);
+
+    assert_eq!(
+        interpreter.debug_command(Command::StepOut),
+        Response::Resumed
+    );
+    assert_eq!(interpreter.run_until(1000), StopReason::Step);
+    assert_eq!(interpreter.state().get_PC(), 0x0003);
+}
+
+#[test]
+fn registers_and_memory_can_be_written() {
+    let mut interpreter = calling_interpreter();
+    interpreter.debug_command(Command::WriteRegister(Register::A, 0x42));
+    interpreter.debug_command(Command::WriteRegister(Register::PC, 0x0010));
+    let Response::Registers(state) = interpreter.debug_command(Command::ReadRegisters) else {
+        panic!("expected the registers");
+    };
+    assert_eq!(state.A, 0x42);
+    assert_eq!(state.get_PC(), 0x0010);
+
+    interpreter.debug_command(Command::WriteMemory {
+        adress: 0x1000,
+        data: vec![1, 2, 3],
+    });
+    assert_eq!(
+        interpreter.debug_command(Command::ReadMemory {
+            adress: 0x0fff,
+            len: 4
+        }),
+        Response::Memory(vec![0x00, 1, 2, 3])
+    );
+}
+
+/// `MVI A,42; STA 1005; LDA 1006; OUT 03; IN 01; JMP 000c`
+fn accessing_interpreter() -> Interpreter<MemoryMap, NoDevices> {
+    let rom = vec![
+        0x3e, 0x42, 0x32, 0x05, 0x10, 0x3a, 0x06, 0x10, 0xd3, 0x03, 0xdb, 0x01, 0xc3, 0x0c, 0x00,
+    ];
+    let memory = MemoryMap::builder()
+        .rom(0x0000, rom)
+        .ram(0x1000, 0x100)
+        .build()
+        .unwrap();
+    Interpreter::new(NoDevices, memory, &[0x0000])
+}
+
+#[test]
+fn write_watchpoint_stops_after_the_write() {
+    let mut interpreter = accessing_interpreter();
+    interpreter.debug_command(Command::AddWatchpoint(Watchpoint::memory(
+        0x1000..=0x10ff,
+        Access::Write,
+    )));
+    assert_eq!(
+        interpreter.run_until(1000),
+        StopReason::MemoryWatch {
+            adress: 0x1005,
+            value: 0x42,
+            write: true
+        }
+    );
+    assert_eq!(interpreter.state().get_PC(), 0x0005);
+
+    // the read of LDA don't trigger it
+    interpreter.debug_command(Command::Continue);
+    assert_eq!(interpreter.run_until(1000), StopReason::ClockReached);
+}
+
+#[test]
+fn watchpoint_value_filters_the_accesses() {
+    let mut interpreter = accessing_interpreter();
+    let watchpoint = Watchpoint::memory(0x1000..=0x10ff, Access::ReadWrite).with_value(0x00);
+    interpreter.debug_command(Command::AddWatchpoint(watchpoint.clone()));
+    assert_eq!(
+        interpreter.run_until(1000),
+        StopReason::MemoryWatch {
+            adress: 0x1006,
+            value: 0x00,
+            write: false
+        }
+    );
+
+    interpreter.debug_command(Command::RemoveWatchpoint(watchpoint));
+    assert!(interpreter.debugger().watchpoints().is_empty());
+}
+
+#[test]
+fn port_watchpoints_stop_at_in_and_out() {
+    let mut interpreter = accessing_interpreter();
+    interpreter.debug_command(Command::AddWatchpoint(Watchpoint::port(
+        0x03,
+        Access::Write,
+    )));
+    interpreter.debug_command(Command::AddWatchpoint(Watchpoint::port(0x01, Access::Read)));
+    assert_eq!(
+        interpreter.run_until(1000),
+        StopReason::PortWatch {
+            port: 0x03,
+            value: 0x00,
+            write: true
+        }
+    );
+    assert_eq!(
+        interpreter.debug_command(Command::Step),
+        Response::Stopped(StopReason::PortWatch {
+            port: 0x01,
+            value: 0x00,
+            write: false
+        })
+    );
+    assert_eq!(interpreter.state().get_PC(), 0x000c);
+}
+
+#[test]
+fn stack_accesses_are_watched() {
+    let mut interpreter = calling_interpreter();
+    interpreter.debug_command(Command::AddWatchpoint(Watchpoint::memory(
+        0x10fe..=0x10ff,
+        Access::Read,
+    )));
+    // the CALL writes the return adress, and the RET reads it
+    assert_eq!(
+        interpreter.run_until(1000),
+        StopReason::MemoryWatch {
+            adress: 0x10fe,
+            value: 0x03,
+            write: false
+        }
+    );
+    assert_eq!(interpreter.state().get_PC(), 0x0003);
+}
+
+/// `INR A; JMP 0000`, forever.
+fn counting_interpreter() -> Interpreter<MemoryMap, NoDevices> {
+    let memory = MemoryMap::builder()
+        .rom(0x0000, vec![0x3c, 0xc3, 0x00, 0x00])
+        .build()
+        .unwrap();
+    Interpreter::new(NoDevices, memory, &[0x0000])
+}
+
+#[test]
+fn conditional_breakpoint_stops_when_true() {
+    let mut interpreter = counting_interpreter();
+    let breakpoint = Breakpoint::default().with_condition(Expression::parse("A == 10").unwrap());
+    interpreter.debug_command(Command::SetBreakpoint(0x0001, breakpoint));
+    assert_eq!(
+        interpreter.run_until(10_000),
+        StopReason::Breakpoint(0x0001)
+    );
+    assert_eq!(interpreter.state().A, 0x10);
+    assert_eq!(interpreter.debugger().breakpoint(0x0001).unwrap().hits, 1);
+}
+
+#[test]
+fn breakpoint_ignores_its_first_hits() {
+    let mut interpreter = counting_interpreter();
+    interpreter.debug_command(Command::SetBreakpoint(
+        0x0001,
+        Breakpoint::default().with_ignore(3),
+    ));
+    assert_eq!(
+        interpreter.run_until(10_000),
+        StopReason::Breakpoint(0x0001)
+    );
+    assert_eq!(interpreter.state().A, 4);
+
+    interpreter.debug_command(Command::Continue);
+    assert_eq!(
+        interpreter.run_until(10_000),
+        StopReason::Breakpoint(0x0001)
+    );
+    assert_eq!(interpreter.state().A, 5);
+    assert_eq!(interpreter.debugger().breakpoint(0x0001).unwrap().hits, 5);
+}
+
+#[test]
+fn tracepoint_logs_without_stopping() {
+    let mut interpreter = counting_interpreter();
+    let tracepoint = Breakpoint::default()
+        .with_condition(Expression::parse("A < 3").unwrap())
+        .with_log(Message::parse("A is {A}").unwrap());
+    interpreter.debug_command(Command::SetBreakpoint(0x0001, tracepoint));
+    assert_eq!(interpreter.run_until(1000), StopReason::ClockReached);
+    let log: Vec<String> = interpreter.debugger_mut().drain_log().collect();
+    assert_eq!(log, ["A is 01", "A is 02"]);
+}
+
+#[test]
+fn watchpoint_condition_filters_the_accesses() {
+    let mut interpreter = accessing_interpreter();
+    let condition = Expression::parse("adress == 1006 && A == 0").unwrap();
+    interpreter.debug_command(Command::AddWatchpoint(
+        Watchpoint::memory(0x1000..=0x10ff, Access::ReadWrite).with_condition(condition),
+    ));
+    assert_eq!(
+        interpreter.run_until(1000),
+        StopReason::MemoryWatch {
+            adress: 0x1006,
+            value: 0x00,
+            write: false
+        }
+    );
+    assert_eq!(interpreter.state().get_PC(), 0x0008);
+}
@@ -0,0 +1,19 @@
+use intel8080::dissasembler::trace;
+
+#[test]
+fn trace_stops_at_the_end_of_the_rom() {
+    // only NOPs, from each entry
+    let rom = [0; 0x100];
+    assert_eq!(trace(&rom, &[0x00, 0x08, 0x10]), vec![0x00..0x100]);
+
+    // a JMP cut by the end of the rom
+    let rom = [0x00, 0x00, 0xc3, 0x00];
+    assert_eq!(trace(&rom, &[0x00]), vec![0x00..0x02]);
+}
+
+#[test]
+fn trace_follows_the_jumps() {
+    // JMP 0006; NOP; NOP; NOP; JMP 0000
+    let rom = [0xc3, 0x06, 0x00, 0x00, 0x00, 0x00, 0xc3, 0x00, 0x00];
+    assert_eq!(trace(&rom, &[0x00]), [0x00..0x03, 0x06..0x09]);
+}
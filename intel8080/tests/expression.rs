@@ -0,0 +1,85 @@
+#![cfg(feature = "debug")]
+
+use intel8080::{
+    I8080State, Memory,
+    expression::{Context, Expression, Message, ParseError},
+    memory_map::MemoryMap,
+};
+
+fn eval(source: &str, state: &I8080State, memory: &MemoryMap) -> i64 {
+    let context = Context {
+        state,
+        memory,
+        access: Some((0x2005, 0x17)),
+    };
+    Expression::parse(source).unwrap().eval(&context)
+}
+
+fn machine() -> (I8080State, MemoryMap) {
+    let mut state = I8080State::new();
+    state.A = 0x10;
+    state.H = 0x20;
+    state.L = 0xea;
+    // zero and carry
+    state.Flags = 0b0100_0011;
+    let mut memory = MemoryMap::builder().ram(0x2000, 0x100).build().unwrap();
+    memory.write(0x20ea, 0x34);
+    memory.write(0x20eb, 0x12);
+    (state, memory)
+}
+
+#[test]
+fn reads_registers_flags_and_memory() {
+    let (state, memory) = machine();
+    assert_eq!(eval("A == 0x10 && [20ea] != 0", &state, &memory), 1);
+    assert_eq!(eval("hl", &state, &memory), 0x20ea);
+    assert_eq!(eval("[HL + 1]", &state, &memory), 0x12);
+    assert_eq!(eval("w[20ea]", &state, &memory), 0x1234);
+    assert_eq!(eval("Z + CY + S", &state, &memory), 2);
+    assert_eq!(eval("psw", &state, &memory), 0x1043);
+    assert_eq!(eval("value == 17 && adress == 2005", &state, &memory), 1);
+    assert_eq!(eval("A == #16", &state, &memory), 1);
+}
+
+#[test]
+fn operators_follow_the_precedence() {
+    let (state, memory) = machine();
+    assert_eq!(eval("1 + 2 << 1", &state, &memory), 6);
+    assert_eq!(eval("A & 0f0 == 10", &state, &memory), 0);
+    assert_eq!(eval("(A & 0f0) == 10", &state, &memory), 1);
+    assert_eq!(eval("0 || 1 && 0", &state, &memory), 0);
+    assert_eq!(eval("!A || -1 < 0", &state, &memory), 1);
+    assert_eq!(eval("~0 & 0ff", &state, &memory), 0xff);
+}
+
+#[test]
+fn invalid_expressions_are_rejected() {
+    assert_eq!(Expression::parse("A =="), Err(ParseError::UnexpectedEnd));
+    assert_eq!(
+        Expression::parse("A == foo"),
+        Err(ParseError::UnknownName("foo".to_string()))
+    );
+    assert_eq!(Expression::parse("[20ea"), Err(ParseError::UnexpectedEnd));
+    assert_eq!(
+        Expression::parse("A = 1"),
+        Err(ParseError::Unexpected(2, '='))
+    );
+    assert_eq!(
+        Expression::parse("12g"),
+        Err(ParseError::InvalidNumber("12g".to_string()))
+    );
+}
+
+#[test]
+fn messages_format_the_expressions() {
+    let (state, memory) = machine();
+    let context = Context {
+        state: &state,
+        memory: &memory,
+        access: None,
+    };
+    let message = Message::parse("A = {A}, [HL] = {w[HL]}!").unwrap();
+    assert_eq!(message.format(&context), "A = 10, [HL] = 1234!");
+    assert_eq!(message.to_string(), "A = {A}, [HL] = {w[HL]}!");
+    assert_eq!(Message::parse("A = {A"), Err(ParseError::UnexpectedEnd));
+}
@@ -0,0 +1,201 @@
+#![cfg(feature = "debug")]
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use intel8080::{
+    NoDevices,
+    debugger::{Access, Watchpoint},
+    gdb::GdbStub,
+    interpreter::{Interpreter, StopReason},
+    memory_map::MemoryMap,
+};
+
+struct Session {
+    stub: GdbStub,
+    client: TcpStream,
+    interpreter: Interpreter<MemoryMap, NoDevices>,
+    /// The bytes received after the last reply.
+    received: Vec<u8>,
+}
+impl Session {
+    /// `NOP; NOP; JMP 0000` in ROM, and RAM at 1000.
+    fn attach() -> Self {
+        let memory = MemoryMap::builder()
+            .rom(0x0000, vec![0x00, 0x00, 0xc3, 0x00, 0x00])
+            .ram(0x1000, 0x100)
+            .build()
+            .unwrap();
+        let mut interpreter = Interpreter::new(NoDevices, memory, &[0x0000]);
+        let mut stub = GdbStub::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        while !stub.is_attached() {
+            stub.poll(&mut interpreter);
+        }
+        Self {
+            stub,
+            client,
+            interpreter,
+            received: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, body: &str) {
+        let checksum = body.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.client, "${}#{:02x}", body, checksum).unwrap();
+    }
+
+    /// Poll the stub until a packet is received, and return its body.
+    fn reply(&mut self) -> String {
+        for _ in 0..100 {
+            let text = String::from_utf8_lossy(&self.received).into_owned();
+            let acks = text.len() - text.trim_start_matches('+').len();
+            let text = &text[acks..];
+            if let Some(end) = text.find('#')
+                && text.len() >= end + 3
+            {
+                self.received.drain(..acks + end + 3);
+                return text[1..end].to_string();
+            }
+            self.stub.poll(&mut self.interpreter);
+            let mut buffer = [0; 1024];
+            if let Ok(n) = self.client.read(&mut buffer) {
+                self.received.extend_from_slice(&buffer[..n]);
+            }
+        }
+        panic!(
+            "no reply, received {:?}",
+            String::from_utf8_lossy(&self.received)
+        );
+    }
+
+    fn command(&mut self, body: &str) -> String {
+        self.send(body);
+        self.reply()
+    }
+}
+
+#[test]
+fn attaching_stops_the_cpu() {
+    let mut session = Session::attach();
+    assert!(session.interpreter.is_stopped());
+    assert_eq!(session.command("?"), "S05");
+}
+
+#[test]
+fn registers_are_read_and_written() {
+    let mut session = Session::attach();
+    session.interpreter.state_mut().set_SP(0x1080);
+    // AF, BC, DE, HL, SP and PC, little endian
+    assert_eq!(session.command("g"), "020000000000000080100000");
+
+    assert_eq!(session.command("P1=3412"), "OK");
+    assert_eq!(session.interpreter.state().get_BC(), 0x1234);
+    assert_eq!(session.command("p1"), "3412");
+    assert_eq!(session.command("p9"), "E01");
+}
+
+#[test]
+fn memory_is_read_and_written() {
+    let mut session = Session::attach();
+    assert_eq!(session.command("m0,5"), "0000c30000");
+    assert_eq!(session.command("M1000,3:aabbcc"), "OK");
+    assert_eq!(session.command("m1000,3"), "aabbcc");
+}
+
+#[test]
+fn step_and_continue_to_a_breakpoint() {
+    let mut session = Session::attach();
+    assert_eq!(session.command("s"), "S05");
+    assert_eq!(session.interpreter.state().get_PC(), 0x0001);
+
+    assert_eq!(session.command("Z0,2,1"), "OK");
+    session.send("c");
+    for _ in 0..100 {
+        session.stub.poll(&mut session.interpreter);
+        if !session.interpreter.is_stopped() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(!session.interpreter.is_stopped());
+    session.interpreter.run_until(1000);
+    assert_eq!(session.reply(), "S05");
+    assert_eq!(session.interpreter.state().get_PC(), 0x0002);
+
+    assert_eq!(session.command("z0,2,1"), "OK");
+    assert!(!session.interpreter.debugger().has_breakpoint(0x0002));
+}
+
+#[test]
+fn no_target_description_is_sent() {
+    let mut session = Session::attach();
+    assert!(
+        !session
+            .command("qSupported:multiprocess+")
+            .contains("qXfer:features:read+")
+    );
+    assert_eq!(session.command("qXfer:features:read:target.xml:0,fff"), "");
+}
+
+#[test]
+fn watchpoints_are_memory_watchpoints() {
+    let mut session = Session::attach();
+    assert_eq!(session.command("Z2,1000,4"), "OK");
+    assert_eq!(
+        session.interpreter.debugger().watchpoints(),
+        [Watchpoint::memory(0x1000..=0x1003, Access::Write)]
+    );
+    assert_eq!(session.command("z2,1000,4"), "OK");
+    assert!(session.interpreter.debugger().watchpoints().is_empty());
+}
+
+#[test]
+fn stop_on_a_watchpoint_names_its_adress() {
+    let mut session = Session::attach();
+    // STA 1000; LDA 1000; JMP 1010
+    assert_eq!(session.command("M1010,9:3200103a0010c31010"), "OK");
+
+    assert_eq!(session.command("Z2,1000,1"), "OK");
+    assert_eq!(session.command("s1010"), "T05watch:1000;");
+    assert_eq!(session.command("z2,1000,1"), "OK");
+
+    assert_eq!(session.command("Z4,1000,1"), "OK");
+    assert_eq!(session.command("s"), "T05awatch:1000;");
+    assert_eq!(session.command("z4,1000,1"), "OK");
+
+    // the continue is answered when the cpu stops, on the next read
+    assert_eq!(session.command("Z3,1000,1"), "OK");
+    session.send("c");
+    while session.interpreter.is_stopped() {
+        session.stub.poll(&mut session.interpreter);
+    }
+    session.interpreter.run_until(1000);
+    assert_eq!(session.reply(), "T05rwatch:1000;");
+}
+
+#[test]
+fn watchdog_reset_is_written_to_the_console() {
+    let mut session = Session::attach();
+    session.send("c");
+    while session.interpreter.is_stopped() {
+        session.stub.poll(&mut session.interpreter);
+    }
+    session.interpreter.run_until(1000);
+    session.interpreter.stop_debugger(StopReason::Watchdog);
+
+    let console = session.reply();
+    let text = console.strip_prefix('O').unwrap();
+    let text: Vec<u8> = (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+        .collect();
+    assert_eq!(text, b"reset by the watchdog\n");
+    assert_eq!(session.reply(), "S05");
+}
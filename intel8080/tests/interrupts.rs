@@ -0,0 +1,127 @@
+use intel8080::{Memory, NoDevices, interpreter::Interpreter, memory_map::MemoryMap};
+
+const RST_1: u8 = 0xcf;
+const RST_2: u8 = 0xd7;
+
+const NOP: u8 = 0x00;
+const EI: u8 = 0xfb;
+const DI: u8 = 0xf3;
+const HLT: u8 = 0x76;
+const INR_A: u8 = 0x3c;
+
+/// 'program' at 0000, with the stack in the RAM at 2000.
+fn interpreter(program: &[u8]) -> Interpreter<MemoryMap, NoDevices> {
+    let mut rom = vec![NOP; 0x100];
+    rom[..program.len()].copy_from_slice(program);
+    let memory = MemoryMap::builder()
+        .rom(0x0000, rom)
+        .ram(0x2000, 0x100)
+        .build()
+        .unwrap();
+    let mut interpreter = Interpreter::new(NoDevices, memory, &[0x0000]);
+    interpreter.state_mut().set_SP(0x2100);
+    interpreter
+}
+
+/// The adress pushed by the last interrupt.
+fn return_adress(interpreter: &Interpreter<MemoryMap, NoDevices>) -> u16 {
+    interpreter.memory.read_u16(interpreter.state().get_SP())
+}
+
+#[test]
+fn interrupt_is_latched_until_enabled() {
+    let mut interpreter = interpreter(&[NOP, NOP, NOP, EI]);
+    assert!(!interpreter.state().interrupt_enabled);
+    interpreter.interrupt(RST_1);
+    for pc in 1..=3 {
+        assert!(interpreter.step());
+        assert_eq!(interpreter.state().get_PC(), pc);
+    }
+
+    interpreter.step(); // EI
+    interpreter.step(); // NOP
+    interpreter.step();
+    assert_eq!(interpreter.state().get_PC(), 0x0008);
+    assert_eq!(return_adress(&interpreter), 0x0005);
+    assert!(!interpreter.state().interrupt_enabled);
+
+    // the request was consumed
+    interpreter.state_mut().interrupt_enabled = true;
+    interpreter.step();
+    assert_eq!(interpreter.state().get_PC(), 0x0009);
+}
+
+#[test]
+fn ei_delays_the_interrupt_by_one_instruction() {
+    let mut interpreter = interpreter(&[EI, INR_A, INR_A]);
+    interpreter.interrupt(RST_1);
+    interpreter.step(); // EI
+    assert!(interpreter.state().interrupt_enabled);
+
+    // the instruction after EI runs before the interrupt
+    interpreter.step();
+    assert_eq!(interpreter.state().A, 1);
+    assert_eq!(interpreter.state().get_PC(), 0x0002);
+
+    interpreter.step();
+    assert_eq!(interpreter.state().A, 1);
+    assert_eq!(interpreter.state().get_PC(), 0x0008);
+    assert_eq!(return_adress(&interpreter), 0x0002);
+}
+
+#[test]
+fn new_request_replaces_the_pending_one() {
+    let mut interpreter = interpreter(&[EI, NOP]);
+    interpreter.interrupt(RST_1);
+    interpreter.interrupt(RST_2);
+    interpreter.step();
+    interpreter.step();
+    interpreter.step();
+    assert_eq!(interpreter.state().get_PC(), 0x0010);
+}
+
+#[test]
+fn di_masks_the_interrupts() {
+    let mut interpreter = interpreter(&[EI, NOP, DI]);
+    interpreter.step(); // EI
+    interpreter.step(); // NOP
+    interpreter.step(); // DI
+    assert!(!interpreter.state().interrupt_enabled);
+
+    interpreter.interrupt(RST_1);
+    for pc in 4..=6 {
+        interpreter.step();
+        assert_eq!(interpreter.state().get_PC(), pc);
+    }
+}
+
+#[test]
+fn interrupt_wakes_the_cpu_from_hlt() {
+    let mut interpreter = interpreter(&[EI, HLT]);
+    interpreter.step(); // EI
+    interpreter.step(); // HLT
+    assert!(interpreter.state().halt);
+
+    // halted, the time passes without executing anything
+    assert!(!interpreter.step());
+    interpreter.run(1000);
+    assert_eq!(interpreter.clock_count(), 1000);
+    assert_eq!(interpreter.state().get_PC(), 0x0002);
+
+    interpreter.interrupt(RST_2);
+    assert!(interpreter.step());
+    assert!(!interpreter.state().halt);
+    assert_eq!(interpreter.state().get_PC(), 0x0010);
+    assert_eq!(return_adress(&interpreter), 0x0002);
+}
+
+#[test]
+fn masked_interrupt_does_not_wake_the_cpu() {
+    let mut interpreter = interpreter(&[DI, HLT]);
+    interpreter.step(); // DI
+    interpreter.step(); // HLT
+    interpreter.interrupt(RST_1);
+    assert!(!interpreter.step());
+    assert!(interpreter.state().halt);
+    assert_eq!(interpreter.state().get_PC(), 0x0002);
+}
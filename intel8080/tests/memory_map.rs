@@ -0,0 +1,144 @@
+use std::sync::{Arc, Mutex};
+
+use intel8080::{
+    Memory,
+    memory_map::{FaultKind, MAX_REGIONS, MemoryFault, MemoryMap, MemoryMapError, Mmio},
+    save_state::SaveStateError,
+};
+
+fn space_invaders_map() -> MemoryMap {
+    MemoryMap::builder()
+        .rom(0x0000, vec![0xaa; 0x2000])
+        .ram(0x2000, 0x2000)
+        .mirror(0x4000, 0xc000, 0x2000, 0x2000)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn mirror_repeats_the_ram() {
+    let mut memory = space_invaders_map();
+    memory.write(0x2123, 0x42);
+    assert_eq!(memory.read(0x4123), 0x42);
+    assert_eq!(memory.read(0xe123), 0x42);
+    memory.write(0x6001, 0x17);
+    assert_eq!(memory.read(0x2001), 0x17);
+    assert_eq!(memory.fault_count(), 0);
+}
+
+#[test]
+fn rom_writes_are_rejected() {
+    let mut memory = space_invaders_map();
+    memory.write(0x0010, 0x00);
+    assert_eq!(memory.read(0x0010), 0xaa);
+    assert_eq!(
+        memory.last_fault(),
+        Some(MemoryFault {
+            kind: FaultKind::RomWrite,
+            adress: 0x0010
+        })
+    );
+    assert_eq!(memory.fault_count(), 1);
+}
+
+#[test]
+fn unmapped_adresses_read_the_open_bus() {
+    let mut memory = MemoryMap::builder()
+        .ram(0x0000, 0x100)
+        .open_bus(0xff)
+        .build()
+        .unwrap();
+    assert_eq!(memory.read(0x1000), 0xff);
+    memory.write(0x1000, 0x00);
+    assert_eq!(
+        memory.last_fault(),
+        Some(MemoryFault {
+            kind: FaultKind::UnmappedWrite,
+            adress: 0x1000
+        })
+    );
+    assert_eq!(memory.read(0x1000), 0xff);
+    assert_eq!(memory.fault_count(), 3);
+}
+
+struct Latch(Arc<Mutex<Vec<(u16, u8)>>>);
+impl Mmio for Latch {
+    fn read(&self, offset: u16) -> u8 {
+        offset as u8 ^ 0xff
+    }
+
+    fn write(&mut self, offset: u16, value: u8) {
+        self.0.lock().unwrap().push((offset, value));
+    }
+}
+
+#[test]
+fn mmio_receives_the_offsets() {
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    let mut memory = MemoryMap::builder()
+        .ram(0x0000, 0x1000)
+        .mmio(0x8000, 0x10, Latch(writes.clone()))
+        .build()
+        .unwrap();
+    assert_eq!(memory.read(0x8003), 0xfc);
+    memory.write(0x800f, 0x12);
+    assert_eq!(*writes.lock().unwrap(), [(0x0f, 0x12)]);
+}
+
+#[test]
+fn invalid_maps_are_rejected() {
+    let overlap = MemoryMap::builder()
+        .ram(0x0000, 0x2000)
+        .ram(0x1000, 0x2000)
+        .build();
+    assert!(matches!(
+        overlap,
+        Err(MemoryMapError::Overlap { adress: 0x1000 })
+    ));
+
+    let out_of_range = MemoryMap::builder().ram(0xf000, 0x2000).build();
+    assert!(matches!(
+        out_of_range,
+        Err(MemoryMapError::OutOfRange { .. })
+    ));
+
+    let bad_mirror = MemoryMap::builder()
+        .ram(0x0000, 0x1000)
+        .mirror(0x4000, 0x1000, 0x2000, 0x1000)
+        .build();
+    assert!(matches!(
+        bad_mirror,
+        Err(MemoryMapError::BadMirrorTarget { target: 0x2000 })
+    ));
+
+    let too_many = (0..=MAX_REGIONS as u16)
+        .fold(MemoryMap::builder(), |builder, i| builder.ram(i, 1))
+        .build();
+    assert!(matches!(
+        too_many,
+        Err(MemoryMapError::TooManyRegions { count }) if count == MAX_REGIONS + 1
+    ));
+    let most = (0..MAX_REGIONS as u16).fold(MemoryMap::builder(), |builder, i| builder.ram(i, 1));
+    assert!(most.build().is_ok());
+}
+
+#[test]
+fn save_state_keeps_only_the_ram() {
+    let mut memory = space_invaders_map();
+    memory.write(0x2000, 1);
+    memory.write(0x3fff, 2);
+    let mut state = Vec::new();
+    memory.save_state(&mut state);
+    assert_eq!(state.len(), 0x2000);
+
+    let mut loaded = space_invaders_map();
+    loaded.load_state(&state).unwrap();
+    assert_eq!(loaded.read(0x2000), 1);
+    assert_eq!(loaded.read(0x3fff), 2);
+    assert_eq!(loaded.get_rom(), vec![0xaa; 0x2000]);
+
+    assert!(matches!(
+        loaded.load_state(&state[1..]),
+        Err(SaveStateError::BadSectionLength { .. })
+    ));
+}
@@ -0,0 +1,115 @@
+use intel8080::{
+    IODevices,
+    interpreter::Interpreter,
+    memory_map::MemoryMap,
+    save_state::{self, SaveStateError},
+};
+
+/// A device that latches the last value written to any port, and saves it as a `u16`.
+struct Latch(u16);
+impl IODevices for Latch {
+    fn read(&mut self, _device: u8) -> u8 {
+        self.0 as u8
+    }
+    fn write(&mut self, _device: u8, value: u8) {
+        self.0 = value as u16;
+    }
+    fn save_state(&self, out: &mut Vec<u8>) {
+        save_state::write_u16(out, self.0);
+    }
+    fn load_state(&mut self, mut section: &[u8]) -> Result<(), SaveStateError> {
+        self.0 = save_state::read_u16(&mut section)?;
+        Ok(())
+    }
+}
+
+/// `INR A; STA 2000; OUT 00; JMP 0000`, forever.
+fn counting_interpreter() -> Interpreter<MemoryMap, Latch> {
+    let memory = MemoryMap::builder()
+        .rom(
+            0x0000,
+            vec![0x3c, 0x32, 0x00, 0x20, 0xd3, 0x00, 0xc3, 0x00, 0x00],
+        )
+        .ram(0x2000, 0x100)
+        .build()
+        .unwrap();
+    Interpreter::new(Latch(0), memory, &[0x0000])
+}
+
+#[test]
+fn save_then_load_round_trip() {
+    let mut interpreter = counting_interpreter();
+    interpreter.run(1000);
+    let saved = interpreter.save_state();
+    let (a, latch) = (interpreter.state().A, interpreter.devices.0);
+
+    interpreter.run(1000);
+    assert_ne!(interpreter.save_state(), saved);
+
+    interpreter.load_state(&saved).unwrap();
+    assert_eq!(interpreter.save_state(), saved);
+    assert_eq!(interpreter.state().A, a);
+    assert_eq!(interpreter.devices.0, latch);
+
+    // the loaded machine runs like the saved one
+    let mut other = counting_interpreter();
+    other.load_state(&saved).unwrap();
+    interpreter.run(500);
+    other.run(500);
+    assert_eq!(other.save_state(), interpreter.save_state());
+}
+
+#[test]
+fn truncated_file_leaves_the_machine_untouched() {
+    let mut interpreter = counting_interpreter();
+    interpreter.run(1000);
+    let saved = interpreter.save_state();
+    interpreter.run(1000);
+    let current = interpreter.save_state();
+
+    for len in 0..saved.len() {
+        assert!(
+            interpreter.load_state(&saved[..len]).is_err(),
+            "len {}",
+            len
+        );
+        assert_eq!(interpreter.save_state(), current, "len {}", len);
+    }
+}
+
+#[test]
+fn bad_devices_section_leaves_the_memory_untouched() {
+    let mut interpreter = counting_interpreter();
+    interpreter.run(1000);
+    let mut saved = interpreter.save_state();
+    interpreter.run(1000);
+    let current = interpreter.save_state();
+
+    // the "IO  " section is the last one: cut its payload to a single byte, after the
+    // memory section was already accepted
+    let io = saved.len() - (4 + 4 + 2);
+    saved.truncate(io + 4);
+    save_state::write_u32(&mut saved, 1);
+    save_state::write_u8(&mut saved, 0xff);
+
+    assert!(matches!(
+        interpreter.load_state(&saved),
+        Err(SaveStateError::UnexpectedEof)
+    ));
+    assert_eq!(interpreter.save_state(), current);
+}
+
+#[test]
+fn other_file_is_rejected() {
+    let mut interpreter = counting_interpreter();
+    let mut saved = interpreter.save_state();
+    assert!(matches!(
+        interpreter.load_state(b"not a save state"),
+        Err(SaveStateError::BadMagic)
+    ));
+    saved[8] = saved[8].wrapping_add(1);
+    assert!(matches!(
+        interpreter.load_state(&saved),
+        Err(SaveStateError::UnsupportedVersion(_))
+    ));
+}
@@ -0,0 +1,113 @@
+use intel8080::{NoDevices, interpreter::Interpreter, memory_map::MemoryMap, scheduler::Scheduler};
+
+/// `JMP 0000`, forever: a instruction of 10 clocks.
+fn jumping_interpreter() -> Interpreter<MemoryMap, NoDevices> {
+    let memory = MemoryMap::builder()
+        .rom(0x0000, vec![0xc3, 0x00, 0x00])
+        .build()
+        .unwrap();
+    Interpreter::new(NoDevices, memory, &[0x0000])
+}
+
+#[test]
+fn events_fire_in_clock_order() {
+    let mut interpreter = jumping_interpreter();
+    let mut scheduler = Scheduler::new(1000);
+    scheduler.add_event(700, 'c');
+    scheduler.add_event(100, 'a');
+    scheduler.add_event(400, 'b');
+    // events at the same clock keep the order they were added
+    scheduler.add_event(400, 'B');
+    scheduler.add_event(1000, 'd');
+
+    let mut events = Vec::new();
+    assert!(
+        scheduler.run_frame(&mut interpreter, |interpreter, &event| {
+            events.push((event, interpreter.clock_count()));
+        })
+    );
+    let order: Vec<char> = events.iter().map(|&(event, _)| event).collect();
+    assert_eq!(order, ['a', 'b', 'B', 'c', 'd']);
+    for (event, clock) in events {
+        let expected = match event {
+            'a' => 100,
+            'b' | 'B' => 400,
+            'c' => 700,
+            _ => 1000,
+        };
+        // a event fires after the instruction that reaches its clock
+        assert!((expected..expected + 10).contains(&clock), "{}", event);
+    }
+}
+
+#[test]
+#[should_panic]
+fn event_out_of_the_frame_is_rejected() {
+    Scheduler::new(1000).add_event(1001, ());
+}
+
+#[test]
+fn overshoot_is_carried_to_the_next_frame() {
+    let mut interpreter = jumping_interpreter();
+    // not a multiple of the 10 clocks of the instruction, so each frame overshoots
+    let mut scheduler = Scheduler::new(1005);
+    scheduler.add_event(500, ());
+
+    for frame in 0..100 {
+        let frame_start = frame * 1005;
+        let mut fired = None;
+        assert!(scheduler.run_frame(&mut interpreter, |interpreter, _| {
+            fired = Some(interpreter.clock_count())
+        }));
+        // the frames start at multiples of the frame length, so no time is gained or
+        // lost, however many frames run
+        let fired = fired.unwrap() - frame_start;
+        assert!((500..510).contains(&fired), "frame {}: {}", frame, fired);
+        let end = frame_start + 1005;
+        assert_eq!(interpreter.target_clock(), end);
+        assert!((end..end + 10).contains(&interpreter.clock_count()));
+    }
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn resume_continues_the_stopped_frame() {
+    use intel8080::{debugger::Command, interpreter::StopReason};
+
+    let mut interpreter = jumping_interpreter();
+    let mut scheduler = Scheduler::new(1000);
+    scheduler.add_event(200, 1);
+    scheduler.add_event(600, 2);
+
+    let mut events = Vec::new();
+    assert!(scheduler.run_frame(&mut interpreter, |_, &event| events.push(event)));
+    assert_eq!(events, [1, 2]);
+
+    // stop the second frame between the two events
+    events.clear();
+    let completed = scheduler.run_frame(&mut interpreter, |interpreter, &event| {
+        events.push(event);
+        interpreter.debug_command(Command::Pause);
+    });
+    assert!(!completed);
+    assert!(scheduler.is_mid_frame());
+    assert_eq!(events, [1]);
+    assert_eq!(
+        interpreter.debugger().stop_reason(),
+        Some(StopReason::Paused)
+    );
+
+    // the next run don't repeat the first event, and keeps the clocks of the frame
+    interpreter.debug_command(Command::Continue);
+    let mut fired = Vec::new();
+    assert!(
+        scheduler.run_frame(&mut interpreter, |interpreter, &event| {
+            fired.push((event, interpreter.clock_count()));
+        })
+    );
+    assert!(!scheduler.is_mid_frame());
+    assert_eq!(fired.len(), 1);
+    assert_eq!(fired[0].0, 2);
+    assert!((1600..1610).contains(&fired[0].1));
+    assert_eq!(interpreter.target_clock(), 2000);
+}
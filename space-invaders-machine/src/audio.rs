@@ -0,0 +1,240 @@
+//! The sound of the machine, mixed in lockstep with the emulation.
+//!
+//! Each write to the sound ports is timestamped with the clock count, and the samples are
+//! generated up to the end of each frame, so the sounds keep in sync with the gameplay
+//! independent of the speed of the emulation.
+
+use std::{collections::VecDeque, fs, io, path::Path};
+
+use crate::{CPU_CLOCK, devices::AMPLIFIER_ENABLE, synth::Synth};
+
+pub const SAMPLE_RATE: u32 = 44_100;
+/// The maximum number of samples not yet taken by the frontend. After that the oldest
+/// ones are dropped.
+const BUFFER_LEN: usize = SAMPLE_RATE as usize / 2;
+
+/// Generates the sound from the level of the sound ports.
+pub trait SoundSource: Send {
+    fn set_ports(&mut self, port3: u8, port5: u8);
+
+    /// The next sample, at `SAMPLE_RATE`, in the range -1.0 to 1.0.
+    fn next_sample(&mut self) -> f32;
+}
+
+impl SoundSource for Synth {
+    fn set_ports(&mut self, port3: u8, port5: u8) {
+        Synth::set_ports(self, port3, port5)
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        Synth::next_sample(self)
+    }
+}
+
+/// Convert 16-bit PCM samples to `SAMPLE_RATE`, by linear interpolation.
+pub fn resample(pcm: &[i16], sample_rate: u32) -> Vec<f32> {
+    if pcm.is_empty() {
+        return Vec::new();
+    }
+    let len = pcm.len() as u64 * SAMPLE_RATE as u64 / sample_rate as u64;
+    let step = sample_rate as f64 / SAMPLE_RATE as f64;
+    (0..len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let j = pos as usize;
+            let a = pcm[j] as f32;
+            let b = pcm.get(j + 1).map_or(a, |&x| x as f32);
+            (a + (b - a) * pos.fract() as f32) / 32768.0
+        })
+        .collect()
+}
+
+/// Plays the recorded samples on the rising edge of the sound bits, like the original
+/// frontend, and loops the ufo sample while its bit is high.
+pub struct SamplePlayer {
+    /// The nine samples, at `SAMPLE_RATE`. The first one is the ufo loop.
+    samples: Vec<Vec<f32>>,
+    port3: u8,
+    port5: u8,
+    /// The playing samples, as (index, position).
+    voices: Vec<(usize, usize)>,
+    ufo: Option<usize>,
+}
+impl SamplePlayer {
+    pub fn new(samples: Vec<Vec<f32>>) -> Self {
+        assert_eq!(samples.len(), 9, "expected 9 samples");
+        Self {
+            samples,
+            port3: 0,
+            port5: 0,
+            voices: Vec::new(),
+            ufo: None,
+        }
+    }
+}
+impl SoundSource for SamplePlayer {
+    fn set_ports(&mut self, port3: u8, port5: u8) {
+        let rising3 = port3 & !self.port3;
+        let rising5 = port5 & !self.port5;
+        self.port3 = port3;
+        self.port5 = port5;
+
+        if port3 & 0b1 == 0 {
+            self.ufo = None;
+        } else if self.ufo.is_none() {
+            self.ufo = Some(0);
+        }
+        for i in 1..4 {
+            if rising3 & (1 << i) != 0 {
+                self.voices.push((i, 0));
+            }
+        }
+        for i in 0..5 {
+            if rising5 & (1 << i) != 0 {
+                self.voices.push((4 + i, 0));
+            }
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let mut out = 0.0;
+        if let Some(pos) = &mut self.ufo {
+            let ufo = &self.samples[0];
+            if !ufo.is_empty() {
+                out += ufo[*pos % ufo.len()];
+                *pos = (*pos + 1) % ufo.len();
+            }
+        }
+        let samples = &self.samples;
+        self.voices
+            .retain_mut(|(index, pos)| match samples[*index].get(*pos) {
+                Some(x) => {
+                    out += x;
+                    *pos += 1;
+                    true
+                }
+                None => false,
+            });
+        out.clamp(-1.0, 1.0)
+    }
+}
+
+pub struct Mixer {
+    source: Box<dyn SoundSource>,
+    /// The clock of the first sample, and the number of samples generated since it.
+    base_clock: u64,
+    sample_count: u64,
+    buffer: VecDeque<f32>,
+    capture: Option<Vec<f32>>,
+    /// If the amplifier is enabled. When not, the sounds still run, but are not heard.
+    amplifier: bool,
+}
+impl Mixer {
+    pub fn new(source: Box<dyn SoundSource>, clock: u64) -> Self {
+        Self {
+            source,
+            base_clock: clock,
+            sample_count: 0,
+            buffer: VecDeque::with_capacity(BUFFER_LEN),
+            capture: None,
+            amplifier: false,
+        }
+    }
+
+    /// The clock count of the next sample.
+    fn next_sample_clock(&self) -> u64 {
+        self.base_clock + self.sample_count * CPU_CLOCK / SAMPLE_RATE as u64
+    }
+
+    /// The clock of the first sample and the number of samples since it, that align the
+    /// samples to the clock.
+    pub(crate) fn clock_base(&self) -> (u64, u64) {
+        (self.base_clock, self.sample_count)
+    }
+
+    /// Restore the alignment of the samples, after loading a save state.
+    pub(crate) fn set_clock_base(&mut self, (base_clock, sample_count): (u64, u64)) {
+        self.base_clock = base_clock;
+        self.sample_count = sample_count;
+    }
+
+    /// Generate the samples until the given clock, applying each port write at the sample
+    /// of its timestamp.
+    pub fn mix_until(&mut self, clock: u64, writes: impl IntoIterator<Item = (u64, u8, u8)>) {
+        // the clock jumped, after loading a save state
+        let next = self.next_sample_clock();
+        if clock < next || clock - next > CPU_CLOCK {
+            self.base_clock = clock;
+            self.sample_count = 0;
+        }
+
+        let mut writes = writes.into_iter().peekable();
+        loop {
+            let sample_clock = self.next_sample_clock();
+            if sample_clock >= clock {
+                break;
+            }
+            while let Some((_, port3, port5)) = writes.next_if(|w| w.0 <= sample_clock) {
+                self.set_ports(port3, port5);
+            }
+            let sample = self.source.next_sample();
+            self.push(if self.amplifier { sample } else { 0.0 });
+            self.sample_count += 1;
+        }
+        for (_, port3, port5) in writes {
+            self.set_ports(port3, port5);
+        }
+    }
+
+    fn set_ports(&mut self, port3: u8, port5: u8) {
+        self.amplifier = port3 & AMPLIFIER_ENABLE != 0;
+        self.source.set_ports(port3, port5);
+    }
+
+    fn push(&mut self, sample: f32) {
+        if self.buffer.len() == BUFFER_LEN {
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back(sample);
+        if let Some(capture) = &mut self.capture {
+            capture.push(sample);
+        }
+    }
+
+    /// Take the samples generated since the last call.
+    pub fn drain(&mut self) -> std::collections::vec_deque::Drain<'_, f32> {
+        self.buffer.drain(..)
+    }
+
+    /// Start keeping all the generated samples, to be written to a WAV file.
+    pub fn start_capture(&mut self) {
+        self.capture = Some(Vec::new());
+    }
+
+    pub fn stop_capture(&mut self) -> Option<Vec<f32>> {
+        self.capture.take()
+    }
+}
+
+/// Write the samples as a mono 16-bit PCM WAV file.
+pub fn write_wav<P: AsRef<Path>>(path: P, samples: &[f32]) -> io::Result<()> {
+    let data_len = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // byte rate
+    out.extend_from_slice(&2u16.to_le_bytes()); // block align
+    out.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for &sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+    fs::write(path, out)
+}
@@ -0,0 +1,249 @@
+use intel8080::{
+    IODevices,
+    save_state::{self, SaveStateError},
+};
+
+use crate::{
+    machines::{MIDWAY_PORTS, PortMap},
+    shifter::Mb14241,
+};
+
+/// The bit of port 3 that enables the sound amplifier. The ROM clears it in the attract
+/// mode, muting all the sounds.
+pub(crate) const AMPLIFIER_ENABLE: u8 = 0b0010_0000;
+
+/// If the ufo sound is audible, with the given port 3 latch.
+fn ufo_playing(port3: u8) -> bool {
+    port3 & (AMPLIFIER_ENABLE | 0b1) == AMPLIFIER_ENABLE | 0b1
+}
+
+/// The most sound events kept until they are drained. A frontend that plays the sound
+/// from the mixer never drains them.
+pub const MAX_SOUND_EVENTS: usize = 64;
+
+/// A change in the sound circuits, that the frontend must reproduce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundEvent {
+    /// Play once the sample of the given index (1 to 8).
+    Play(u8),
+    /// Start looping the ufo sound (sample 0).
+    StartUfo,
+    StopUfo,
+}
+
+pub struct SpaceInvadersDevices {
+    port_map: PortMap,
+    shifter: Mb14241,
+    pub(crate) read_ports: [u8; 3],
+
+    wport3: u8,
+    wport5: u8,
+    sound_events: Vec<SoundEvent>,
+
+    /// The clock count of the current instruction.
+    clock: u64,
+    /// The writes to the sound ports, as (clock, port 3, port 5).
+    sound_writes: Vec<(u64, u8, u8)>,
+    /// If port 6 was written since the last frame.
+    watchdog_kicked: bool,
+}
+impl SpaceInvadersDevices {
+    pub fn new(ports: [u8; 3]) -> Self {
+        Self::with_port_map(MIDWAY_PORTS, ports)
+    }
+
+    /// The devices of a board with other port assignments.
+    pub fn with_port_map(port_map: PortMap, ports: [u8; 3]) -> Self {
+        Self {
+            port_map,
+            shifter: Mb14241::new(),
+            read_ports: ports,
+
+            wport3: 0,
+            wport5: 0,
+            sound_events: Vec::new(),
+
+            clock: 0,
+            // the latches are cleared at power on
+            sound_writes: vec![(0, 0, 0)],
+            watchdog_kicked: false,
+        }
+    }
+
+    /// Take the sound events emitted since the last call. If more than
+    /// `MAX_SOUND_EVENTS` were emitted, the older ones are lost.
+    pub fn drain_sound_events(&mut self) -> std::vec::Drain<'_, SoundEvent> {
+        self.sound_events.drain(..)
+    }
+
+    pub fn shifter(&self) -> &Mb14241 {
+        &self.shifter
+    }
+
+    /// The last values written to the sound ports 3 and 5.
+    pub fn sound_ports(&self) -> (u8, u8) {
+        (self.wport3, self.wport5)
+    }
+
+    /// Take the writes to the sound ports since the last call, timestamped with the
+    /// clock count.
+    pub fn drain_sound_writes(&mut self) -> std::vec::Drain<'_, (u64, u8, u8)> {
+        self.sound_writes.drain(..)
+    }
+
+    /// If the watchdog was kicked since the last call.
+    pub(crate) fn take_watchdog_kick(&mut self) -> bool {
+        std::mem::take(&mut self.watchdog_kicked)
+    }
+
+    fn push_sound_event(&mut self, event: SoundEvent) {
+        if self.sound_events.len() >= MAX_SOUND_EVENTS {
+            // the old events are stale, only the state of the ufo loop still matters
+            self.sound_events.clear();
+            self.sound_events.push(if ufo_playing(self.wport3) {
+                SoundEvent::StartUfo
+            } else {
+                SoundEvent::StopUfo
+            });
+        }
+        self.sound_events.push(event);
+    }
+
+    fn start_ufo(&mut self) {
+        self.push_sound_event(SoundEvent::StartUfo);
+    }
+    pub(crate) fn stop_ufo(&mut self) {
+        self.push_sound_event(SoundEvent::StopUfo);
+    }
+
+    fn play_sound(&mut self, index: u8) {
+        self.push_sound_event(SoundEvent::Play(index));
+    }
+}
+impl IODevices for SpaceInvadersDevices {
+    fn read(&mut self, device: u8) -> u8 {
+        let map = &self.port_map;
+        if let Some(i) = map.inputs.iter().position(|&port| port == device) {
+            self.read_ports[i]
+        } else if device == map.shift_result {
+            self.shifter.result()
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, device: u8, value: u8) {
+        let map = self.port_map;
+        if device == map.shift_amount {
+            self.shifter.set_amount(value);
+        } else if map.sound.is_some_and(|sound| device == sound[0]) {
+            let check_bit = |byte: u8, i: u8| byte & (0b1 << i) != 0;
+
+            if ufo_playing(value) && !ufo_playing(self.wport3) {
+                self.start_ufo();
+            } else if !ufo_playing(value) && ufo_playing(self.wport3) {
+                self.stop_ufo();
+            }
+
+            if value & AMPLIFIER_ENABLE != 0 {
+                for i in 1..=3 {
+                    if check_bit(value, i) && !check_bit(self.wport3, i) {
+                        self.play_sound(i);
+                    }
+                }
+            }
+            self.wport3 = value;
+            self.sound_writes
+                .push((self.clock, self.wport3, self.wport5));
+        } else if device == map.shift_data {
+            self.shifter.push(value);
+        } else if map.sound.is_some_and(|sound| device == sound[1]) {
+            let check_bit = |byte: u8, i: u8| byte & (0b1 << i) != 0;
+
+            if self.wport3 & AMPLIFIER_ENABLE != 0 {
+                for i in 0..=4 {
+                    if check_bit(value, i) && !check_bit(self.wport5, i) {
+                        self.play_sound(4 + i);
+                    }
+                }
+            }
+            self.wport5 = value;
+            self.sound_writes
+                .push((self.clock, self.wport3, self.wport5));
+        } else if map.watchdog == Some(device) {
+            self.watchdog_kicked = true;
+        }
+    }
+
+    fn set_clock_count(&mut self, clock: u64) {
+        self.clock = clock;
+    }
+
+    fn print_state(&self, w: &mut dyn std::fmt::Write) {
+        let [port0, port1, port2] = self.read_ports;
+        let shifter = &self.shifter;
+        let [in0, in1, in2] = self.port_map.inputs;
+        writeln!(
+            w,
+            "in  {}: {:08b} {}: {:08b} {}: {:08b}",
+            in0, port0, in1, port1, in2, port2
+        )
+        .unwrap();
+        if let Some([sound1, sound2]) = self.port_map.sound {
+            writeln!(
+                w,
+                "out {}: {:08b} {}: {:08b}",
+                sound1, self.wport3, sound2, self.wport5
+            )
+            .unwrap();
+        }
+        writeln!(
+            w,
+            "shifter: register {:016b} amount {} result {:08b}",
+            shifter.register(),
+            shifter.amount(),
+            shifter.result()
+        )
+        .unwrap();
+    }
+
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.shifter.save_state(out);
+        out.extend_from_slice(&self.read_ports);
+        save_state::write_u8(out, self.wport3);
+        save_state::write_u8(out, self.wport5);
+        save_state::write_bool(out, self.watchdog_kicked);
+    }
+
+    fn load_state(&mut self, mut section: &[u8]) -> Result<(), SaveStateError> {
+        // parse the whole section before changing anything
+        let input = &mut section;
+        let mut shifter = self.shifter.clone();
+        shifter.load_state(input)?;
+        let read_ports = save_state::read_bytes(input, self.read_ports.len())?;
+        let wport3 = save_state::read_u8(input)?;
+        let wport5 = save_state::read_u8(input)?;
+        let watchdog_kicked = save_state::read_bool(input)?;
+        if !input.is_empty() {
+            return Err(SaveStateError::Invalid(
+                "trailing bytes in the devices section",
+            ));
+        }
+
+        self.shifter = shifter;
+        self.read_ports.copy_from_slice(read_ports);
+        self.wport5 = wport5;
+        self.watchdog_kicked = watchdog_kicked;
+
+        // the ufo is the only looping sound, keep it in sync with the restored latch
+        if ufo_playing(wport3) {
+            self.start_ufo();
+        } else {
+            self.stop_ufo();
+        }
+        self.wport3 = wport3;
+        self.sound_writes.clear();
+        self.sound_writes.push((0, self.wport3, self.wport5));
+        Ok(())
+    }
+}
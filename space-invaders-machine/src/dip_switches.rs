@@ -0,0 +1,121 @@
+/// The bits of read port 2 that come from the dip switches.
+pub const DIP_SWITCHES_MASK: u8 = 0b1000_1011;
+
+/// The score that gives a extra ship.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtraShip {
+    At1000,
+    At1500,
+}
+
+/// The settings of the dip switches in the cabinet, read by the ROM in port 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DipSwitches {
+    /// Number of ships per game, from 3 to 6.
+    ships: u8,
+    pub extra_ship: ExtraShip,
+    /// Show the coin info in the attract mode.
+    pub coin_info: bool,
+}
+impl DipSwitches {
+    /// Return None if 'ships' is not in the range 3 to 6.
+    pub fn new(ships: u8, extra_ship: ExtraShip, coin_info: bool) -> Option<Self> {
+        let mut dip_switches = Self::default();
+        dip_switches.set_ships(ships)?;
+        dip_switches.extra_ship = extra_ship;
+        dip_switches.coin_info = coin_info;
+        Some(dip_switches)
+    }
+
+    pub fn ships(&self) -> u8 {
+        self.ships
+    }
+
+    /// Set the number of ships. Return None if it is not in the range 3 to 6.
+    pub fn set_ships(&mut self, ships: u8) -> Option<()> {
+        if !(3..=6).contains(&ships) {
+            return None;
+        }
+        self.ships = ships;
+        Some(())
+    }
+
+    /// The bits of port 2.
+    pub fn to_bits(self) -> u8 {
+        let mut bits = self.ships - 3; // bits 0 and 1
+        if self.extra_ship == ExtraShip::At1000 {
+            bits |= 0b0000_1000;
+        }
+        if !self.coin_info {
+            bits |= 0b1000_0000;
+        }
+        bits
+    }
+
+    /// Decode the bits of port 2, ignoring the ones that are not dip switches.
+    pub fn from_bits(bits: u8) -> Self {
+        Self {
+            ships: (bits & 0b11) + 3,
+            extra_ship: if bits & 0b0000_1000 != 0 {
+                ExtraShip::At1000
+            } else {
+                ExtraShip::At1500
+            },
+            coin_info: bits & 0b1000_0000 == 0,
+        }
+    }
+}
+
+impl Default for DipSwitches {
+    fn default() -> Self {
+        Self {
+            ships: 3,
+            extra_ship: ExtraShip::At1500,
+            coin_info: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits_round_trip() {
+        for ships in 3..=6 {
+            for extra_ship in [ExtraShip::At1000, ExtraShip::At1500] {
+                for coin_info in [false, true] {
+                    let dip_switches = DipSwitches::new(ships, extra_ship, coin_info).unwrap();
+                    let bits = dip_switches.to_bits();
+                    assert_eq!(bits & !DIP_SWITCHES_MASK, 0);
+                    assert_eq!(DipSwitches::from_bits(bits), dip_switches);
+                    // the other bits of the port are ignored
+                    assert_eq!(
+                        DipSwitches::from_bits(bits | !DIP_SWITCHES_MASK),
+                        dip_switches
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bits_of_port_2() {
+        // bits 0 and 1: ships, bit 3: extra ship at 1000, bit 7: coin info off
+        assert_eq!(DipSwitches::default().to_bits(), 0b0000_0000);
+        let ships = |n| DipSwitches::new(n, ExtraShip::At1500, true).unwrap();
+        assert_eq!(ships(4).to_bits(), 0b0000_0001);
+        assert_eq!(ships(5).to_bits(), 0b0000_0010);
+        assert_eq!(ships(6).to_bits(), 0b0000_0011);
+        let extra_ship = DipSwitches::new(3, ExtraShip::At1000, true).unwrap();
+        assert_eq!(extra_ship.to_bits(), 0b0000_1000);
+        let no_coin_info = DipSwitches::new(3, ExtraShip::At1500, false).unwrap();
+        assert_eq!(no_coin_info.to_bits(), 0b1000_0000);
+    }
+
+    #[test]
+    fn ships_out_of_range_are_rejected() {
+        assert!(DipSwitches::new(2, ExtraShip::At1500, true).is_none());
+        assert!(DipSwitches::new(7, ExtraShip::At1500, true).is_none());
+    }
+}
@@ -0,0 +1,579 @@
+//! The Space Invaders arcade machine, without any dependency on a window, audio or
+//! input backend. The frontends feed the inputs, run it frame by frame, and present
+//! the framebuffer and the sound events.
+
+pub mod audio;
+mod devices;
+mod dip_switches;
+pub mod machines;
+mod memory;
+pub mod movie;
+pub mod presentation;
+pub mod shifter;
+pub mod synth;
+pub mod watchdog;
+
+pub use devices::{MAX_SOUND_EVENTS, SoundEvent, SpaceInvadersDevices};
+pub use dip_switches::{DIP_SWITCHES_MASK, DipSwitches, ExtraShip};
+pub use memory::{SpaceInvadersMemory, VIDEO_RAM, VIDEO_RAM_LEN};
+
+use audio::{Mixer, SoundSource};
+use machines::{MachineDef, Orientation, OverlayRect};
+use movie::{Movie, MovieError};
+use watchdog::Watchdog;
+
+use intel8080::{
+    Memory,
+    interpreter::Interpreter,
+    save_state::{self, SaveStateError},
+    scheduler::Scheduler,
+};
+use std::{fs, io, path::Path};
+
+pub const SCREEN_WIDTH: u32 = 224;
+pub const SCREEN_HEIGHT: u32 = 256;
+
+/// 320 pixels per scanline, with the pixel clock (4.9920MHz) at 2.5 times the cpu clock
+/// (1.9968MHz): 320 / 2.5 = 128 cpu clocks.
+const CLOCKS_PER_SCANLINE: u32 = 128;
+const SCANLINES_PER_FRAME: u32 = 262;
+
+/// The clock of the cpu, in Hz.
+pub const CPU_CLOCK: u64 = 1_996_800;
+/// The number of frames per second, about 59.54.
+pub const FRAME_RATE: f64 = CPU_CLOCK as f64 / (CLOCKS_PER_SCANLINE * SCANLINES_PER_FRAME) as f64;
+
+/// The entry points of the ROM: the reset and the two interrupts.
+pub const ENTRIES: [u16; 3] = [0x0, 0x8, 0x10];
+
+/// The save state section of the machine, after the sections of the interpreter.
+pub const MACHINE_TAG: &[u8; 4] = b"MACH";
+
+/// The size of a colour map of the Taito boards, see `SpaceInvaders::set_color_map`.
+pub const COLOR_MAP_LEN: usize = 0x400;
+
+/// A ROM image of `len` bytes that only loops, with `JMP 0x0000` at each entry point,
+/// for the tests that drive the machine by its memory and ports.
+#[doc(hidden)]
+pub fn idle_rom(len: usize) -> Vec<u8> {
+    let mut rom = vec![0; len];
+    for entry in ENTRIES {
+        let entry = entry as usize;
+        rom[entry..entry + 3].copy_from_slice(&[0xc3, 0x00, 0x00]);
+    }
+    rom
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    One,
+    Two,
+}
+
+/// The buttons of the cabinets. Each game only reads some of them, see
+/// `MachineDef::inputs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Coin,
+    P1Start,
+    P2Start,
+    Left(Player),
+    Right(Player),
+    /// Up and down are only on the 8-way joysticks of Gun Fight.
+    Up(Player),
+    Down(Player),
+    Fire(Player),
+    /// The tilt switch, that ends the game when the cabinet is shaken.
+    Tilt,
+}
+
+enum MovieState {
+    None,
+    Recording(Movie),
+    Playing { movie: Movie, frame: usize },
+}
+
+pub struct SpaceInvaders {
+    machine: &'static MachineDef,
+    interpreter: Interpreter<SpaceInvadersMemory, SpaceInvadersDevices>,
+    scheduler: Scheduler<u8>,
+    framebuffer: Box<[u8]>,
+    color_map: Option<Box<[u8]>>,
+    rom_hash: u64,
+    movie: MovieState,
+    mixer: Option<Mixer>,
+    watchdog: Option<Watchdog>,
+    /// If the watchdog reset the machine in the last frame.
+    watchdog_fired: bool,
+}
+impl SpaceInvaders {
+    /// Create the machine, from the 0x2000 bytes of the ROM (invaders.h, g, f and e).
+    pub fn new(rom: &[u8]) -> Self {
+        Self::with_machine(&machines::INVADERS, rom)
+    }
+
+    /// Create the board of another game, from its ROM image of `machine.rom_len()`
+    /// bytes, with each chip at its adress.
+    pub fn with_machine(machine: &'static MachineDef, rom: &[u8]) -> Self {
+        let (width, height) = machine.orientation.size();
+        Self {
+            machine,
+            interpreter: create_interpreter(machine, rom, machine.default_inputs),
+            scheduler: create_scheduler(),
+            framebuffer: vec![0; (width * height * 4) as usize].into_boxed_slice(),
+            color_map: None,
+            rom_hash: movie::rom_hash(rom),
+            movie: MovieState::None,
+            mixer: None,
+            watchdog: None,
+            watchdog_fired: false,
+        }
+    }
+
+    /// The game running on the board.
+    pub fn machine(&self) -> &'static MachineDef {
+        self.machine
+    }
+
+    /// Colour the screen with the PROMs of a Taito board, of `machine().color_map_len()`
+    /// bytes: maps of `COLOR_MAP_LEN` bytes, with a 3 bits colour (bit 0 red, bit 1 blue
+    /// and bit 2 green) for each 8x8 pixels of the video RAM. The game selects the map
+    /// with bit 5 of port 5. Without it, the screen has the overlay of the machine.
+    pub fn set_color_map(&mut self, color_map: &[u8]) {
+        assert!(
+            color_map.len() == self.machine.color_map_len()
+                && color_map.len().is_multiple_of(COLOR_MAP_LEN)
+                && !color_map.is_empty(),
+            "bad colour map size: {:#x}",
+            color_map.len()
+        );
+        self.color_map = Some(color_map.into());
+        self.render();
+    }
+
+    /// Power cycle the machine, keeping the dip switches: the RAM, the devices and the cpu
+    /// start over. The reset line of the board, pulled by the watchdog, only resets the
+    /// cpu.
+    pub fn reset(&mut self) {
+        let rom = self.interpreter.memory.get_rom();
+        let mut ports = self.machine.default_inputs;
+        ports[2] |= self.interpreter.devices.read_ports[2] & self.machine.dip_switches_mask;
+        self.interpreter.memory = memory::create_memory(self.machine, &rom);
+        self.interpreter.devices = SpaceInvadersDevices::with_port_map(self.machine.ports, ports);
+        self.interpreter.reset();
+        self.scheduler = create_scheduler();
+        self.interpreter.devices.stop_ufo();
+        self.framebuffer.fill(0);
+        if let Some(watchdog) = &mut self.watchdog {
+            *watchdog = Watchdog::new();
+        }
+    }
+
+    /// Enable the watchdog, that resets the machine if the ROM stops writing to port 6.
+    /// Does nothing if the board has no watchdog.
+    pub fn set_watchdog(&mut self, enabled: bool) {
+        let has_watchdog = self.machine.ports.watchdog.is_some();
+        self.watchdog = (enabled && has_watchdog).then(Watchdog::new);
+    }
+
+    /// If the watchdog fired and reset the machine in the last call to `step_frame`.
+    pub fn watchdog_fired(&self) -> bool {
+        self.watchdog_fired
+    }
+
+    /// Run the machine for one video frame, and render the framebuffer. With a external
+    /// debugger, the frame may stop before its end, and the next call continues it.
+    pub fn step_frame(&mut self) {
+        self.watchdog_fired = false;
+        let frame_start = !self.scheduler.is_mid_frame();
+        match &mut self.movie {
+            _ if !frame_start => {}
+            MovieState::None => {}
+            MovieState::Recording(movie) => movie.frames.push(self.interpreter.devices.read_ports),
+            MovieState::Playing { movie, frame } => match movie.frames.get(*frame) {
+                Some(&ports) => {
+                    self.interpreter.devices.read_ports = ports;
+                    *frame += 1;
+                }
+                None => self.movie = MovieState::None,
+            },
+        }
+
+        let completed = self
+            .scheduler
+            .run_frame(&mut self.interpreter, |interpreter, &rst| {
+                interpreter.interrupt(rst)
+            });
+        if !completed {
+            return;
+        }
+        self.render();
+
+        let clock = self.interpreter.clock_count();
+        let writes = self.interpreter.devices.drain_sound_writes();
+        match &mut self.mixer {
+            Some(mixer) => mixer.mix_until(clock, writes),
+            None => drop(writes),
+        }
+
+        let kicked = self.interpreter.devices.take_watchdog_kick();
+        self.watchdog_fired = match &mut self.watchdog {
+            Some(watchdog) => watchdog.tick(kicked),
+            None => false,
+        };
+        if self.watchdog_fired {
+            // the reset line of the cpu: the RAM and the devices are kept, and the ROM
+            // starts over from 0x0000
+            self.interpreter.reset();
+            self.scheduler = create_scheduler();
+            #[cfg(feature = "debug")]
+            self.interpreter
+                .stop_debugger(intel8080::interpreter::StopReason::Watchdog);
+        }
+    }
+
+    fn render(&mut self) {
+        let mut framebuffer = std::mem::take(&mut self.framebuffer);
+        render_screen(
+            &mut framebuffer,
+            self.video_memory(),
+            self.machine.orientation,
+        );
+        self.colorize(&mut framebuffer);
+        self.framebuffer = framebuffer;
+    }
+
+    /// Tint a screen rendered by `render_screen` with the colours of the cabinet.
+    pub fn colorize(&self, screen: &mut [u8]) {
+        let orientation = self.machine.orientation;
+        match &self.color_map {
+            Some(color_map) => {
+                let maps = color_map.len() / COLOR_MAP_LEN;
+                let map = (self.sound_ports().1 >> 5 & 0x1) as usize % maps * COLOR_MAP_LEN;
+                apply_color_map(screen, orientation, &color_map[map..map + COLOR_MAP_LEN]);
+            }
+            None => apply_overlay(screen, orientation.size(), self.machine.overlay),
+        }
+    }
+
+    /// Serialize the entire machine: the sections of the interpreter, and a section with
+    /// the frame stopped by the debugger, the position of the movie, the alignment of the
+    /// audio and the watchdog.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = self.interpreter.save_state();
+        save_state::write_section(&mut out, MACHINE_TAG, |out| {
+            self.scheduler.save_state(out);
+            let position = match &self.movie {
+                MovieState::None => None,
+                MovieState::Recording(movie) => Some(movie.frames.len()),
+                MovieState::Playing { frame, .. } => Some(*frame),
+            };
+            save_state::write_bool(out, position.is_some());
+            save_state::write_u32(out, position.unwrap_or(0) as u32);
+            let clock_base = self.mixer.as_ref().map(Mixer::clock_base);
+            let (base_clock, sample_count) = clock_base.unwrap_or((0, 0));
+            save_state::write_bool(out, clock_base.is_some());
+            save_state::write_u64(out, base_clock);
+            save_state::write_u64(out, sample_count);
+            save_state::write_bool(out, self.watchdog.is_some());
+            let watchdog = self.watchdog.clone().unwrap_or_default();
+            watchdog.save_state(out);
+        });
+        out
+    }
+
+    /// Restore a state created by `save_state`. The position of the movie is applied to
+    /// the movie being recorded or played, rewinding it. On error, the machine is left as
+    /// it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut input = data;
+        save_state::read_header(&mut input)?;
+        for tag in [
+            save_state::CPU_TAG,
+            save_state::MEMORY_TAG,
+            save_state::DEVICES_TAG,
+        ] {
+            save_state::read_section(&mut input, tag)?;
+        }
+        let input = &mut save_state::read_section(&mut input, MACHINE_TAG)?;
+
+        // parse the whole section before changing anything
+        let mut scheduler = self.scheduler.clone();
+        scheduler.load_state(input)?;
+        let has_position = save_state::read_bool(input)?;
+        let position = save_state::read_u32(input)? as usize;
+        let has_clock_base = save_state::read_bool(input)?;
+        let clock_base = (save_state::read_u64(input)?, save_state::read_u64(input)?);
+        let has_watchdog = save_state::read_bool(input)?;
+        let mut watchdog = Watchdog::new();
+        watchdog.load_state(input)?;
+        let movie_len = match &self.movie {
+            MovieState::None => None,
+            MovieState::Recording(movie) | MovieState::Playing { movie, .. } => {
+                Some(movie.frames.len())
+            }
+        };
+        if has_position && movie_len.is_some_and(|len| position > len) {
+            return Err(SaveStateError::Invalid(
+                "the movie is shorter than the state",
+            ));
+        }
+
+        self.interpreter.load_state(data)?;
+        self.scheduler = scheduler;
+        match &mut self.movie {
+            _ if !has_position => {}
+            MovieState::None => {}
+            MovieState::Recording(movie) => movie.frames.truncate(position),
+            MovieState::Playing { frame, .. } => *frame = position,
+        }
+        if let Some(mixer) = &mut self.mixer
+            && has_clock_base
+        {
+            mixer.set_clock_base(clock_base);
+        }
+        // the watchdog is enabled by the frontend, only its count is restored
+        if let Some(current) = &mut self.watchdog
+            && has_watchdog
+        {
+            *current = watchdog;
+        }
+        self.watchdog_fired = false;
+        self.render();
+        Ok(())
+    }
+
+    pub fn save_state_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.save_state())
+    }
+
+    pub fn load_state_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), SaveStateError> {
+        let data = fs::read(path)?;
+        self.load_state(&data)
+    }
+
+    /// Press or release a button. Does nothing if the game has no such button.
+    pub fn set_input(&mut self, input: Input, pressed: bool) {
+        let machine = self.machine;
+        let Some(&(_, port, mask)) = machine.inputs.iter().find(|&&(i, ..)| i == input) else {
+            return;
+        };
+        let pressed = pressed != (machine.active_low[port] & mask != 0);
+        let port = &mut self.interpreter.devices.read_ports[port];
+        if pressed {
+            *port |= mask;
+        } else {
+            *port &= !mask;
+        }
+    }
+
+    /// The state of the three input ports.
+    pub fn read_ports(&self) -> [u8; 3] {
+        self.interpreter.devices.read_ports
+    }
+
+    pub fn set_read_ports(&mut self, ports: [u8; 3]) {
+        self.interpreter.devices.read_ports = ports;
+    }
+
+    /// The dip switches of Space Invaders. The games with other dip switches keep them at
+    /// 0, and return the default.
+    pub fn dip_switches(&self) -> DipSwitches {
+        let mask = self.machine.dip_switches_mask;
+        DipSwitches::from_bits(self.interpreter.devices.read_ports[2] & mask)
+    }
+
+    /// Change the dip switches. The ROM only reads some of them at power on, so
+    /// `reset` may be needed for the change to take effect. Does nothing if the game has
+    /// other dip switches.
+    pub fn set_dip_switches(&mut self, dip_switches: DipSwitches) {
+        let mask = self.machine.dip_switches_mask;
+        let port = &mut self.interpreter.devices.read_ports[2];
+        *port = (*port & !mask) | (dip_switches.to_bits() & mask);
+    }
+
+    /// Reset the machine and start recording the inputs of each frame.
+    pub fn start_recording(&mut self) {
+        self.reset();
+        self.movie = MovieState::Recording(Movie::new(self.rom_hash, self.dip_switches()));
+    }
+
+    /// Stop the recording, returning the recorded movie, if any.
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        match std::mem::replace(&mut self.movie, MovieState::None) {
+            MovieState::Recording(movie) => Some(movie),
+            other => {
+                self.movie = other;
+                None
+            }
+        }
+    }
+
+    /// Reset the machine and replay the inputs of the movie. While playing, the inputs
+    /// from `set_input` are ignored.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        if movie.rom_hash != self.rom_hash {
+            return Err(MovieError::RomMismatch {
+                expected: movie.rom_hash,
+                found: self.rom_hash,
+            });
+        }
+        self.set_dip_switches(movie.dip_switches);
+        self.reset();
+        self.movie = MovieState::Playing { movie, frame: 0 };
+        Ok(())
+    }
+
+    /// Return true while a movie is being replayed.
+    pub fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, MovieState::Playing { .. })
+    }
+
+    /// The number of frames of the movie being played.
+    pub fn movie_len(&self) -> Option<usize> {
+        match &self.movie {
+            MovieState::Playing { movie, .. } => Some(movie.frames.len()),
+            _ => None,
+        }
+    }
+
+    /// The size of the screen, as (width, height): SCREEN_WIDTH x SCREEN_HEIGHT, or the
+    /// transpose for a horizontal monitor.
+    pub fn screen_size(&self) -> (u32, u32) {
+        self.machine.orientation.size()
+    }
+
+    /// The screen rendered in the last frame, in RGBA, with `screen_size()` pixels.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    /// The 1 bit per pixel video RAM, in the rotated layout of the hardware.
+    pub fn video_memory(&self) -> &[u8] {
+        self.interpreter
+            .memory
+            .bytes(VIDEO_RAM, VIDEO_RAM_LEN)
+            .expect("the video RAM is mapped")
+    }
+
+    /// Take the sound events emitted since the last call. If more than
+    /// `MAX_SOUND_EVENTS` were emitted, the older ones are lost.
+    pub fn drain_sound_events(&mut self) -> std::vec::Drain<'_, SoundEvent> {
+        self.interpreter.devices.drain_sound_events()
+    }
+
+    /// The last values written to the sound ports 3 and 5.
+    pub fn sound_ports(&self) -> (u8, u8) {
+        self.interpreter.devices.sound_ports()
+    }
+
+    /// Start mixing the sound of each frame, from the given source.
+    pub fn enable_audio(&mut self, source: Box<dyn SoundSource>) {
+        let mut mixer = Mixer::new(source, self.interpreter.clock_count());
+        let (port3, port5) = self.sound_ports();
+        mixer.mix_until(self.interpreter.clock_count(), [(0, port3, port5)]);
+        self.mixer = Some(mixer);
+    }
+
+    /// Take the samples mixed since the last call, at `audio::SAMPLE_RATE`. Empty if the
+    /// audio is not enabled.
+    pub fn drain_samples(&mut self) -> impl Iterator<Item = f32> + '_ {
+        self.mixer.iter_mut().flat_map(Mixer::drain)
+    }
+
+    /// Start keeping all the mixed samples. Does nothing if the audio is not enabled.
+    pub fn start_audio_capture(&mut self) {
+        if let Some(mixer) = &mut self.mixer {
+            mixer.start_capture();
+        }
+    }
+
+    /// Stop the capture, returning the samples mixed since it started.
+    pub fn stop_audio_capture(&mut self) -> Option<Vec<f32>> {
+        self.mixer.as_mut().and_then(Mixer::stop_capture)
+    }
+
+    pub fn interpreter(&self) -> &Interpreter<SpaceInvadersMemory, SpaceInvadersDevices> {
+        &self.interpreter
+    }
+
+    pub fn interpreter_mut(
+        &mut self,
+    ) -> &mut Interpreter<SpaceInvadersMemory, SpaceInvadersDevices> {
+        &mut self.interpreter
+    }
+}
+
+fn create_interpreter(
+    machine: &MachineDef,
+    rom: &[u8],
+    ports: [u8; 3],
+) -> Interpreter<SpaceInvadersMemory, SpaceInvadersDevices> {
+    Interpreter::new(
+        SpaceInvadersDevices::with_port_map(machine.ports, ports),
+        memory::create_memory(machine, rom),
+        &ENTRIES,
+    )
+}
+
+/// The video circuit request RST 1 when the beam reaches the middle of the screen, and
+/// RST 2 at the start of the vertical blank.
+pub fn create_scheduler() -> Scheduler<u8> {
+    let mut scheduler = Scheduler::new(SCANLINES_PER_FRAME * CLOCKS_PER_SCANLINE);
+    scheduler.add_event(96 * CLOCKS_PER_SCANLINE, 0b11001111); // RST 1 (0xcf)
+    scheduler.add_event(224 * CLOCKS_PER_SCANLINE, 0b11010111); // RST 2 (0xd7)
+    scheduler
+}
+
+/// Render the video memory to a white on black RGBA screen, of `orientation.size()`.
+pub fn render_screen(screen: &mut [u8], memory: &[u8], orientation: Orientation) {
+    for x in 0..SCREEN_WIDTH {
+        for y in 0..SCREEN_HEIGHT {
+            let i = (x * SCREEN_HEIGHT + y) as usize;
+            let m = memory[i / 8];
+            let c = if (m >> (i % 8)) & 0x1 != 0 { 0xff } else { 0x0 };
+            let p = pixel_offset(orientation, x, y);
+            screen[p..p + 4].copy_from_slice(&[c, c, c, 0xff]);
+        }
+    }
+}
+
+/// The offset in a RGBA screen of the pixel `y` of the line `x` of the video RAM.
+fn pixel_offset(orientation: Orientation, x: u32, y: u32) -> usize {
+    (match orientation {
+        Orientation::Vertical => (SCREEN_HEIGHT - y - 1) * SCREEN_WIDTH + x,
+        Orientation::Horizontal => x * SCREEN_HEIGHT + y,
+    } * 4) as usize
+}
+
+/// Colour a screen rendered by `render_screen` with a colour map of `COLOR_MAP_LEN`
+/// bytes: the pixels of a 8x8 cell of the video RAM keep the channels set in its colour.
+pub fn apply_color_map(screen: &mut [u8], orientation: Orientation, color_map: &[u8]) {
+    for x in 0..SCREEN_WIDTH {
+        for y in 0..SCREEN_HEIGHT {
+            // the cells of 8 lines are 32 bytes apart, like the bytes of the lines
+            let color = color_map[(x / 8 * 32 + y / 8) as usize];
+            let p = pixel_offset(orientation, x, y);
+            for (channel, bit) in screen[p..p + 3].iter_mut().zip([0, 2, 1]) {
+                if color >> bit & 0x1 == 0 {
+                    *channel = 0;
+                }
+            }
+        }
+    }
+}
+
+/// Tint the rectangles of the overlay on a RGBA screen of the given (width, height). The
+/// rectangles are clipped to the screen.
+pub fn apply_overlay(screen: &mut [u8], (width, height): (u32, u32), overlay: &[OverlayRect]) {
+    for rect in overlay {
+        for y in rect.y.start..rect.y.end.min(height) {
+            for x in rect.x.start..rect.x.end.min(width) {
+                let p = (y * width + x) as usize * 4;
+                for (channel, &tint) in screen[p..p + 3].iter_mut().zip(&rect.color) {
+                    *channel = (*channel as u16 * tint as u16 / 0xff) as u8;
+                }
+            }
+        }
+    }
+}
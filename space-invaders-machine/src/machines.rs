@@ -0,0 +1,374 @@
+//! The definitions of the games that run on the Midway 8080 board, and its Taito
+//! variants. They share the cpu, the video circuit and the shifter, but differ in the
+//! ROM layout, the port assignments, the controls, the monitor and the colour overlay.
+
+use std::ops::Range;
+
+use crate::{DIP_SWITCHES_MASK, Input, Player, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// A ROM chip, loaded from the file `name` at the given adress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomFile {
+    pub name: &'static str,
+    pub adress: u16,
+    pub len: usize,
+    /// The checksums of a good dump, if known.
+    pub crc32: Option<u32>,
+    pub sha1: Option<&'static str>,
+}
+
+/// A 2KiB chip, with unknown checksums.
+const fn rom(name: &'static str, adress: u16) -> RomFile {
+    RomFile {
+        name,
+        adress,
+        len: 0x800,
+        crc32: None,
+        sha1: None,
+    }
+}
+
+/// A 1KiB chip, with unknown checksums.
+const fn small_rom(name: &'static str, adress: u16) -> RomFile {
+    RomFile {
+        len: 0x400,
+        ..rom(name, adress)
+    }
+}
+
+/// A 2KiB chip, with the checksums of a good dump.
+const fn checked_rom(name: &'static str, adress: u16, crc32: u32, sha1: &'static str) -> RomFile {
+    RomFile {
+        crc32: Some(crc32),
+        sha1: Some(sha1),
+        ..rom(name, adress)
+    }
+}
+
+/// The I/O ports of the devices of the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortMap {
+    /// The read ports of the 3 input latches.
+    pub inputs: [u8; 3],
+    pub shift_result: u8,
+    pub shift_amount: u8,
+    pub shift_data: u8,
+    /// The two sound latches, 'port 3' and 'port 5' of Space Invaders. None if the sound
+    /// board of the game is not emulated.
+    pub sound: Option<[u8; 2]>,
+    /// None if the board has no watchdog.
+    pub watchdog: Option<u8>,
+}
+
+/// The ports of Space Invaders, also used by the Taito boards.
+pub const MIDWAY_PORTS: PortMap = PortMap {
+    inputs: [0, 1, 2],
+    shift_result: 3,
+    shift_amount: 2,
+    shift_data: 4,
+    sound: Some([3, 5]),
+    watchdog: Some(6),
+};
+
+/// The ports of Gun Fight. Its sound board, written at port 1, is not emulated, and it has
+/// no watchdog.
+pub const GUNFIGHT_PORTS: PortMap = PortMap {
+    inputs: [0, 1, 2],
+    shift_result: 3,
+    shift_amount: 2,
+    shift_data: 4,
+    sound: None,
+    watchdog: None,
+};
+
+/// The bits of the read ports set by the buttons of the Space Invaders cabinet, as
+/// (input, port, mask), also used by the Taito boards.
+const MIDWAY_INPUTS: &[(Input, usize, u8)] = &[
+    (Input::Coin, 1, 0b0000_0001),
+    (Input::P2Start, 1, 0b0000_0010),
+    (Input::P1Start, 1, 0b0000_0100),
+    (Input::Fire(Player::One), 1, 0b0001_0000),
+    (Input::Left(Player::One), 1, 0b0010_0000),
+    (Input::Right(Player::One), 1, 0b0100_0000),
+    (Input::Tilt, 2, 0b0000_0100),
+    (Input::Fire(Player::Two), 2, 0b0001_0000),
+    (Input::Left(Player::Two), 2, 0b0010_0000),
+    (Input::Right(Player::Two), 2, 0b0100_0000),
+];
+
+/// The 8-way joysticks and the triggers of Gun Fight, one port for each player. The gun
+/// levers are not emulated, see `GUNFIGHT_INPUTS_IDLE`.
+const GUNFIGHT_INPUTS: &[(Input, usize, u8)] = &[
+    (Input::Up(Player::One), 0, 0b0000_0001),
+    (Input::Down(Player::One), 0, 0b0000_0010),
+    (Input::Left(Player::One), 0, 0b0000_0100),
+    (Input::Right(Player::One), 0, 0b0000_1000),
+    (Input::Fire(Player::One), 0, 0b1000_0000),
+    (Input::Up(Player::Two), 1, 0b0000_0001),
+    (Input::Down(Player::Two), 1, 0b0000_0010),
+    (Input::Left(Player::Two), 1, 0b0000_0100),
+    (Input::Right(Player::Two), 1, 0b0000_1000),
+    (Input::Fire(Player::Two), 1, 0b1000_0000),
+    (Input::Coin, 2, 0b0100_0000),
+    (Input::P1Start, 2, 0b1000_0000),
+];
+
+/// How the monitor is mounted in the cabinet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    /// Rotated 90° counter-clockwise, like in Space Invaders: the video RAM is shown as
+    /// SCREEN_WIDTH x SCREEN_HEIGHT pixels, with its lines as columns.
+    Vertical,
+    /// The video RAM is shown as it is scanned, SCREEN_HEIGHT x SCREEN_WIDTH pixels.
+    Horizontal,
+}
+impl Orientation {
+    /// The size of the screen, as (width, height).
+    pub fn size(self) -> (u32, u32) {
+        match self {
+            Orientation::Vertical => (SCREEN_WIDTH, SCREEN_HEIGHT),
+            Orientation::Horizontal => (SCREEN_HEIGHT, SCREEN_WIDTH),
+        }
+    }
+}
+
+/// A rectangle of the screen, in screen pixels, tinted by the overlay. The pixels not
+/// covered by any rectangle are white.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayRect {
+    pub x: Range<u32>,
+    pub y: Range<u32>,
+    pub color: [u8; 3],
+}
+
+const RED: [u8; 3] = [0xff, 0x00, 0x00];
+const GREEN: [u8; 3] = [0x00, 0xff, 0x00];
+
+/// The cellophane strips of the Space Invaders cabinet: red over the ufo, green over the
+/// shields and the player, except over the credits.
+const INVADERS_OVERLAY: &[OverlayRect] = &[
+    OverlayRect {
+        x: 0..224,
+        y: 32..64,
+        color: RED,
+    },
+    OverlayRect {
+        x: 0..224,
+        y: 184..240,
+        color: GREEN,
+    },
+    OverlayRect {
+        x: 16..102,
+        y: 240..256,
+        color: GREEN,
+    },
+];
+
+/// A game of the board.
+#[derive(Debug)]
+pub struct MachineDef {
+    /// The name used to select the game, the same as the MAME short name.
+    pub name: &'static str,
+    pub title: &'static str,
+    pub roms: &'static [RomFile],
+    /// The RAM regions, as (start, len). The video RAM is always at 0x2400.
+    pub ram: &'static [(u16, u32)],
+    /// The mirrors of the RAM, as (start, len, target, size).
+    pub mirrors: &'static [(u16, u32, u16, u32)],
+    pub ports: PortMap,
+    /// The bit of the read ports of each input of the cabinet, as (input, port, mask).
+    pub inputs: &'static [(Input, usize, u8)],
+    /// The bits of the read ports that are 0 while their input is pressed.
+    pub active_low: [u8; 3],
+    /// The input latches with no button pressed, and the dip switches at 0.
+    pub default_inputs: [u8; 3],
+    /// The bits of port 2 set by `DipSwitches`, 0 if the game has other dip switches.
+    pub dip_switches_mask: u8,
+    pub orientation: Orientation,
+    pub overlay: &'static [OverlayRect],
+    /// The colour PROMs of the Taito boards, each at its offset in the colour map, see
+    /// `SpaceInvaders::set_color_map`. Empty for the games coloured by a overlay.
+    pub color_proms: &'static [RomFile],
+}
+impl MachineDef {
+    /// The size of the ROM image: from 0 to the end of the last ROM chip.
+    pub fn rom_len(&self) -> usize {
+        chips_len(self.roms)
+    }
+
+    /// The size of the colour map: from 0 to the end of the last colour PROM.
+    pub fn color_map_len(&self) -> usize {
+        chips_len(self.color_proms)
+    }
+}
+
+fn chips_len(chips: &[RomFile]) -> usize {
+    chips
+        .iter()
+        .map(|rom| rom.adress as usize + rom.len)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The 8KiB of RAM at 0x2000, mirrored in the rest of the adress space.
+const MIDWAY_RAM: &[(u16, u32)] = &[(0x2000, 0x2000)];
+const MIDWAY_MIRRORS: &[(u16, u32, u16, u32)] = &[(0x4000, 0xc000, 0x2000, 0x2000)];
+
+/// The Taito boards have more ROM at 0x4000 and a colour RAM at 0xc000, so the main RAM
+/// is only mirrored at 0x6000. The games here don't use the colour RAM: their colours come
+/// from PROMs.
+const TAITO_RAM: &[(u16, u32)] = &[(0x2000, 0x2000), (0xc000, 0x2000)];
+const TAITO_MIRRORS: &[(u16, u32, u16, u32)] = &[
+    (0x6000, 0x2000, 0x2000, 0x2000),
+    (0xe000, 0x2000, 0xc000, 0x2000),
+];
+
+const DEFAULT_INPUTS: [u8; 3] = [0b0000_1111, 0b0000_1000, 0b0000_0000];
+
+pub const INVADERS: MachineDef = MachineDef {
+    name: "invaders",
+    title: "Space Invaders",
+    roms: &[
+        checked_rom(
+            "invaders.h",
+            0x0000,
+            0x734f5ad8,
+            "ff6200af4c9110d8181249cbcef1a8a40fa40b7f",
+        ),
+        checked_rom(
+            "invaders.g",
+            0x0800,
+            0x6bfaca4a,
+            "16f48649b531bdef8c2d1446c429b5f414524350",
+        ),
+        checked_rom(
+            "invaders.f",
+            0x1000,
+            0x0ccead96,
+            "537aef03468f63c5b9e11dd61e253f7ae17d9743",
+        ),
+        checked_rom(
+            "invaders.e",
+            0x1800,
+            0x14e538b0,
+            "1d6ca0c99f9df71e2990b610deb9d7da0125e2d8",
+        ),
+    ],
+    ram: MIDWAY_RAM,
+    mirrors: MIDWAY_MIRRORS,
+    ports: MIDWAY_PORTS,
+    inputs: MIDWAY_INPUTS,
+    active_low: [0; 3],
+    default_inputs: DEFAULT_INPUTS,
+    dip_switches_mask: DIP_SWITCHES_MASK,
+    orientation: Orientation::Vertical,
+    overlay: INVADERS_OVERLAY,
+    color_proms: &[],
+};
+
+pub const INVADPT2: MachineDef = MachineDef {
+    name: "invadpt2",
+    title: "Space Invaders Part II",
+    roms: &[
+        rom("pv01", 0x0000),
+        rom("pv02", 0x0800),
+        rom("pv03", 0x1000),
+        rom("pv04", 0x1800),
+        rom("pv05", 0x4000),
+    ],
+    ram: TAITO_RAM,
+    mirrors: TAITO_MIRRORS,
+    ports: MIDWAY_PORTS,
+    inputs: MIDWAY_INPUTS,
+    active_low: [0; 3],
+    default_inputs: DEFAULT_INPUTS,
+    dip_switches_mask: DIP_SWITCHES_MASK,
+    orientation: Orientation::Vertical,
+    overlay: &[],
+    color_proms: &[small_rom("pv06.1", 0x0000), small_rom("pv07.2", 0x0400)],
+};
+
+pub const LRESCUE: MachineDef = MachineDef {
+    name: "lrescue",
+    title: "Lunar Rescue",
+    roms: &[
+        rom("lrescue.1", 0x0000),
+        rom("lrescue.2", 0x0800),
+        rom("lrescue.3", 0x1000),
+        rom("lrescue.4", 0x1800),
+        rom("lrescue.5", 0x4000),
+        rom("lrescue.6", 0x4800),
+    ],
+    ram: TAITO_RAM,
+    mirrors: TAITO_MIRRORS,
+    ports: MIDWAY_PORTS,
+    inputs: MIDWAY_INPUTS,
+    active_low: [0; 3],
+    default_inputs: DEFAULT_INPUTS,
+    dip_switches_mask: DIP_SWITCHES_MASK,
+    orientation: Orientation::Vertical,
+    overlay: &[],
+    color_proms: &[
+        small_rom("7643-1.cpu", 0x0000),
+        small_rom("7643-2.cpu", 0x0400),
+    ],
+};
+
+pub const BALLBOMB: MachineDef = MachineDef {
+    name: "ballbomb",
+    title: "Balloon Bomber",
+    roms: &[
+        rom("tn01", 0x0000),
+        rom("tn02", 0x0800),
+        rom("tn03", 0x1000),
+        rom("tn04", 0x1800),
+        rom("tn05-1", 0x4000),
+    ],
+    ram: TAITO_RAM,
+    mirrors: TAITO_MIRRORS,
+    ports: MIDWAY_PORTS,
+    inputs: MIDWAY_INPUTS,
+    active_low: [0; 3],
+    default_inputs: DEFAULT_INPUTS,
+    dip_switches_mask: DIP_SWITCHES_MASK,
+    orientation: Orientation::Vertical,
+    overlay: &[],
+    color_proms: &[small_rom("tn06", 0x0000), small_rom("tn07", 0x0400)],
+};
+
+/// The joysticks and the triggers at rest, with the gun levers held at the middle of their
+/// seven positions, and the start button up.
+const GUNFIGHT_INPUTS_IDLE: [u8; 3] = [0b1011_1111, 0b1011_1111, 0b1000_0000];
+
+/// Gun Fight has a horizontal monitor and a smaller ROM, of 1KiB chips.
+pub const GUNFIGHT: MachineDef = MachineDef {
+    name: "gunfight",
+    title: "Gun Fight",
+    roms: &[
+        small_rom("7609h.bin", 0x0000),
+        small_rom("7609g.bin", 0x0400),
+        small_rom("7609f.bin", 0x0800),
+        small_rom("7609e.bin", 0x0c00),
+    ],
+    ram: MIDWAY_RAM,
+    mirrors: MIDWAY_MIRRORS,
+    ports: GUNFIGHT_PORTS,
+    inputs: GUNFIGHT_INPUTS,
+    active_low: [0b1000_1111, 0b1000_1111, 0b1000_0000],
+    default_inputs: GUNFIGHT_INPUTS_IDLE,
+    dip_switches_mask: 0,
+    orientation: Orientation::Horizontal,
+    overlay: &[],
+    color_proms: &[],
+};
+
+/// All the known games.
+pub const MACHINES: &[&MachineDef] = &[&INVADERS, &INVADPT2, &LRESCUE, &BALLBOMB, &GUNFIGHT];
+
+/// Find a game by its name.
+pub fn find(name: &str) -> Option<&'static MachineDef> {
+    MACHINES
+        .iter()
+        .copied()
+        .find(|machine| machine.name == name)
+}
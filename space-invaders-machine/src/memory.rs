@@ -0,0 +1,31 @@
+use intel8080::memory_map::MemoryMap;
+
+use crate::machines::MachineDef;
+
+pub type SpaceInvadersMemory = MemoryMap;
+
+/// The start of the video RAM.
+pub const VIDEO_RAM: u16 = 0x2400;
+/// The size of the video RAM, 256x224 pixels at 1 bit per pixel.
+pub const VIDEO_RAM_LEN: usize = 0x1c00;
+
+/// The memory of the board, with each ROM chip of the machine loaded from its adress in
+/// the ROM image.
+pub fn create_memory(machine: &MachineDef, rom: &[u8]) -> SpaceInvadersMemory {
+    let mut image = vec![0; machine.rom_len()];
+    let len = rom.len().min(image.len());
+    image[..len].copy_from_slice(&rom[..len]);
+
+    let mut builder = MemoryMap::builder();
+    for file in machine.roms {
+        let start = file.adress as usize;
+        builder = builder.rom(file.adress, &image[start..start + file.len]);
+    }
+    for &(start, len) in machine.ram {
+        builder = builder.ram(start, len);
+    }
+    for &(start, len, target, size) in machine.mirrors {
+        builder = builder.mirror(start, len, target, size);
+    }
+    builder.build().expect("the memory map is valid")
+}
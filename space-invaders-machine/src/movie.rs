@@ -0,0 +1,177 @@
+//! Input movies: the state of the three input ports in each frame, since power on.
+//!
+//! Because the emulation is deterministic, replaying the inputs of a movie in a freshly
+//! reset machine reproduces exactly the same session.
+//!
+//! ```text
+//! "SIMOVIE\0" | version: u16 | rom hash: u64 | dip switches: u8 | frames: u32 | [u8; 3] * frames
+//! ```
+
+use std::{fmt, fs, io, path::Path};
+
+use crate::DipSwitches;
+
+const MAGIC: &[u8; 8] = b"SIMOVIE\0";
+const VERSION: u16 = 1;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    /// The data does not start with the movie magic.
+    BadMagic,
+    /// The movie was written by a incompatible version.
+    UnsupportedVersion(u16),
+    /// The data ended before the expected.
+    UnexpectedEof,
+    /// The movie was recorded with a different ROM.
+    RomMismatch {
+        expected: u64,
+        found: u64,
+    },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(e) => write!(f, "io error: {}", e),
+            MovieError::BadMagic => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(v) => {
+                write!(f, "unsupported movie version {} (expected {})", v, VERSION)
+            }
+            MovieError::UnexpectedEof => write!(f, "unexpected end of movie"),
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "the movie was recorded with other ROM (hash {:016x}, but the loaded is {:016x})",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> Self {
+        MovieError::Io(e)
+    }
+}
+
+/// FNV-1a hash of the ROM, to check that a movie is replayed in the same program.
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in rom {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    /// The dip switches when the recording started.
+    pub dip_switches: DipSwitches,
+    pub frames: Vec<[u8; 3]>,
+}
+impl Movie {
+    pub fn new(rom_hash: u64, dip_switches: DipSwitches) -> Self {
+        Self {
+            rom_hash,
+            dip_switches,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(23 + self.frames.len() * 3);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        out.push(self.dip_switches.to_bits());
+        out.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for frame in &self.frames {
+            out.extend_from_slice(frame);
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        let mut input = data;
+        let mut read = |len: usize| -> Result<&[u8], MovieError> {
+            if input.len() < len {
+                return Err(MovieError::UnexpectedEof);
+            }
+            let (bytes, rest) = input.split_at(len);
+            input = rest;
+            Ok(bytes)
+        };
+
+        if read(MAGIC.len()).map_err(|_| MovieError::BadMagic)? != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let version = u16::from_le_bytes(read(2)?.try_into().unwrap());
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = u64::from_le_bytes(read(8)?.try_into().unwrap());
+        let dip_switches = DipSwitches::from_bits(read(1)?[0]);
+        let len = u32::from_le_bytes(read(4)?.try_into().unwrap()) as usize;
+        // the length is untrusted, and may overflow a 32 bits usize
+        let frames = read(len.checked_mul(3).ok_or(MovieError::UnexpectedEof)?)?
+            .chunks_exact(3)
+            .map(|f| [f[0], f[1], f[2]])
+            .collect();
+
+        Ok(Self {
+            rom_hash,
+            dip_switches,
+            frames,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MovieError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie() -> Movie {
+        let mut movie = Movie::new(0x0123_4567_89ab_cdef, DipSwitches::default());
+        movie.frames = vec![[0x0f, 0x08, 0x00], [0x0f, 0x09, 0x00], [0x0f, 0x08, 0x80]];
+        movie
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let movie = movie();
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let bytes = movie().to_bytes();
+        assert!(matches!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MovieError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn huge_frame_count_is_rejected() {
+        let mut bytes = movie().to_bytes();
+        // the frame count is right after the dip switches
+        let len = MAGIC.len() + 2 + 8 + 1;
+        bytes[len..len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Movie::from_bytes(&bytes),
+            Err(MovieError::UnexpectedEof)
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+//! The presentation of the screen: the colour overlay, the background artwork and the
+//! CRT effects. Everything is done on the cpu, so it works without a GPU.
+
+use crate::{
+    SpaceInvaders, apply_overlay,
+    machines::{MachineDef, Orientation, OverlayRect},
+    render_screen,
+};
+
+const RED: [u8; 3] = [0xff, 0x20, 0x20];
+const ORANGE: [u8; 3] = [0xff, 0x90, 0x20];
+const YELLOW: [u8; 3] = [0xff, 0xff, 0x40];
+const GREEN: [u8; 3] = [0x40, 0xff, 0x40];
+
+/// A overlay in the style of the Space Invaders Deluxe cabinet, with more colour bands.
+const DELUXE_OVERLAY: &[OverlayRect] = &[
+    OverlayRect {
+        x: 0..224,
+        y: 32..64,
+        color: RED,
+    },
+    OverlayRect {
+        x: 0..224,
+        y: 64..120,
+        color: ORANGE,
+    },
+    OverlayRect {
+        x: 0..224,
+        y: 120..184,
+        color: YELLOW,
+    },
+    OverlayRect {
+        x: 0..224,
+        y: 184..256,
+        color: GREEN,
+    },
+];
+
+/// The colours in front of the screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Overlay {
+    /// White on black, without any overlay.
+    Monochrome,
+    /// The overlay of the cabinet of the game.
+    Classic,
+    /// A overlay in the style of the Space Invaders Deluxe cabinet.
+    Deluxe,
+    /// A RGBA image of the size of the screen, multiplied with it.
+    Image(Box<[u8]>),
+}
+
+pub struct Presentation {
+    pub overlay: Overlay,
+    /// A RGBA image of the size of the screen behind it, like the moon artwork of the
+    /// cabinet. The screen is added over it.
+    pub background: Option<Box<[u8]>>,
+    /// Draw the gaps between the scanlines, doubling the output size.
+    pub scanlines: bool,
+    /// How much of the last frame is kept by the phosphors, from 0 (none) to 1.
+    pub persistence: f32,
+    /// Blur the screen, like the spread of the electron beam.
+    pub blur: bool,
+
+    orientation: Orientation,
+    /// The phosphors, in the last frame.
+    phosphors: Box<[u8]>,
+    output: Vec<u8>,
+}
+impl Default for Presentation {
+    fn default() -> Self {
+        Self::for_orientation(Orientation::Vertical)
+    }
+}
+impl Presentation {
+    /// The classic overlay, without any effect: the same as `SpaceInvaders::framebuffer`.
+    /// For the vertical monitor of Space Invaders.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The same as `new`, for the monitor of the given game.
+    pub fn for_machine(machine: &MachineDef) -> Self {
+        Self::for_orientation(machine.orientation)
+    }
+
+    fn for_orientation(orientation: Orientation) -> Self {
+        let (width, height) = orientation.size();
+        Self {
+            overlay: Overlay::Classic,
+            background: None,
+            scanlines: false,
+            persistence: 0.0,
+            blur: false,
+            orientation,
+            phosphors: vec![0; (width * height * 4) as usize].into_boxed_slice(),
+            output: Vec::new(),
+        }
+    }
+
+    /// The size of the screen, before the scale, as (width, height).
+    pub fn screen_size(&self) -> (u32, u32) {
+        self.orientation.size()
+    }
+
+    /// The scale of the output: 2 with the scanlines, so the gaps can be seen.
+    pub fn scale(&self) -> u32 {
+        if self.scanlines { 2 } else { 1 }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.screen_size().0 * self.scale()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.screen_size().1 * self.scale()
+    }
+
+    /// Render the last frame of the machine, in RGBA, with `width()` x `height()` pixels.
+    /// The machine must have the monitor of the presentation.
+    pub fn present(&mut self, machine: &SpaceInvaders) -> &[u8] {
+        let size = self.screen_size();
+        let mut screen = vec![0; self.phosphors.len()];
+        render_screen(&mut screen, machine.video_memory(), self.orientation);
+
+        if self.persistence > 0.0 {
+            for (pixel, phosphor) in screen.iter_mut().zip(self.phosphors.iter()) {
+                let faded = (*phosphor as f32 * self.persistence) as u8;
+                *pixel = (*pixel).max(faded);
+            }
+        }
+        self.phosphors.copy_from_slice(&screen);
+
+        if self.blur {
+            blur(&mut screen, size);
+        }
+
+        match &self.overlay {
+            Overlay::Monochrome => {}
+            Overlay::Classic => machine.colorize(&mut screen),
+            Overlay::Deluxe => apply_overlay(&mut screen, size, DELUXE_OVERLAY),
+            Overlay::Image(image) => {
+                for (pixel, tint) in screen.chunks_exact_mut(4).zip(image.chunks_exact(4)) {
+                    for (channel, &tint) in pixel[..3].iter_mut().zip(tint) {
+                        *channel = (*channel as u16 * tint as u16 / 0xff) as u8;
+                    }
+                }
+            }
+        }
+
+        if let Some(background) = &self.background {
+            for (pixel, back) in screen.chunks_exact_mut(4).zip(background.chunks_exact(4)) {
+                for (channel, &back) in pixel[..3].iter_mut().zip(back) {
+                    *channel = channel.saturating_add(back);
+                }
+            }
+        }
+
+        self.output.clear();
+        if !self.scanlines {
+            self.output.extend_from_slice(&screen);
+            return &self.output;
+        }
+        let gap = |pixel: &[u8]| [pixel[0] / 2, pixel[1] / 2, pixel[2] / 2, 0xff];
+        for row in screen.chunks_exact(size.0 as usize * 4) {
+            match self.orientation {
+                // the monitor is rotated, so the scanlines are the columns of the screen
+                Orientation::Vertical => {
+                    let start = self.output.len();
+                    for pixel in row.chunks_exact(4) {
+                        self.output.extend_from_slice(pixel);
+                        self.output.extend_from_slice(&gap(pixel));
+                    }
+                    self.output.extend_from_within(start..);
+                }
+                Orientation::Horizontal => {
+                    for pixel in row.chunks_exact(4) {
+                        self.output.extend_from_slice(pixel);
+                        self.output.extend_from_slice(pixel);
+                    }
+                    for pixel in row.chunks_exact(4) {
+                        self.output.extend_from_slice(&gap(pixel));
+                        self.output.extend_from_slice(&gap(pixel));
+                    }
+                }
+            }
+        }
+        &self.output
+    }
+}
+
+/// Blur a RGBA screen of the given (width, height) with a 3x3 gaussian kernel.
+fn blur(screen: &mut [u8], (width, height): (u32, u32)) {
+    let (width, height) = (width as usize, height as usize);
+    let source = screen.to_vec();
+    let at = |x: usize, y: usize, c: usize| source[(y * width + x) * 4 + c] as u32;
+    for y in 0..height {
+        for x in 0..width {
+            let xs = [x.saturating_sub(1), x, (x + 1).min(width - 1)];
+            let ys = [y.saturating_sub(1), y, (y + 1).min(height - 1)];
+            for c in 0..3 {
+                let mut sum = 0;
+                for (i, &sy) in ys.iter().enumerate() {
+                    for (j, &sx) in xs.iter().enumerate() {
+                        let weight = [1, 2, 1][i] * [1, 2, 1][j];
+                        sum += at(sx, sy, c) * weight;
+                    }
+                }
+                screen[(y * width + x) * 4 + c] = (sum / 16) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! The Fujitsu MB14241, the hardware bit shifter of the Midway 8080 boards.
+//!
+//! The 8080 has no barrel shifter, so the board has a external one to draw the sprites
+//! at any pixel offset. It holds a 16 bit register: each byte written to the data port
+//! enters the high byte, moving the old high byte to the low byte. Reading the result
+//! returns the 8 bits starting `amount` bits below the top of the register.
+//!
+//! ```text
+//! register: HHHHHHHH LLLLLLLL
+//! amount 0: HHHHHHHH
+//! amount 3:    HHHHHLLL
+//! ```
+
+use intel8080::save_state::{self, SaveStateError};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mb14241 {
+    register: u16,
+    amount: u8,
+}
+impl Mb14241 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the shift amount. Only the 3 lower bits are connected.
+    pub fn set_amount(&mut self, value: u8) {
+        self.amount = value & 0b111;
+    }
+
+    /// Shift a new byte in the high byte of the register.
+    pub fn push(&mut self, value: u8) {
+        self.register = (self.register >> 8) | ((value as u16) << 8);
+    }
+
+    /// The 8 bits of the register, starting `amount` bits below the top.
+    pub fn result(&self) -> u8 {
+        (self.register >> (8 - self.amount)) as u8
+    }
+
+    pub fn register(&self) -> u16 {
+        self.register
+    }
+
+    pub fn amount(&self) -> u8 {
+        self.amount
+    }
+
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        save_state::write_u16(out, self.register);
+        save_state::write_u8(out, self.amount);
+    }
+
+    pub fn load_state(&mut self, input: &mut &[u8]) -> Result<(), SaveStateError> {
+        self.register = save_state::read_u16(input)?;
+        self.set_amount(save_state::read_u8(input)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_moves_the_high_byte_to_the_low_byte() {
+        let mut shifter = Mb14241::new();
+        shifter.push(0x12);
+        assert_eq!(shifter.register(), 0x1200);
+        shifter.push(0x34);
+        assert_eq!(shifter.register(), 0x3412);
+        shifter.push(0x56);
+        assert_eq!(shifter.register(), 0x5634);
+    }
+
+    #[test]
+    fn all_shift_amounts() {
+        let mut shifter = Mb14241::new();
+        shifter.push(0b1100_1010); // low byte
+        shifter.push(0b1011_0001); // high byte
+        let expected = [
+            0b1011_0001,
+            0b0110_0011,
+            0b1100_0111,
+            0b1000_1110,
+            0b0001_1100,
+            0b0011_1001,
+            0b0111_0010,
+            0b1110_0101,
+        ];
+        for (amount, &expected) in expected.iter().enumerate() {
+            shifter.set_amount(amount as u8);
+            assert_eq!(
+                shifter.result(),
+                expected,
+                "amount {}: {:08b} != {:08b}",
+                amount,
+                shifter.result(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn only_the_lower_bits_of_the_amount_are_used() {
+        let mut shifter = Mb14241::new();
+        shifter.push(0x0f);
+        shifter.push(0xf0);
+        shifter.set_amount(0b1111_1100);
+        assert_eq!(shifter.amount(), 4);
+        assert_eq!(shifter.result(), 0x00);
+        shifter.set_amount(0b0000_1010);
+        assert_eq!(shifter.result(), 0xc0);
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut shifter = Mb14241::new();
+        shifter.push(0xab);
+        shifter.push(0xcd);
+        shifter.set_amount(5);
+        let mut out = Vec::new();
+        shifter.save_state(&mut out);
+
+        let mut loaded = Mb14241::new();
+        loaded.load_state(&mut out.as_slice()).unwrap();
+        assert_eq!(loaded, shifter);
+    }
+}
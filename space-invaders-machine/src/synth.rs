@@ -0,0 +1,242 @@
+//! A approximation of the discrete sound circuits of the Space Invaders board.
+//!
+//! Each sound is a generator driven by the level of its bit in the output ports 3 and 5,
+//! so it starts, stops and overlaps with the others as the ROM drives the hardware,
+//! instead of playing a recorded sample on each rising edge.
+
+/// The frequencies of the four notes of the fleet march, in Hz.
+const FLEET_NOTES: [f32; 4] = [98.0, 87.3, 77.8, 73.4];
+
+/// The factor that decays a envelope by 1/e in `tau` seconds.
+fn decay(tau: f32, sample_rate: f32) -> f32 {
+    (-1.0 / (tau * sample_rate)).exp()
+}
+
+fn square(phase: f32) -> f32 {
+    if phase < 0.5 { 1.0 } else { -1.0 }
+}
+
+fn triangle(phase: f32) -> f32 {
+    4.0 * (phase - 0.5).abs() - 1.0
+}
+
+/// A oscillator phase, in cycles.
+#[derive(Default)]
+struct Phase(f32);
+impl Phase {
+    fn advance(&mut self, freq: f32, sample_rate: f32) -> f32 {
+        self.0 = (self.0 + freq / sample_rate).fract();
+        self.0
+    }
+}
+
+/// The white noise source shared by the explosions and the shot.
+struct Noise {
+    lfsr: u32,
+    value: f32,
+    phase: Phase,
+}
+impl Noise {
+    /// The rate at which the noise changes, in Hz.
+    const RATE: f32 = 8000.0;
+
+    fn new() -> Self {
+        Self {
+            lfsr: 1,
+            value: 0.0,
+            phase: Phase::default(),
+        }
+    }
+
+    fn sample(&mut self, sample_rate: f32) -> f32 {
+        let old = self.phase.0;
+        if self.phase.advance(Self::RATE, sample_rate) < old {
+            // 17 bit LFSR, with taps at 17 and 14
+            let bit = ((self.lfsr >> 16) ^ (self.lfsr >> 13)) & 1;
+            self.lfsr = ((self.lfsr << 1) | bit) & 0x1_ffff;
+            self.value = if self.lfsr & 1 != 0 { 1.0 } else { -1.0 };
+        }
+        self.value
+    }
+}
+
+/// A envelope that is reset by the rising edge of its bit, decays slowly while the bit is
+/// high, and quickly after it goes low.
+struct Envelope {
+    level: f32,
+    hold_decay: f32,
+    release_decay: f32,
+}
+impl Envelope {
+    fn new(hold: f32, release: f32, sample_rate: f32) -> Self {
+        Self {
+            level: 0.0,
+            hold_decay: decay(hold, sample_rate),
+            release_decay: decay(release, sample_rate),
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.level = 1.0;
+    }
+
+    fn next(&mut self, high: bool) -> f32 {
+        self.level *= if high {
+            self.hold_decay
+        } else {
+            self.release_decay
+        };
+        self.level
+    }
+}
+
+pub struct Synth {
+    sample_rate: f32,
+    port3: u8,
+    port5: u8,
+    noise: Noise,
+
+    ufo: Phase,
+    ufo_lfo: Phase,
+    shot: Envelope,
+    shot_phase: Phase,
+    shot_time: f32,
+    player_die: Envelope,
+    invader_die: Envelope,
+    invader_die_phase: Phase,
+    extra_life: Phase,
+    extra_life_lfo: Phase,
+    fleet: [Envelope; 4],
+    fleet_phase: [Phase; 4],
+    ufo_hit: Envelope,
+    ufo_hit_phase: Phase,
+    ufo_hit_lfo: Phase,
+}
+impl Synth {
+    pub fn new(sample_rate: u32) -> Self {
+        let sr = sample_rate as f32;
+        Self {
+            sample_rate: sr,
+            port3: 0,
+            port5: 0,
+            noise: Noise::new(),
+
+            ufo: Phase::default(),
+            ufo_lfo: Phase::default(),
+            shot: Envelope::new(0.25, 0.02, sr),
+            shot_phase: Phase::default(),
+            shot_time: 0.0,
+            player_die: Envelope::new(1.0, 0.05, sr),
+            invader_die: Envelope::new(0.1, 0.1, sr),
+            invader_die_phase: Phase::default(),
+            extra_life: Phase::default(),
+            extra_life_lfo: Phase::default(),
+            fleet: std::array::from_fn(|_| Envelope::new(0.08, 0.08, sr)),
+            fleet_phase: Default::default(),
+            ufo_hit: Envelope::new(1.0, 0.03, sr),
+            ufo_hit_phase: Phase::default(),
+            ufo_hit_lfo: Phase::default(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    /// Update the levels of the sound ports, as latched by the last writes to ports 3 and 5.
+    pub fn set_ports(&mut self, port3: u8, port5: u8) {
+        let rising3 = port3 & !self.port3;
+        let rising5 = port5 & !self.port5;
+        self.port3 = port3;
+        self.port5 = port5;
+
+        if rising3 & 0b0000_0010 != 0 {
+            self.shot.trigger();
+            self.shot_time = 0.0;
+        }
+        if rising3 & 0b0000_0100 != 0 {
+            self.player_die.trigger();
+        }
+        if rising3 & 0b0000_1000 != 0 {
+            self.invader_die.trigger();
+        }
+        for (i, envelope) in self.fleet.iter_mut().enumerate() {
+            if rising5 & (1 << i) != 0 {
+                envelope.trigger();
+            }
+        }
+        if rising5 & 0b0001_0000 != 0 {
+            self.ufo_hit.trigger();
+        }
+    }
+
+    /// Generate the next sample, in the range -1.0 to 1.0.
+    pub fn next_sample(&mut self) -> f32 {
+        let sr = self.sample_rate;
+        let bit3 = |i: u8| self.port3 & (1 << i) != 0;
+        let bit5 = |i: u8| self.port5 & (1 << i) != 0;
+        let (ufo_on, shot_on, player_die_on, invader_die_on, extra_life_on) =
+            (bit3(0), bit3(1), bit3(2), bit3(3), bit3(4));
+        let (fleet_on, ufo_hit_on) = ([bit5(0), bit5(1), bit5(2), bit5(3)], bit5(4));
+
+        let noise = self.noise.sample(sr);
+        let mut out = 0.0;
+
+        // ufo: a warbling tone, while the bit is high
+        if ufo_on {
+            let lfo = triangle(self.ufo_lfo.advance(6.0, sr));
+            out += 0.3 * triangle(self.ufo.advance(650.0 + 250.0 * lfo, sr));
+        }
+
+        // shot: a descending tone over noise
+        let env = self.shot.next(shot_on);
+        if env > 0.001 {
+            self.shot_time += 1.0 / sr;
+            let freq = 400.0 + 800.0 * (-self.shot_time / 0.1).exp();
+            let tone = square(self.shot_phase.advance(freq, sr));
+            out += 0.25 * env * (0.6 * tone + 0.4 * noise);
+        }
+
+        // player explosion: noise, while the bit is high
+        out += 0.5 * self.player_die.next(player_die_on) * noise;
+
+        // invader explosion: a short burst of noise and a low tone
+        let env = self.invader_die.next(invader_die_on);
+        if env > 0.001 {
+            let tone = triangle(self.invader_die_phase.advance(180.0, sr));
+            out += 0.4 * env * (0.5 * noise + 0.5 * tone);
+        }
+
+        // extra life: a beeping tone, while the bit is high
+        if extra_life_on {
+            let gate = square(self.extra_life_lfo.advance(8.0, sr)).max(0.0);
+            out += 0.2 * gate * square(self.extra_life.advance(1200.0, sr));
+        }
+
+        // fleet march: four low thumps
+        for i in 0..4 {
+            let env = self.fleet[i].next(fleet_on[i]);
+            if env > 0.001 {
+                let tone = triangle(self.fleet_phase[i].advance(FLEET_NOTES[i], sr));
+                out += 0.6 * env * tone;
+            }
+        }
+
+        // ufo hit: a fast warble, while the bit is high
+        let env = self.ufo_hit.next(ufo_hit_on);
+        if env > 0.001 {
+            let lfo = triangle(self.ufo_hit_lfo.advance(15.0, sr));
+            out += 0.3 * env * square(self.ufo_hit_phase.advance(900.0 + 600.0 * lfo, sr));
+        }
+
+        out.clamp(-1.0, 1.0)
+    }
+}
+
+impl Iterator for Synth {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        Some(self.next_sample())
+    }
+}
@@ -0,0 +1,45 @@
+//! The watchdog of the board, that resets the machine if the ROM stops writing to port 6.
+
+use intel8080::save_state::{self, SaveStateError};
+
+/// The number of frames without a write to port 6 before the watchdog fires.
+pub const WATCHDOG_FRAMES: u32 = 255;
+
+#[derive(Debug, Clone, Default)]
+pub struct Watchdog {
+    /// Frames since the last kick.
+    frames: u32,
+}
+impl Watchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance one frame. Return true if the watchdog fired, because it was not kicked in
+    /// the last `WATCHDOG_FRAMES` frames.
+    pub fn tick(&mut self, kicked: bool) -> bool {
+        if kicked {
+            self.frames = 0;
+            return false;
+        }
+        self.frames += 1;
+        if self.frames >= WATCHDOG_FRAMES {
+            self.frames = 0;
+            return true;
+        }
+        false
+    }
+
+    pub fn save_state(&self, out: &mut Vec<u8>) {
+        save_state::write_u32(out, self.frames);
+    }
+
+    pub fn load_state(&mut self, input: &mut &[u8]) -> Result<(), SaveStateError> {
+        let frames = save_state::read_u32(input)?;
+        if frames >= WATCHDOG_FRAMES {
+            return Err(SaveStateError::Invalid("the watchdog is past its timeout"));
+        }
+        self.frames = frames;
+        Ok(())
+    }
+}
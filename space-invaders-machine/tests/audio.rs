@@ -0,0 +1,141 @@
+//! Check that the mixer applies the writes to the sound ports at the sample of their
+//! timestamp, and keeps pace with the emulated clock.
+
+use intel8080::IODevices;
+use space_invaders_machine::{
+    CPU_CLOCK, MAX_SOUND_EVENTS, SoundEvent, SpaceInvaders, SpaceInvadersDevices,
+    audio::{Mixer, SAMPLE_RATE, SoundSource},
+    idle_rom,
+};
+
+/// The amplifier enable bit of port 3.
+const AMPLIFIER: u8 = 0b0010_0000;
+
+/// Output the value of port 3, without the amplifier bit, to see where each write was
+/// applied.
+struct PortLevel(u8);
+impl SoundSource for PortLevel {
+    fn set_ports(&mut self, port3: u8, _port5: u8) {
+        self.0 = port3;
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        (self.0 & !AMPLIFIER) as f32
+    }
+}
+
+#[test]
+fn writes_are_sample_accurate() {
+    let mut mixer = Mixer::new(Box::new(PortLevel(0)), 0);
+    // one write every 1000 clocks, in two batches
+    mixer.mix_until(10_000, (1..10).map(|i| (i * 1000, AMPLIFIER | i as u8, 0)));
+    mixer.mix_until(20_000, (10..20).map(|i| (i * 1000, AMPLIFIER | i as u8, 0)));
+    let samples: Vec<f32> = mixer.drain().collect();
+
+    assert_eq!(
+        samples.len() as u64,
+        (20_000 * SAMPLE_RATE as u64).div_ceil(CPU_CLOCK)
+    );
+    for (i, &sample) in samples.iter().enumerate() {
+        let clock = i as u64 * CPU_CLOCK / SAMPLE_RATE as u64;
+        assert_eq!(
+            sample,
+            (clock / 1000) as f32,
+            "sample {} at clock {}",
+            i,
+            clock
+        );
+    }
+}
+
+#[test]
+fn samples_follow_the_emulated_time() {
+    let mut machine = SpaceInvaders::new(&idle_rom(0x2000));
+    machine.enable_audio(Box::new(PortLevel(0)));
+
+    let mut count = 0;
+    for _ in 0..60 {
+        machine.step_frame();
+        count += machine.drain_samples().count() as u64;
+    }
+    let clocks = machine.interpreter().clock_count();
+    let expected = clocks * SAMPLE_RATE as u64 / CPU_CLOCK;
+    assert!(
+        count.abs_diff(expected) <= 1,
+        "{} samples, expected {}",
+        count,
+        expected
+    );
+}
+
+#[test]
+fn amplifier_mutes_the_sound() {
+    let mut mixer = Mixer::new(Box::new(PortLevel(0)), 0);
+    mixer.mix_until(10_000, [(0, 0b1, 0), (5000, AMPLIFIER | 0b1, 0)]);
+    let samples: Vec<f32> = mixer.drain().collect();
+
+    for (i, &sample) in samples.iter().enumerate() {
+        let clock = i as u64 * CPU_CLOCK / SAMPLE_RATE as u64;
+        let expected = if clock < 5000 { 0.0 } else { 1.0 };
+        assert_eq!(sample, expected, "sample {} at clock {}", i, clock);
+    }
+}
+
+#[test]
+fn port5_sounds_follow_their_own_latch() {
+    let mut devices = SpaceInvadersDevices::new([0; 3]);
+    let events = |devices: &mut SpaceInvadersDevices| -> Vec<SoundEvent> {
+        devices.drain_sound_events().collect()
+    };
+
+    devices.write(3, AMPLIFIER | 0b1);
+    assert_eq!(events(&mut devices), [SoundEvent::StartUfo]);
+
+    // a fleet note, while port 3 bit 0 is set
+    devices.write(5, 0b1);
+    assert_eq!(events(&mut devices), [SoundEvent::Play(4)]);
+    devices.write(5, 0b1);
+    assert_eq!(events(&mut devices), []);
+    devices.write(5, 0b0);
+    devices.write(5, 0b1);
+    assert_eq!(events(&mut devices), [SoundEvent::Play(4)]);
+}
+
+#[test]
+fn amplifier_mutes_the_sound_events() {
+    let mut devices = SpaceInvadersDevices::new([0; 3]);
+
+    devices.write(3, 0b1);
+    devices.write(3, 0b10);
+    devices.write(5, 0b1);
+    assert_eq!(devices.drain_sound_events().count(), 0);
+
+    // the ufo is heard when the amplifier is enabled
+    devices.write(3, AMPLIFIER | 0b1);
+    devices.write(3, 0b1);
+    let events: Vec<_> = devices.drain_sound_events().collect();
+    assert_eq!(events, [SoundEvent::StartUfo, SoundEvent::StopUfo]);
+}
+
+#[test]
+fn undrained_sound_events_are_capped() {
+    let mut devices = SpaceInvadersDevices::new([0; 3]);
+    devices.write(3, AMPLIFIER | 0b1);
+    for _ in 0..1000 {
+        devices.write(5, 0b1);
+        devices.write(5, 0b0);
+    }
+    let events: Vec<_> = devices.drain_sound_events().collect();
+    assert!(events.len() <= MAX_SOUND_EVENTS);
+    // the ufo loop is still playing
+    assert_eq!(events[0], SoundEvent::StartUfo);
+    assert!(
+        events[1..]
+            .iter()
+            .all(|&event| event == SoundEvent::Play(4))
+    );
+
+    devices.write(3, AMPLIFIER);
+    let events: Vec<_> = devices.drain_sound_events().collect();
+    assert_eq!(events, [SoundEvent::StopUfo]);
+}
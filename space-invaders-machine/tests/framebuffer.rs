@@ -0,0 +1,148 @@
+//! Boot the ROM without a window, play a scripted input sequence, and compare the
+//! rendered frames against the golden images in `tests/goldens`.
+//!
+//! Run with `UPDATE_GOLDENS=1` to write the current frames as the new goldens.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use space_invaders_machine::{Input, Player, SCREEN_HEIGHT, SCREEN_WIDTH, SpaceInvaders};
+
+fn load_rom() -> Vec<u8> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../rom");
+    let mut rom = Vec::with_capacity(0x2000);
+    for e in ['h', 'g', 'f', 'e'] {
+        let path = dir.join(format!("invaders.{}", e));
+        rom.extend(fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)));
+    }
+    rom
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/goldens")
+        .join(format!("{}.png", name))
+}
+
+fn write_png(path: &Path, rgba: &[u8]) {
+    let file = fs::File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(file, SCREEN_WIDTH, SCREEN_HEIGHT);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .unwrap()
+        .write_image_data(rgba)
+        .unwrap();
+}
+
+fn read_png(path: &Path) -> Vec<u8> {
+    let decoder = png::Decoder::new(fs::File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+    assert_eq!(
+        (info.width, info.height, info.color_type),
+        (SCREEN_WIDTH, SCREEN_HEIGHT, png::ColorType::Rgba),
+        "{} has the wrong format",
+        path.display()
+    );
+    buf.truncate(info.buffer_size());
+    buf
+}
+
+/// Compare the framebuffer with the golden image, or overwrite it in update mode.
+fn check_golden(name: &str, framebuffer: &[u8]) {
+    let path = golden_path(name);
+    if std::env::var_os("UPDATE_GOLDENS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_png(&path, framebuffer);
+        return;
+    }
+
+    assert!(
+        path.exists(),
+        "missing golden {}, run the tests with UPDATE_GOLDENS=1 to create it",
+        path.display()
+    );
+    let golden = read_png(&path);
+    if golden != framebuffer {
+        let actual = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", name));
+        write_png(&actual, framebuffer);
+        let diff = golden
+            .chunks_exact(4)
+            .zip(framebuffer.chunks_exact(4))
+            .filter(|(a, b)| a != b)
+            .count();
+        panic!(
+            "frame '{}' differs from the golden in {} pixels, the actual frame was written to {}",
+            name,
+            diff,
+            actual.display()
+        );
+    }
+}
+
+/// A scripted session: inputs pressed or released at given frames, and frames that are
+/// checked against goldens.
+enum Step {
+    Press(Input),
+    Release(Input),
+    Check(&'static str),
+}
+
+fn run_script(frames: u32, script: &[(u32, Step)]) {
+    let mut machine = SpaceInvaders::new(&load_rom());
+    let mut script = script.iter().peekable();
+    for frame in 0..frames {
+        while let Some((_, step)) = script.next_if(|(f, _)| *f == frame) {
+            match step {
+                Step::Press(input) => machine.set_input(*input, true),
+                Step::Release(input) => machine.set_input(*input, false),
+                Step::Check(name) => check_golden(name, machine.framebuffer()),
+            }
+        }
+        machine.step_frame();
+    }
+    assert!(script.next().is_none(), "script steps after the last frame");
+}
+
+#[test]
+fn attract_mode() {
+    run_script(
+        1201,
+        &[
+            (120, Step::Check("attract_title")),
+            (600, Step::Check("attract_score_table")),
+            (1200, Step::Check("attract_demo")),
+        ],
+    );
+}
+
+#[test]
+fn one_player_game() {
+    use Input::*;
+    use Step::*;
+    run_script(
+        701,
+        &[
+            (100, Press(Coin)),
+            (105, Release(Coin)),
+            (200, Check("coin_inserted")),
+            (220, Press(P1Start)),
+            (225, Release(P1Start)),
+            (400, Check("game_start")),
+            (420, Press(Left(Player::One))),
+            (470, Release(Left(Player::One))),
+            (480, Press(Fire(Player::One))),
+            (485, Release(Fire(Player::One))),
+            (500, Press(Right(Player::One))),
+            (600, Release(Right(Player::One))),
+            (610, Press(Fire(Player::One))),
+            (615, Release(Fire(Player::One))),
+            (700, Check("game_player_moved")),
+        ],
+    );
+}
@@ -0,0 +1,122 @@
+//! Check that the definitions of the games build a valid board.
+
+use intel8080::{IODevices, Memory};
+use space_invaders_machine::{
+    COLOR_MAP_LEN, DIP_SWITCHES_MASK, DipSwitches, Input, Player, SCREEN_HEIGHT, SCREEN_WIDTH,
+    SpaceInvaders, VIDEO_RAM, VIDEO_RAM_LEN, idle_rom,
+    machines::{GUNFIGHT, INVADERS, INVADPT2, MACHINES},
+};
+
+#[test]
+fn all_machines_run() {
+    for &machine in MACHINES {
+        let mut board = SpaceInvaders::with_machine(machine, &idle_rom(machine.rom_len()));
+        board.step_frame();
+        board.reset();
+        board.step_frame();
+        assert_eq!(board.machine().name, machine.name);
+    }
+}
+
+#[test]
+fn rom_chips_do_not_overlap_the_ram() {
+    for machine in MACHINES {
+        for file in machine.roms {
+            let (start, end) = (file.adress as u32, file.adress as u32 + file.len as u32);
+            for &(ram, len) in machine.ram {
+                let (ram, ram_end) = (ram as u32, ram as u32 + len);
+                assert!(
+                    end <= ram || start >= ram_end,
+                    "{}: {} overlaps the RAM",
+                    machine.name,
+                    file.name
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn inputs_follow_the_controls_of_the_game() {
+    let mut board = SpaceInvaders::with_machine(&GUNFIGHT, &idle_rom(GUNFIGHT.rom_len()));
+    let idle = board.read_ports();
+
+    // the triggers are active low, and the coin active high
+    board.set_input(Input::Fire(Player::One), true);
+    board.set_input(Input::Coin, true);
+    assert_eq!(board.read_ports()[0], idle[0] & !0b1000_0000);
+    assert_eq!(board.read_ports()[2], idle[2] | 0b0100_0000);
+    board.set_input(Input::Fire(Player::One), false);
+    board.set_input(Input::Coin, false);
+    assert_eq!(board.read_ports(), idle);
+
+    // Gun Fight has no tilt switch, and its dip switches are not the ones of Space Invaders
+    board.set_input(Input::Tilt, true);
+    board.set_dip_switches(DipSwitches::from_bits(DIP_SWITCHES_MASK));
+    assert_eq!(board.read_ports(), idle);
+
+    // Space Invaders has no up and down
+    let mut board = SpaceInvaders::with_machine(&INVADERS, &idle_rom(INVADERS.rom_len()));
+    let idle = board.read_ports();
+    board.set_input(Input::Up(Player::One), true);
+    assert_eq!(board.read_ports(), idle);
+}
+
+#[test]
+fn horizontal_monitor_shows_the_video_ram_as_scanned() {
+    let mut board = SpaceInvaders::with_machine(&GUNFIGHT, &idle_rom(GUNFIGHT.rom_len()));
+    assert_eq!(board.screen_size(), (256, 224));
+    // the first pixel of the second line
+    board
+        .interpreter_mut()
+        .memory
+        .write(VIDEO_RAM + 32, 0b0000_0001);
+    board.step_frame();
+
+    let lit: Vec<usize> = board
+        .framebuffer()
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(_, pixel)| pixel[0] != 0)
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(lit, [256]);
+}
+
+#[test]
+fn board_without_watchdog_is_never_reset() {
+    let mut board = SpaceInvaders::with_machine(&GUNFIGHT, &idle_rom(GUNFIGHT.rom_len()));
+    board.set_watchdog(true);
+    for _ in 0..300 {
+        board.step_frame();
+        assert!(!board.watchdog_fired());
+    }
+}
+
+#[test]
+fn color_map_colours_the_cells_of_the_video_ram() {
+    let mut board = SpaceInvaders::with_machine(&INVADPT2, &idle_rom(INVADPT2.rom_len()));
+    // the first map is red, with a green cell above the bottom left one, and the second
+    // map is blue
+    let mut color_map = vec![0b001; INVADPT2.color_map_len()];
+    color_map[1] = 0b100;
+    color_map[COLOR_MAP_LEN..].fill(0b010);
+    board.set_color_map(&color_map);
+    for i in 0..VIDEO_RAM_LEN as u16 {
+        board.interpreter_mut().memory.write(VIDEO_RAM + i, 0xff);
+    }
+
+    let pixel = |board: &SpaceInvaders, x: u32, y: u32| {
+        let p = ((y * SCREEN_WIDTH + x) * 4) as usize;
+        board.framebuffer()[p..p + 4].to_vec()
+    };
+    board.step_frame();
+    assert_eq!(pixel(&board, 0, SCREEN_HEIGHT - 1), [0xff, 0, 0, 0xff]);
+    assert_eq!(pixel(&board, 0, SCREEN_HEIGHT - 9), [0, 0xff, 0, 0xff]);
+    assert_eq!(pixel(&board, 8, SCREEN_HEIGHT - 9), [0xff, 0, 0, 0xff]);
+
+    // the game selects the second map with bit 5 of port 5
+    board.interpreter_mut().devices.write(5, 0b0010_0000);
+    board.step_frame();
+    assert_eq!(pixel(&board, 0, SCREEN_HEIGHT - 9), [0, 0, 0xff, 0xff]);
+}
@@ -0,0 +1,111 @@
+//! Check the presentation modes against the plain framebuffer.
+
+use intel8080::Memory;
+use space_invaders_machine::{
+    SCREEN_HEIGHT, SCREEN_WIDTH, SpaceInvaders, VIDEO_RAM, idle_rom,
+    machines::GUNFIGHT,
+    presentation::{Overlay, Presentation},
+};
+
+/// A machine stuck in a loop, with some pixels lit in each colour band.
+fn machine() -> SpaceInvaders {
+    let mut machine = SpaceInvaders::new(&idle_rom(0x2000));
+    for i in 0..0x1c00 {
+        machine
+            .interpreter_mut()
+            .memory
+            .write(VIDEO_RAM + i, if i % 3 == 0 { 0xff } else { 0 });
+    }
+    machine.step_frame();
+    machine
+}
+
+#[test]
+fn classic_is_the_framebuffer() {
+    let machine = machine();
+    let mut presentation = Presentation::new();
+    assert_eq!(presentation.present(&machine), machine.framebuffer());
+}
+
+#[test]
+fn monochrome_has_no_colour() {
+    let machine = machine();
+    let mut presentation = Presentation::new();
+    presentation.overlay = Overlay::Monochrome;
+    let rgba = presentation.present(&machine);
+    assert!(rgba.chunks(4).all(|p| p[0] == p[1] && p[1] == p[2]));
+    assert!(rgba.chunks(4).any(|p| p[0] == 0xff));
+}
+
+#[test]
+fn scanlines_double_the_size() {
+    let machine = machine();
+    let mut presentation = Presentation::new();
+    presentation.scanlines = true;
+    let (width, height) = (presentation.width(), presentation.height());
+    assert_eq!((width, height), (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2));
+    let rgba = presentation.present(&machine).to_vec();
+    assert_eq!(rgba.len(), (width * height * 4) as usize);
+
+    // the odd columns are the gaps between the scanlines
+    let framebuffer = machine.framebuffer();
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let source = ((y / 2) * SCREEN_WIDTH as usize + x / 2) * 4;
+            let expected = if x % 2 == 0 {
+                framebuffer[source]
+            } else {
+                framebuffer[source] / 2
+            };
+            assert_eq!(rgba[(y * width as usize + x) * 4], expected);
+        }
+    }
+}
+
+#[test]
+fn persistence_keeps_the_last_frame() {
+    let mut machine = machine();
+    let mut presentation = Presentation::new();
+    presentation.overlay = Overlay::Monochrome;
+    presentation.persistence = 0.5;
+    presentation.present(&machine);
+
+    for i in 0..0x1c00 {
+        machine.interpreter_mut().memory.write(VIDEO_RAM + i, 0);
+    }
+    machine.step_frame();
+    let rgba = presentation.present(&machine);
+    assert!(rgba.chunks(4).any(|p| p[0] == 0x7f));
+    assert!(rgba.chunks(4).all(|p| p[0] == 0x7f || p[0] == 0));
+}
+
+#[test]
+fn horizontal_scanlines_are_the_rows() {
+    let mut machine = SpaceInvaders::with_machine(&GUNFIGHT, &idle_rom(GUNFIGHT.rom_len()));
+    for i in 0..0x1c00 {
+        machine
+            .interpreter_mut()
+            .memory
+            .write(VIDEO_RAM + i, if i % 3 == 0 { 0xff } else { 0 });
+    }
+    machine.step_frame();
+    let mut presentation = Presentation::for_machine(&GUNFIGHT);
+    presentation.scanlines = true;
+    let (width, height) = (presentation.width(), presentation.height());
+    assert_eq!((width, height), (256 * 2, 224 * 2));
+    let rgba = presentation.present(&machine).to_vec();
+
+    // the odd rows are the gaps between the scanlines
+    let framebuffer = machine.framebuffer();
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            let source = ((y / 2) * 256 + x / 2) * 4;
+            let expected = if y % 2 == 0 {
+                framebuffer[source]
+            } else {
+                framebuffer[source] / 2
+            };
+            assert_eq!(rgba[(y * width as usize + x) * 4], expected);
+        }
+    }
+}
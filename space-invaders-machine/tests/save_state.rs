@@ -0,0 +1,197 @@
+//! The save states of the machine restore the state around the interpreter: the frame
+//! stopped by the debugger, the movie position and the audio alignment.
+
+use std::{fs, path::Path};
+
+use intel8080::{IODevices, save_state::SaveStateError};
+use space_invaders_machine::{Input, MACHINE_TAG, SpaceInvaders, audio::SoundSource, movie::Movie};
+
+fn load_rom() -> Vec<u8> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../rom");
+    let mut rom = Vec::with_capacity(0x2000);
+    for e in ['h', 'g', 'f', 'e'] {
+        rom.extend(fs::read(dir.join(format!("invaders.{}", e))).unwrap());
+    }
+    rom
+}
+
+struct Silence;
+impl SoundSource for Silence {
+    fn set_ports(&mut self, _port3: u8, _port5: u8) {}
+
+    fn next_sample(&mut self) -> f32 {
+        0.0
+    }
+}
+
+/// Insert a coin and start a game, so the frames change with the inputs.
+fn play(machine: &mut SpaceInvaders, frames: u32) {
+    for frame in 0..frames {
+        machine.set_input(Input::Coin, frame % 200 == 10);
+        machine.set_input(Input::P1Start, frame % 200 == 60);
+        machine.step_frame();
+    }
+}
+
+#[test]
+fn loaded_machine_runs_like_the_saved_one() {
+    let mut machine = SpaceInvaders::new(&load_rom());
+    machine.enable_audio(Box::new(Silence));
+    play(&mut machine, 100);
+    let saved = machine.save_state();
+
+    play(&mut machine, 100);
+    let framebuffer = machine.framebuffer().to_vec();
+    let state = machine.save_state();
+    let samples = machine.drain_samples().count();
+
+    machine.load_state(&saved).unwrap();
+    assert_eq!(machine.save_state(), saved);
+    machine.drain_samples().for_each(drop);
+    play(&mut machine, 100);
+    assert_eq!(machine.framebuffer(), framebuffer);
+    assert_eq!(machine.save_state(), state);
+    // the audio is not restarted, so the loaded frames have the same samples
+    assert_eq!(machine.drain_samples().count(), samples);
+}
+
+#[test]
+fn interpreter_state_is_rejected() {
+    let mut machine = SpaceInvaders::new(&load_rom());
+    play(&mut machine, 10);
+    let interpreter_only = machine.interpreter().save_state();
+    let current = machine.save_state();
+
+    assert!(matches!(
+        machine.load_state(&interpreter_only),
+        Err(SaveStateError::MissingSection(tag)) if tag == MACHINE_TAG
+    ));
+    assert_eq!(machine.save_state(), current);
+}
+
+#[test]
+fn older_version_is_rejected() {
+    let mut machine = SpaceInvaders::new(&load_rom());
+    let mut saved = machine.save_state();
+    // version 3 had no watchdog state
+    saved[8..10].copy_from_slice(&3u16.to_le_bytes());
+    assert!(matches!(
+        machine.load_state(&saved),
+        Err(SaveStateError::UnsupportedVersion(3))
+    ));
+}
+
+#[test]
+fn truncated_state_leaves_the_machine_untouched() {
+    let mut machine = SpaceInvaders::new(&load_rom());
+    play(&mut machine, 10);
+    let saved = machine.save_state();
+    play(&mut machine, 10);
+    let current = machine.save_state();
+
+    // only the lengths that cut the machine section, the others are tested in intel8080
+    let interpreter_len = machine.interpreter().save_state().len();
+    for len in interpreter_len..saved.len() {
+        assert!(machine.load_state(&saved[..len]).is_err(), "len {}", len);
+        assert_eq!(machine.save_state(), current, "len {}", len);
+    }
+}
+
+#[test]
+fn devices_section_with_trailing_bytes_is_rejected() {
+    let mut machine = SpaceInvaders::new(&load_rom());
+    play(&mut machine, 10);
+    let devices = &mut machine.interpreter_mut().devices;
+    let mut section = Vec::new();
+    devices.save_state(&mut section);
+
+    section.push(0);
+    assert!(matches!(
+        devices.load_state(&section),
+        Err(SaveStateError::Invalid(_))
+    ));
+    section.pop();
+    assert!(devices.load_state(&section).is_ok());
+}
+
+#[test]
+fn loading_rewinds_the_recording() {
+    let mut machine = SpaceInvaders::new(&load_rom());
+    machine.start_recording();
+    play(&mut machine, 50);
+    let saved = machine.save_state();
+    play(&mut machine, 50);
+
+    machine.load_state(&saved).unwrap();
+    play(&mut machine, 30);
+    let movie = machine.stop_recording().unwrap();
+    assert_eq!(movie.frames.len(), 80);
+}
+
+#[test]
+fn loading_seeks_the_movie() {
+    let mut machine = SpaceInvaders::new(&load_rom());
+    machine.start_recording();
+    play(&mut machine, 300);
+    let movie: Movie = machine.stop_recording().unwrap();
+
+    machine.play_movie(movie.clone()).unwrap();
+    for _ in 0..100 {
+        machine.step_frame();
+    }
+    let saved = machine.save_state();
+    for _ in 0..200 {
+        machine.step_frame();
+    }
+    let framebuffer = machine.framebuffer().to_vec();
+
+    // the replay continues from the frame of the state
+    machine.load_state(&saved).unwrap();
+    for _ in 0..200 {
+        machine.step_frame();
+    }
+    assert_eq!(machine.framebuffer(), framebuffer);
+    machine.step_frame();
+    assert!(!machine.is_playing_movie());
+
+    // a shorter movie can't seek to the frame of the state
+    let mut short = movie;
+    short.frames.truncate(50);
+    machine.play_movie(short).unwrap();
+    assert!(matches!(
+        machine.load_state(&saved),
+        Err(SaveStateError::Invalid(_))
+    ));
+}
+
+#[cfg(feature = "debug")]
+#[test]
+fn stopped_frame_is_restored() {
+    use intel8080::debugger::Command;
+
+    let mut machine = SpaceInvaders::new(&load_rom());
+    play(&mut machine, 100);
+
+    // stop in the middle of a frame, at the RST 1 handler
+    machine
+        .interpreter_mut()
+        .debug_command(Command::AddBreakpoint(0x0008));
+    machine.step_frame();
+    assert!(machine.interpreter().is_stopped());
+    let saved = machine.save_state();
+
+    machine
+        .interpreter_mut()
+        .debug_command(Command::RemoveBreakpoint(0x0008));
+    machine.interpreter_mut().debug_command(Command::Continue);
+    machine.step_frame();
+    play(&mut machine, 1);
+    let (framebuffer, state) = (machine.framebuffer().to_vec(), machine.save_state());
+
+    // the loaded state finishes the stopped frame, instead of starting a new one
+    machine.load_state(&saved).unwrap();
+    machine.step_frame();
+    play(&mut machine, 1);
+    assert_eq!(machine.framebuffer(), framebuffer);
+    assert_eq!(machine.save_state(), state);
+}
@@ -0,0 +1,38 @@
+use intel8080::{NoDevices, interpreter::Interpreter, memory_map::MemoryMap};
+use space_invaders_machine::{CPU_CLOCK, FRAME_RATE, create_scheduler};
+
+const CLOCKS_PER_SCANLINE: u64 = 128;
+const SCANLINES_PER_FRAME: u64 = 262;
+
+#[test]
+fn frame_has_262_lines_of_128_clocks() {
+    let scheduler = create_scheduler();
+    assert_eq!(
+        scheduler.frame_length() as u64,
+        SCANLINES_PER_FRAME * CLOCKS_PER_SCANLINE
+    );
+    assert_eq!(CPU_CLOCK, 1_996_800);
+    assert!((FRAME_RATE - 59.54).abs() < 0.01);
+}
+
+#[test]
+fn interrupts_fire_at_line_96_and_224_of_each_frame() {
+    // NOPs, forever
+    let memory = MemoryMap::builder()
+        .rom(0x0000, vec![0x00; 0x2000])
+        .build()
+        .unwrap();
+    let mut interpreter = Interpreter::new(NoDevices, memory, &[0x0000]);
+    let mut scheduler = create_scheduler();
+
+    for frame in 0..3 {
+        let frame_start = frame * SCANLINES_PER_FRAME * CLOCKS_PER_SCANLINE;
+        let mut events = Vec::new();
+        assert!(scheduler.run_frame(&mut interpreter, |interpreter, &rst| {
+            let clock = interpreter.clock_count() - frame_start;
+            events.push((clock / CLOCKS_PER_SCANLINE, rst));
+        }));
+        // RST 1, then RST 2
+        assert_eq!(events, [(96, 0xcf), (224, 0xd7)], "frame {}", frame);
+    }
+}
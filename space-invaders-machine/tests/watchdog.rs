@@ -0,0 +1,101 @@
+//! The watchdog resets a hung ROM, but not one that keeps writing to port 6.
+
+use std::{fs, path::Path};
+
+use intel8080::Memory;
+
+use space_invaders_machine::{SpaceInvaders, idle_rom, watchdog::WATCHDOG_FRAMES};
+
+#[test]
+fn hung_rom_is_reset() {
+    let mut machine = SpaceInvaders::new(&idle_rom(0x2000));
+    machine.set_watchdog(true);
+
+    for frame in 1..=2 * WATCHDOG_FRAMES {
+        machine.step_frame();
+        assert_eq!(
+            machine.watchdog_fired(),
+            frame % WATCHDOG_FRAMES == 0,
+            "frame {}",
+            frame
+        );
+        // the reset stops the cpu in the debugger
+        #[cfg(feature = "debug")]
+        if machine.watchdog_fired() {
+            assert_eq!(
+                machine.interpreter().debugger().stop_reason(),
+                Some(intel8080::interpreter::StopReason::Watchdog)
+            );
+            machine
+                .interpreter_mut()
+                .debug_command(intel8080::debugger::Command::Continue);
+        }
+    }
+}
+
+#[test]
+fn watchdog_resets_only_the_cpu() {
+    let mut machine = SpaceInvaders::new(&idle_rom(0x2000));
+    machine.set_watchdog(true);
+    machine.interpreter_mut().memory.write(0x2000, 0x5a);
+    machine.interpreter_mut().state_mut().set_SP(0x2400);
+    while !machine.watchdog_fired() {
+        machine.step_frame();
+    }
+    // a power cycle would clear the RAM
+    assert_eq!(machine.interpreter().memory.read(0x2000), 0x5a);
+    assert_eq!(machine.interpreter().state().get_PC(), 0x0000);
+    assert_eq!(machine.interpreter().state().get_SP(), 0x0000);
+}
+
+#[test]
+fn kicked_watchdog_does_not_fire() {
+    // OUT 6; JMP 0x0000
+    let mut rom = idle_rom(0x2000);
+    rom[..5].copy_from_slice(&[0xd3, 0x06, 0xc3, 0x00, 0x00]);
+    let mut machine = SpaceInvaders::new(&rom);
+    machine.set_watchdog(true);
+
+    for _ in 0..2 * WATCHDOG_FRAMES {
+        machine.step_frame();
+        assert!(!machine.watchdog_fired());
+    }
+}
+
+#[test]
+fn space_invaders_kicks_the_watchdog() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../rom");
+    let mut rom = Vec::with_capacity(0x2000);
+    for e in ['h', 'g', 'f', 'e'] {
+        rom.extend(fs::read(dir.join(format!("invaders.{}", e))).unwrap());
+    }
+    let mut machine = SpaceInvaders::new(&rom);
+    machine.set_watchdog(true);
+
+    for frame in 0..1000 {
+        machine.step_frame();
+        assert!(!machine.watchdog_fired(), "fired at frame {}", frame);
+    }
+}
+
+#[test]
+fn watchdog_count_is_saved() {
+    let mut machine = SpaceInvaders::new(&idle_rom(0x2000));
+    machine.set_watchdog(true);
+    for _ in 0..WATCHDOG_FRAMES - 10 {
+        machine.step_frame();
+    }
+    let saved = machine.save_state();
+    for _ in 0..5 {
+        machine.step_frame();
+    }
+
+    // the count goes back to the one of the state, and not to zero
+    machine.load_state(&saved).unwrap();
+    for _ in 0..9 {
+        machine.step_frame();
+        assert!(!machine.watchdog_fired());
+    }
+    machine.step_frame();
+    assert!(machine.watchdog_fired());
+}
@@ -0,0 +1,199 @@
+//! The headless capture mode: run the machine for a number of frames, and write the
+//! frames as PNGs or a animated GIF, and the audio as a WAV file.
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+use space_invaders_machine::{FRAME_RATE, audio};
+
+use crate::space_invaders::{self, Options};
+
+/// The frames captured when there is no `-frames` and no movie: one minute.
+const DEFAULT_FRAMES: u32 = 3600;
+
+fn write_png(path: &Path, (width, height): (u32, u32), rgba: &[u8]) -> io::Result<()> {
+    let file = io::BufWriter::new(fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgba))
+        .map_err(io::Error::other)
+}
+
+/// A animated GIF writer. The plain screen has only a few colors, so those frames are
+/// indexed directly, without loss. The frames with more than 256 colors, from the effects
+/// or the artwork, are quantized.
+struct GifWriter {
+    encoder: gif::Encoder<io::BufWriter<fs::File>>,
+    width: u16,
+    height: u16,
+    /// The time of the current frame, in centiseconds, for the frame delays.
+    time: f64,
+}
+impl GifWriter {
+    /// The speed of the quantization, from 1 (best) to 30 (fastest).
+    const QUANTIZATION_SPEED: i32 = 10;
+
+    fn new(path: &Path, (width, height): (u32, u32)) -> io::Result<Self> {
+        let (width, height) = (width as u16, height as u16);
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        let mut encoder = gif::Encoder::new(file, width, height, &[]).map_err(io::Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            encoder,
+            width,
+            height,
+            time: 0.0,
+        })
+    }
+
+    /// Index the colors of the frame, if there are at most 256 of them.
+    fn indexed_frame(&self, rgba: &[u8]) -> Option<gif::Frame<'static>> {
+        let mut colors = HashMap::new();
+        let mut palette = Vec::new();
+        let mut pixels = Vec::with_capacity(rgba.len() / 4);
+        for pixel in rgba.chunks_exact(4) {
+            let color = [pixel[0], pixel[1], pixel[2]];
+            let index = match colors.get(&color) {
+                Some(&index) => index,
+                None => {
+                    let index = u8::try_from(colors.len()).ok()?;
+                    palette.extend_from_slice(&color);
+                    colors.insert(color, index);
+                    index
+                }
+            };
+            pixels.push(index);
+        }
+        Some(gif::Frame::from_palette_pixels(
+            self.width,
+            self.height,
+            pixels,
+            palette,
+            None,
+        ))
+    }
+
+    fn write_frame(&mut self, rgba: &[u8]) -> io::Result<()> {
+        // the delays are in centiseconds, so round the time of each frame
+        let next_time = self.time + 100.0 / FRAME_RATE;
+        let delay = next_time.round() - self.time.round();
+        self.time = next_time;
+
+        let mut frame = self.indexed_frame(rgba).unwrap_or_else(|| {
+            let mut rgba = rgba.to_vec();
+            // the screen is opaque, and a transparent color would take a palette entry
+            rgba.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 0xff);
+            gif::Frame::from_rgba_speed(
+                self.width,
+                self.height,
+                &mut rgba,
+                Self::QUANTIZATION_SPEED,
+            )
+        });
+        frame.delay = delay as u16;
+        self.encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+}
+
+pub fn run(options: &Options) -> io::Result<()> {
+    let mut config = space_invaders::load_config();
+    let mut machine =
+        space_invaders::create_machine(options, &mut config).map_err(io::Error::other)?;
+    let mut presentation = space_invaders::load_presentation(&mut config, options);
+    let size = (presentation.width(), presentation.height());
+
+    let frames = match (options.frames, machine.movie_len()) {
+        (Some(frames), _) => frames,
+        (None, Some(len)) => len as u32,
+        (None, None) => DEFAULT_FRAMES,
+    };
+
+    if let Some(dir) = &options.png {
+        fs::create_dir_all(dir)?;
+    }
+    let mut gif = options
+        .gif
+        .as_deref()
+        .map(Path::new)
+        .map(|path| GifWriter::new(path, size))
+        .transpose()?;
+    let mut samples = Vec::new();
+
+    for frame in 0..frames {
+        machine.step_frame();
+        machine.drain_sound_events().for_each(drop);
+        samples.extend(machine.drain_samples());
+
+        let rgba = presentation.present(&machine);
+        if let Some(dir) = &options.png {
+            let path = Path::new(dir).join(format!("frame_{:05}.png", frame));
+            write_png(&path, size, rgba)?;
+        }
+        if let Some(gif) = &mut gif {
+            gif.write_frame(rgba)?;
+        }
+    }
+
+    if let Some(path) = &options.wav {
+        audio::write_wav(path, &samples)?;
+    }
+    println!("captured {} frames", frames);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use intel8080::Memory;
+    use space_invaders_machine::{
+        SCREEN_HEIGHT, SCREEN_WIDTH, SpaceInvaders, VIDEO_RAM, idle_rom, presentation::Presentation,
+    };
+
+    #[test]
+    fn gif_with_effects_is_quantized() {
+        let mut machine = SpaceInvaders::new(&idle_rom(0x2000));
+        for i in 0..0x1c00 {
+            let value = if i % 5 == 0 { 0xff } else { 0 };
+            machine.interpreter_mut().memory.write(VIDEO_RAM + i, value);
+        }
+        let mut presentation = Presentation::new();
+        presentation.blur = true;
+        presentation.persistence = 0.7;
+        // a gradient artwork
+        let background = (0..SCREEN_HEIGHT * SCREEN_WIDTH)
+            .flat_map(|i| {
+                let (x, y) = ((i % SCREEN_WIDTH) as u8, (i / SCREEN_WIDTH) as u8);
+                [x, y, x ^ y, 0xff]
+            })
+            .collect();
+        presentation.background = Some(background);
+        let size = (presentation.width(), presentation.height());
+
+        let path = std::env::temp_dir().join(format!("capture-test-{}.gif", std::process::id()));
+        let mut gif = GifWriter::new(&path, size).unwrap();
+        for _ in 0..3 {
+            machine.step_frame();
+            let rgba = presentation.present(&machine);
+            let colors: std::collections::HashSet<&[u8]> = rgba.chunks_exact(4).collect();
+            assert!(colors.len() > 256);
+            gif.write_frame(rgba).unwrap();
+        }
+        drop(gif);
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(fs::File::open(&path).unwrap()).unwrap();
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!((frame.width as u32, frame.height as u32), size);
+            frames += 1;
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(frames, 3);
+    }
+}
@@ -0,0 +1,110 @@
+//! The settings persisted between runs, in a simple `key = value` text file.
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use space_invaders_machine::{DipSwitches, ExtraShip};
+
+pub const CONFIG_PATH: &str = "space_invaders.cfg";
+
+#[derive(Default)]
+pub struct Config {
+    values: BTreeMap<String, String>,
+    /// If there are changes not saved yet.
+    modified: bool,
+}
+impl Config {
+    /// Load the config file. A missing file is a empty config.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let values = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Ok(Self {
+            values,
+            modified: false,
+        })
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let mut text = String::new();
+        for (key, value) in &self.values {
+            text += &format!("{} = {}\n", key, value);
+        }
+        fs::write(path, text)?;
+        self.modified = false;
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    pub fn set(&mut self, key: &str, value: String) {
+        if self.get(key) != Some(&value) {
+            self.values.insert(key.to_string(), value);
+            self.modified = true;
+        }
+    }
+
+    /// Get the value of the key, inserting the default if it is missing.
+    pub fn get_or_insert(&mut self, key: &str, default: &str) -> &str {
+        if !self.values.contains_key(key) {
+            self.set(key, default.to_string());
+        }
+        &self.values[key]
+    }
+
+    /// The dip switches, with the default value for the missing or invalid keys.
+    pub fn dip_switches(&self) -> DipSwitches {
+        let mut dip_switches = DipSwitches::default();
+        if let Some(ships) = self.get("dip.ships").and_then(|x| x.parse().ok()) {
+            let _ = dip_switches.set_ships(ships);
+        }
+        if let Some(extra_ship) = self.get("dip.extra_ship").and_then(parse_extra_ship) {
+            dip_switches.extra_ship = extra_ship;
+        }
+        if let Some(coin_info) = self.get("dip.coin_info").and_then(parse_on_off) {
+            dip_switches.coin_info = coin_info;
+        }
+        dip_switches
+    }
+
+    pub fn set_dip_switches(&mut self, dip_switches: DipSwitches) {
+        self.set("dip.ships", dip_switches.ships().to_string());
+        let extra_ship = match dip_switches.extra_ship {
+            ExtraShip::At1000 => "1000",
+            ExtraShip::At1500 => "1500",
+        };
+        self.set("dip.extra_ship", extra_ship.to_string());
+        let coin_info = if dip_switches.coin_info { "on" } else { "off" };
+        self.set("dip.coin_info", coin_info.to_string());
+    }
+}
+
+pub fn parse_extra_ship(value: &str) -> Option<ExtraShip> {
+    match value {
+        "1000" => Some(ExtraShip::At1000),
+        "1500" => Some(ExtraShip::At1500),
+        _ => None,
+    }
+}
+
+pub fn parse_on_off(value: &str) -> Option<bool> {
+    match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
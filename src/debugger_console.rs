@@ -0,0 +1,463 @@
+//! The debugger in the terminal: when the cpu stops, the disassembly and the registers are
+//! printed, and the commands are read from the stdin. The cpu test blocks on the prompt,
+//! and the game polls a `Console` between the frames.
+
+use std::{
+    fmt::Write,
+    io::{self, BufWriter},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use intel8080::{
+    IODevices, Memory,
+    debugger::{Access, Breakpoint, Command, Register, Response, Watchpoint},
+    dissasembler::dissasembly_around,
+    expression::{Expression, Message},
+    interpreter::Interpreter,
+};
+
+use crate::write_adapter::WriteAdapter;
+
+fn parse_adress(arg: Option<&str>) -> Option<u16> {
+    arg.and_then(|arg| u16::from_str_radix(arg, 16).ok())
+}
+
+fn parse_register(name: &str) -> Option<Register> {
+    Some(match name.to_ascii_uppercase().as_str() {
+        "A" => Register::A,
+        "F" | "FLAGS" => Register::Flags,
+        "B" => Register::B,
+        "C" => Register::C,
+        "D" => Register::D,
+        "E" => Register::E,
+        "H" => Register::H,
+        "L" => Register::L,
+        "SP" => Register::SP,
+        "PC" => Register::PC,
+        _ => return None,
+    })
+}
+
+/// Parse the access, the value and the condition of a watchpoint, like `rw =ff if A == 0`.
+fn parse_watch_options<'a>(
+    mut watchpoint: Watchpoint,
+    mut args: impl Iterator<Item = &'a str>,
+) -> Result<Watchpoint, String> {
+    while let Some(arg) = args.next() {
+        watchpoint.access = match arg {
+            "r" | "in" => Access::Read,
+            "w" | "out" => Access::Write,
+            "rw" | "inout" => Access::ReadWrite,
+            "if" => {
+                let condition = args.by_ref().collect::<Vec<_>>().join(" ");
+                let condition = Expression::parse(&condition).map_err(|e| e.to_string())?;
+                return Ok(watchpoint.with_condition(condition));
+            }
+            _ => {
+                let value = arg
+                    .strip_prefix('=')
+                    .and_then(|value| u8::from_str_radix(value, 16).ok())
+                    .ok_or_else(|| format!("unexpected '{}'", arg))?;
+                watchpoint.value = Some(value);
+                continue;
+            }
+        };
+    }
+    Ok(watchpoint)
+}
+
+/// Parse the options of a breakpoint, like `if A == 10 after 3 log A is {A}`.
+fn parse_breakpoint_options(mut args: &[&str]) -> Result<Breakpoint, String> {
+    let mut breakpoint = Breakpoint::default();
+    // the message takes the rest of the line
+    if let Some(i) = args.iter().position(|&arg| arg == "log") {
+        let message = Message::parse(&args[i + 1..].join(" ")).map_err(|e| e.to_string())?;
+        breakpoint = breakpoint.with_log(message);
+        args = &args[..i];
+    }
+    if let Some(i) = args.iter().position(|&arg| arg == "after") {
+        let ignore = match &args[i + 1..] {
+            [count] => count.parse().ok(),
+            _ => None,
+        };
+        let ignore = ignore.ok_or("'after' takes the decimal number of ignored hits")?;
+        breakpoint = breakpoint.with_ignore(ignore);
+        args = &args[..i];
+    }
+    match args {
+        [] => {}
+        ["if", condition @ ..] => {
+            let condition = Expression::parse(&condition.join(" ")).map_err(|e| e.to_string())?;
+            breakpoint = breakpoint.with_condition(condition);
+        }
+        [arg, ..] => return Err(format!("unexpected '{}'", arg)),
+    }
+    Ok(breakpoint)
+}
+
+fn print_breakpoints<M: Memory, I: IODevices>(interpreter: &Interpreter<M, I>) {
+    let debugger = interpreter.debugger();
+    for adress in debugger.breakpoints() {
+        if let Some(breakpoint) = debugger.breakpoint(adress) {
+            println!("{:04x}: {}", adress, breakpoint);
+        }
+    }
+}
+
+fn print_watchpoints<M: Memory, I: IODevices>(interpreter: &Interpreter<M, I>) {
+    for (i, watchpoint) in interpreter.debugger().watchpoints().iter().enumerate() {
+        println!("{}: {}", i, watchpoint);
+    }
+}
+
+fn print_stop<M: Memory, I: IODevices>(interpreter: &mut Interpreter<M, I>) {
+    let stdout = io::stdout();
+    let mut w = WriteAdapter(BufWriter::new(stdout.lock()));
+    if let Some(reason) = interpreter.debugger().stop_reason() {
+        writeln!(w, "\nstopped: {}", reason).unwrap();
+    }
+    let rom = interpreter.memory.get_rom();
+    let pc = interpreter.state().get_PC();
+    dissasembly_around(&mut w, interpreter.debugger().traced(), &rom, pc).unwrap();
+    writeln!(w).unwrap();
+    interpreter.state().print_state(&mut w);
+}
+
+/// Execute a line of command. Return true if the command ran the cpu.
+fn execute<M: Memory, I: IODevices>(interpreter: &mut Interpreter<M, I>, line: &str) -> bool {
+    let mut input = line.split_ascii_whitespace();
+    let Some(command) = input.next() else {
+        // do one step
+        interpreter.debug_command(Command::Step);
+        return true;
+    };
+    match command {
+        "run" => {
+            interpreter.debug_command(Command::Continue);
+            return true;
+        }
+        "over" => {
+            interpreter.debug_command(Command::StepOver);
+            return true;
+        }
+        "out" => {
+            interpreter.debug_command(Command::StepOut);
+            return true;
+        }
+        "runto" => match parse_adress(input.next()) {
+            Some(adress) => {
+                let mut safety = 0;
+                while interpreter.state().get_PC() != adress {
+                    safety += 1;
+                    if safety > 100_000 {
+                        println!(
+                            "safety: after 100_000 steps, it don't reach the adress {:04x} yet",
+                            adress
+                        );
+                        break;
+                    }
+                    if interpreter.state().halt {
+                        println!("the cpu halted before reaching the adress");
+                        break;
+                    }
+                    interpreter.debug_command(Command::Step);
+                }
+                return true;
+            }
+            None => println!(
+                "use 'runto <ADRESS>', where <ADRESS> is the hexadecimal adress of the opcode it will stop when reached."
+            ),
+        },
+        "bp" => {
+            let adress = parse_adress(input.next());
+            let args: Vec<&str> = input.collect();
+            match adress.map(|adress| (adress, parse_breakpoint_options(&args))) {
+                Some((adress, Ok(breakpoint))) => {
+                    interpreter.debug_command(Command::SetBreakpoint(adress, breakpoint));
+                    print_breakpoints(interpreter);
+                }
+                error => {
+                    if let Some((_, Err(e))) = error {
+                        println!("{}", e);
+                    }
+                    println!(
+                        "use 'bp <ADRESS> [if <CONDITION>] [after <N>] [log <MESSAGE>]', where <ADRESS> is the hexadecimal adress of the breakpoint."
+                    )
+                }
+            }
+        }
+        "rm" => match parse_adress(input.next()) {
+            Some(adress) => {
+                interpreter.debug_command(Command::RemoveBreakpoint(adress));
+                print_breakpoints(interpreter);
+            }
+            None => println!(
+                "use 'rm <ADRESS>', where <ADRESS> is the hexadecimal adress of the breakpoint."
+            ),
+        },
+        "watch" => {
+            let range = input.next().and_then(|arg| match arg.split_once('-') {
+                Some((start, end)) => Some(parse_adress(Some(start))?..=parse_adress(Some(end))?),
+                None => parse_adress(Some(arg)).map(|adress| adress..=adress),
+            });
+            match range.map(|range| {
+                parse_watch_options(Watchpoint::memory(range, Access::Write), &mut input)
+            }) {
+                Some(Ok(watchpoint)) => {
+                    interpreter.debug_command(Command::AddWatchpoint(watchpoint));
+                    print_watchpoints(interpreter);
+                }
+                error => {
+                    if let Some(Err(e)) = error {
+                        println!("{}", e);
+                    }
+                    println!(
+                        "use 'watch <ADRESS>[-<END>] [r|w|rw] [=<VALUE>] [if <CONDITION>]', where <ADRESS>, <END> and <VALUE> are hexadecimal."
+                    )
+                }
+            }
+        }
+        "port" => {
+            let port = input
+                .next()
+                .and_then(|arg| u8::from_str_radix(arg, 16).ok());
+            match port.map(|port| {
+                parse_watch_options(Watchpoint::port(port, Access::ReadWrite), &mut input)
+            }) {
+                Some(Ok(watchpoint)) => {
+                    interpreter.debug_command(Command::AddWatchpoint(watchpoint));
+                    print_watchpoints(interpreter);
+                }
+                error => {
+                    if let Some(Err(e)) = error {
+                        println!("{}", e);
+                    }
+                    println!(
+                        "use 'port <PORT> [in|out|inout] [=<VALUE>] [if <CONDITION>]', where <PORT> and <VALUE> are hexadecimal."
+                    )
+                }
+            }
+        }
+        "unwatch" => {
+            let watchpoint = input
+                .next()
+                .and_then(|arg| arg.parse::<usize>().ok())
+                .and_then(|i| interpreter.debugger().watchpoints().get(i).cloned());
+            match watchpoint {
+                Some(watchpoint) => {
+                    interpreter.debug_command(Command::RemoveWatchpoint(watchpoint));
+                    print_watchpoints(interpreter);
+                }
+                None => {
+                    println!("use 'unwatch <N>', where <N> is the number of the watchpoint.")
+                }
+            }
+        }
+        "interrupt" => match input
+            .next()
+            .and_then(|arg| u8::from_str_radix(arg, 16).ok())
+        {
+            Some(opcode) => {
+                interpreter.debug_command(Command::Interrupt(opcode));
+            }
+            None => println!(
+                "use 'interrupt <OPCODE>', where <OPCODE> is the hexadecimal that opcode will be run."
+            ),
+        },
+        "mem" => match parse_adress(input.next()) {
+            Some(adress) => {
+                let len = input
+                    .next()
+                    .and_then(|arg| u16::from_str_radix(arg, 16).ok())
+                    .unwrap_or(0x40);
+                if let Response::Memory(data) =
+                    interpreter.debug_command(Command::ReadMemory { adress, len })
+                {
+                    for (i, row) in data.chunks(16).enumerate() {
+                        let mut line = format!("{:04x}", adress.wrapping_add(i as u16 * 16));
+                        for value in row {
+                            write!(line, " {:02x}", value).unwrap();
+                        }
+                        println!("{}", line);
+                    }
+                }
+            }
+            None => {
+                println!("use 'mem <ADRESS> [LEN]', where <ADRESS> and [LEN] are hexadecimal.")
+            }
+        },
+        "set" => match (
+            input.next().and_then(parse_register),
+            parse_adress(input.next()),
+        ) {
+            (Some(register), Some(value)) => {
+                interpreter.debug_command(Command::WriteRegister(register, value));
+                interpreter
+                    .state()
+                    .print_state(&mut WriteAdapter(io::stdout()));
+            }
+            _ => println!(
+                "use 'set <REGISTER> <VALUE>', where <REGISTER> is A, F, B, C, D, E, H, L, SP or PC, and <VALUE> is hexadecimal."
+            ),
+        },
+        "devices" => {
+            let mut w = WriteAdapter(io::stdout());
+            interpreter.devices.print_state(&mut w);
+        }
+        _ => println!("unknown command '{}'", command),
+    }
+    false
+}
+
+/// Read commands from the stdin, until one of them runs the cpu.
+pub fn prompt<M: Memory, I: IODevices>(interpreter: &mut Interpreter<M, I>) {
+    print_stop(interpreter);
+    loop {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line).unwrap();
+        if execute(interpreter, &line) {
+            return;
+        }
+    }
+}
+
+/// The console while the game runs: a thread reads the lines of the stdin, and they are
+/// executed between the frames, so the game don't block waiting for a command.
+pub struct Console {
+    lines: Receiver<String>,
+    /// If the cpu was stopped in the last poll, to print each stop once.
+    stopped: bool,
+}
+impl Console {
+    pub fn spawn() -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Self::new(lines)
+    }
+
+    fn new(lines: Receiver<String>) -> Self {
+        Self {
+            lines,
+            stopped: false,
+        }
+    }
+
+    /// Execute the lines read since the last poll, and print the cpu when it stops.
+    pub fn poll<M: Memory, I: IODevices>(&mut self, interpreter: &mut Interpreter<M, I>) {
+        if interpreter.is_stopped() && !self.stopped {
+            print_stop(interpreter);
+        }
+        while let Ok(line) = self.lines.try_recv() {
+            // a empty line only steps a stopped cpu
+            if line.trim().is_empty() && !interpreter.is_stopped() {
+                continue;
+            }
+            if execute(interpreter, &line) && interpreter.is_stopped() {
+                print_stop(interpreter);
+            }
+        }
+        self.stopped = interpreter.is_stopped();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use intel8080::interpreter::StopReason;
+    use space_invaders_machine::SpaceInvaders;
+
+    /// `EI; JMP 0001`, with `JMP 0008` at the RST 1 vector.
+    fn machine() -> SpaceInvaders {
+        let mut rom = vec![0; 0x2000];
+        rom[..4].copy_from_slice(&[0xfb, 0xc3, 0x01, 0x00]);
+        rom[0x08..0x0b].copy_from_slice(&[0xc3, 0x08, 0x00]);
+        rom[0x10..0x13].copy_from_slice(&[0xc3, 0x10, 0x00]);
+        SpaceInvaders::new(&rom)
+    }
+
+    #[test]
+    fn console_runs_commands_between_the_frames() {
+        let mut machine = machine();
+        let (sender, lines) = mpsc::channel();
+        let mut console = Console::new(lines);
+
+        // a empty line don't stop the running cpu
+        sender.send(String::new()).unwrap();
+        sender.send("interrupt cf".to_string()).unwrap();
+        console.poll(machine.interpreter_mut());
+        assert!(!machine.interpreter().is_stopped());
+
+        let interpreter = machine.interpreter_mut();
+        interpreter.run(100);
+        assert_eq!(interpreter.state().get_PC(), 0x0008);
+
+        sender.send("bp 0001".to_string()).unwrap();
+        console.poll(interpreter);
+        assert!(interpreter.debugger().has_breakpoint(0x0001));
+
+        interpreter.state_mut().set_PC(0x0000);
+        assert_eq!(interpreter.run(100), StopReason::Breakpoint(0x0001));
+        sender.send(String::new()).unwrap();
+        console.poll(interpreter);
+        assert_eq!(interpreter.state().get_PC(), 0x0001);
+        assert_eq!(interpreter.debugger().stop_reason(), Some(StopReason::Step));
+    }
+
+    #[test]
+    fn console_adds_watchpoints_to_the_game() {
+        let mut machine = machine();
+        let (sender, lines) = mpsc::channel();
+        let mut console = Console::new(lines);
+        for line in [
+            "watch 2000-23ff rw =00",
+            "port 3 out",
+            "port 05 =ff",
+            "port 3 out",
+        ] {
+            sender.send(line.to_string()).unwrap();
+        }
+        console.poll(machine.interpreter_mut());
+        let watchpoints = machine.interpreter().debugger().watchpoints();
+        assert_eq!(
+            watchpoints,
+            [
+                Watchpoint::memory(0x2000..=0x23ff, Access::ReadWrite).with_value(0x00),
+                Watchpoint::port(0x03, Access::Write),
+                Watchpoint::port(0x05, Access::ReadWrite).with_value(0xff),
+            ]
+        );
+
+        sender.send("unwatch 1".to_string()).unwrap();
+        console.poll(machine.interpreter_mut());
+        assert_eq!(machine.interpreter().debugger().watchpoints().len(), 2);
+    }
+
+    #[test]
+    fn console_adds_conditional_breakpoints_to_the_game() {
+        let mut machine = machine();
+        let (sender, lines) = mpsc::channel();
+        let mut console = Console::new(lines);
+        sender
+            .send("bp 0008 if SP != 0 after 2 log SP={SP}".to_string())
+            .unwrap();
+        // a invalid condition adds nothing
+        sender.send("bp 0010 if A ==".to_string()).unwrap();
+        console.poll(machine.interpreter_mut());
+
+        let debugger = machine.interpreter().debugger();
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), [0x0008]);
+        let expected = Breakpoint::default()
+            .with_condition(Expression::parse("SP != 0").unwrap())
+            .with_ignore(2)
+            .with_log(Message::parse("SP={SP}").unwrap());
+        assert_eq!(debugger.breakpoint(0x0008), Some(&expected));
+    }
+}
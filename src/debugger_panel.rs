@@ -0,0 +1,393 @@
+//! A debugger drawn in the window, at the right of the screen, for builds with the `debug`
+//! feature. The machine keeps being rendered while it is paused, and each command is
+//! executed between frames, so nothing blocks the event loop.
+
+use std::{convert::Infallible, fmt::Write};
+
+use embedded_graphics::{
+    Drawable, Pixel,
+    mono_font::{MonoTextStyle, ascii::FONT_6X10},
+    pixelcolor::{Rgb888, RgbColor},
+    prelude::{DrawTarget, OriginDimensions, Point, Size},
+    primitives::Rectangle,
+    text::{Baseline, Text},
+};
+use intel8080::{
+    debugger::{Command, Debugger, Response},
+    dissasembler::dissasembly_around,
+};
+use space_invaders_machine::SpaceInvaders;
+use winit::event::VirtualKeyCode;
+
+pub const PANEL_WIDTH: u32 = 360;
+/// The height of the window buffer while the panel is open. The screen is scaled to it.
+pub const PANEL_HEIGHT: u32 = 512;
+
+const LINE_HEIGHT: i32 = 10;
+const MARGIN: i32 = 6;
+
+const BACKGROUND: [u8; 4] = [0x18, 0x18, 0x20, 0xff];
+const TEXT: Rgb888 = Rgb888::new(0xd0, 0xd0, 0xd0);
+const TITLE: Rgb888 = Rgb888::new(0x60, 0xc0, 0xff);
+const CURSOR: Rgb888 = Rgb888::new(0x30, 0x30, 0x60);
+const CURRENT: Rgb888 = Rgb888::new(0xff, 0xff, 0x40);
+const BREAKPOINT: Rgb888 = Rgb888::new(0xff, 0x40, 0x40);
+
+/// The number of lines written by `dissasembly_around`.
+const DISASSEMBLY_LINES: usize = 17;
+const MEMORY_LINES: u16 = 8;
+const STACK_WORDS: u16 = 8;
+
+/// A RGBA buffer that embedded-graphics can draw into.
+struct Canvas<'a> {
+    frame: &'a mut [u8],
+    width: u32,
+    height: u32,
+}
+impl OriginDimensions for Canvas<'_> {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+impl DrawTarget for Canvas<'_> {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (x, y) = (point.x as u32, point.y as u32);
+            if point.x < 0 || point.y < 0 || x >= self.width || y >= self.height {
+                continue;
+            }
+            let i = ((y * self.width + x) * 4) as usize;
+            self.frame[i..i + 4].copy_from_slice(&[color.r(), color.g(), color.b(), 0xff]);
+        }
+        Ok(())
+    }
+}
+
+pub struct DebuggerPanel {
+    visible: bool,
+    /// The selected line of the disassembly.
+    cursor: usize,
+    /// The first adress of the memory view.
+    memory_view: u16,
+    /// The breakpoint placed by 'run to cursor', removed when the cpu stops.
+    run_to: Option<u16>,
+    /// The adress of each line of the last drawn disassembly.
+    adresses: Vec<Option<u16>>,
+}
+impl DebuggerPanel {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            cursor: DISASSEMBLY_LINES / 2,
+            memory_view: 0x2000,
+            run_to: None,
+            adresses: Vec::new(),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// The size of the window buffer, with the panel if it is open.
+    pub fn frame_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.visible {
+            let scale = (PANEL_HEIGHT / height).max(1);
+            (width * scale + PANEL_WIDTH, height * scale)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Open the panel, pausing the machine.
+    pub fn open(&mut self, machine: &mut SpaceInvaders) {
+        self.visible = true;
+        self.cursor = DISASSEMBLY_LINES / 2;
+        machine.interpreter_mut().debug_command(Command::Pause);
+    }
+
+    /// Close the panel, resuming the machine.
+    pub fn close(&mut self, machine: &mut SpaceInvaders) {
+        self.visible = false;
+        self.clear_run_to(machine);
+        machine.interpreter_mut().debug_command(Command::Continue);
+    }
+
+    /// Check the machine after each frame: remove the breakpoint of 'run to cursor' and
+    /// open the panel when the cpu is stopped by a breakpoint or the watchdog.
+    pub fn update(&mut self, machine: &mut SpaceInvaders) {
+        if !machine.interpreter().is_stopped() {
+            return;
+        }
+        if self.run_to.is_some() {
+            self.clear_run_to(machine);
+            self.cursor = DISASSEMBLY_LINES / 2;
+        }
+        self.visible = true;
+    }
+
+    fn clear_run_to(&mut self, machine: &mut SpaceInvaders) {
+        if let Some(adress) = self.run_to.take() {
+            debugger(machine).remove_breakpoint(adress);
+        }
+    }
+
+    fn cursor_adress(&self) -> Option<u16> {
+        self.adresses.get(self.cursor).copied().flatten()
+    }
+
+    /// Handle a pressed key. Return false if the key is not a command of the panel.
+    pub fn key(&mut self, key: VirtualKeyCode, machine: &mut SpaceInvaders) -> bool {
+        let stopped = machine.interpreter().is_stopped();
+        let command = match key {
+            VirtualKeyCode::F6 if stopped => Command::Step,
+            VirtualKeyCode::F10 if stopped => Command::StepOver,
+            VirtualKeyCode::F11 if stopped => Command::StepOut,
+            VirtualKeyCode::F7 if stopped => Command::Continue,
+            VirtualKeyCode::F7 => Command::Pause,
+            VirtualKeyCode::F8 if stopped => {
+                let Some(adress) = self.cursor_adress() else {
+                    return true;
+                };
+                if !debugger(machine).has_breakpoint(adress) {
+                    self.run_to = Some(adress);
+                }
+                machine
+                    .interpreter_mut()
+                    .debug_command(Command::AddBreakpoint(adress));
+                Command::Continue
+            }
+            VirtualKeyCode::F2 => {
+                let Some(adress) = self.cursor_adress() else {
+                    return true;
+                };
+                if self.run_to == Some(adress) {
+                    // it is kept as a breakpoint
+                    self.run_to = None;
+                    return true;
+                }
+                if debugger(machine).has_breakpoint(adress) {
+                    Command::RemoveBreakpoint(adress)
+                } else {
+                    Command::AddBreakpoint(adress)
+                }
+            }
+            VirtualKeyCode::Up => {
+                self.cursor = self.cursor.saturating_sub(1);
+                return true;
+            }
+            VirtualKeyCode::Down => {
+                self.cursor = (self.cursor + 1).min(DISASSEMBLY_LINES - 1);
+                return true;
+            }
+            VirtualKeyCode::PageUp => {
+                self.memory_view = self.memory_view.wrapping_sub(0x80);
+                return true;
+            }
+            VirtualKeyCode::PageDown => {
+                self.memory_view = self.memory_view.wrapping_add(0x80);
+                return true;
+            }
+            _ => return false,
+        };
+        let response = machine.interpreter_mut().debug_command(command);
+        if let Response::Stopped(_) = response {
+            self.cursor = DISASSEMBLY_LINES / 2;
+        }
+        true
+    }
+
+    /// Draw the screen, scaled, and the panel at its right, in a frame of `frame_size`.
+    pub fn draw(
+        &mut self,
+        machine: &SpaceInvaders,
+        screen: &[u8],
+        (width, height): (u32, u32),
+        frame: &mut [u8],
+    ) {
+        let (frame_width, frame_height) = self.frame_size(width, height);
+        let scale = (PANEL_HEIGHT / height).max(1);
+        for (y, row) in frame.chunks_exact_mut(frame_width as usize * 4).enumerate() {
+            let (game, panel) = row.split_at_mut((width * scale) as usize * 4);
+            let source = (y as u32 / scale * width) as usize * 4;
+            for (x, pixel) in game.chunks_exact_mut(4).enumerate() {
+                let i = source + (x as u32 / scale) as usize * 4;
+                pixel.copy_from_slice(&screen[i..i + 4]);
+            }
+            for pixel in panel.chunks_exact_mut(4) {
+                pixel.copy_from_slice(&BACKGROUND);
+            }
+        }
+
+        let mut canvas = Canvas {
+            frame,
+            width: frame_width,
+            height: frame_height,
+        };
+        let mut panel = Panel {
+            canvas: &mut canvas,
+            x: (width * scale) as i32 + MARGIN,
+            line: 0,
+        };
+
+        let interpreter = machine.interpreter();
+        let state = interpreter.state();
+        let memory = &interpreter.memory;
+        let debugger = interpreter.debugger();
+        let breakpoints: Vec<u16> = debugger.breakpoints().collect();
+
+        match debugger.stop_reason() {
+            Some(reason) => panel.text(&format!("STOPPED: {}", reason), TITLE),
+            None => panel.text("RUNNING", TITLE),
+        }
+        panel.text("F6 step  F10 step over  F11 step out", TEXT);
+        panel.text("F7 continue/pause  F8 run to cursor  F2 breakpoint", TEXT);
+        panel.text("Up/Down cursor  PgUp/PgDn memory", TEXT);
+        panel.line += 1;
+
+        panel.text("Registers", TITLE);
+        panel.text("A  SZ-A-P-C  B  C  D  E  H  L", TEXT);
+        panel.text(
+            &format!(
+                "{:02x} {:08b}  {:02x} {:02x} {:02x} {:02x} {:02x} {:02x}",
+                state.A, state.Flags, state.B, state.C, state.D, state.E, state.H, state.L
+            ),
+            TEXT,
+        );
+        panel.text(
+            &format!(
+                "PC {:04x}  SP {:04x}  IE {}  HALT {}  CLOCK {}",
+                state.get_PC(),
+                state.get_SP(),
+                state.interrupt_enabled as u8,
+                state.halt as u8,
+                interpreter.clock_count()
+            ),
+            TEXT,
+        );
+        panel.line += 1;
+
+        panel.text("Disassembly", TITLE);
+        // the code may run from RAM, so the whole adress space is disassembled
+        let image: Vec<u8> = (0..=0xffff)
+            .map(|adress| memory.peek(adress).unwrap_or(0))
+            .collect();
+        let mut disassembly = String::new();
+        dissasembly_around(&mut disassembly, debugger.traced(), &image, state.get_PC()).unwrap();
+        self.adresses.clear();
+        for (i, text) in disassembly.lines().take(DISASSEMBLY_LINES).enumerate() {
+            let adress = text
+                .get(..4)
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+            self.adresses.push(adress);
+            if i == self.cursor {
+                panel.highlight(CURSOR);
+            }
+            let color = match adress {
+                Some(adress) if adress == state.get_PC() => CURRENT,
+                Some(adress) if breakpoints.contains(&adress) => BREAKPOINT,
+                _ => TEXT,
+            };
+            panel.text(text, color);
+        }
+        panel.line += DISASSEMBLY_LINES.saturating_sub(self.adresses.len()) as i32 + 1;
+
+        panel.text("Stack", TITLE);
+        let sp = state.get_SP();
+        for row in 0..STACK_WORDS / 4 {
+            let mut text = String::new();
+            for word in 0..4 {
+                let adress = sp.wrapping_add((row * 4 + word) * 2);
+                match (memory.peek(adress), memory.peek(adress.wrapping_add(1))) {
+                    (Some(low), Some(high)) => {
+                        write!(text, "{:04x}:{:02x}{:02x}  ", adress, high, low).unwrap()
+                    }
+                    _ => write!(text, "{:04x}:----  ", adress).unwrap(),
+                }
+            }
+            panel.text(&text, TEXT);
+        }
+        panel.line += 1;
+
+        panel.text("Memory", TITLE);
+        for row in 0..MEMORY_LINES {
+            let start = self.memory_view.wrapping_add(row * 16);
+            let mut text = format!("{:04x}", start);
+            for i in 0..16 {
+                match memory.peek(start.wrapping_add(i)) {
+                    Some(value) => write!(text, " {:02x}", value).unwrap(),
+                    None => text.push_str(" --"),
+                }
+            }
+            panel.text(&text, TEXT);
+        }
+        panel.line += 1;
+
+        panel.text("Breakpoints", TITLE);
+        if breakpoints.is_empty() {
+            panel.text("none", TEXT);
+        }
+        // the conditional breakpoints and the tracepoints take a line each
+        let (plain, special): (Vec<u16>, Vec<u16>) = breakpoints.iter().partition(|&&adress| {
+            debugger
+                .breakpoint(adress)
+                .is_some_and(|b| b.condition.is_none() && b.log.is_none() && b.ignore == 0)
+        });
+        for row in plain.chunks(8) {
+            let text: Vec<String> = row.iter().map(|adress| format!("{:04x}", adress)).collect();
+            panel.text(&text.join(" "), BREAKPOINT);
+        }
+        for adress in special {
+            if let Some(breakpoint) = debugger.breakpoint(adress) {
+                panel.text(&format!("{:04x} {}", adress, breakpoint), BREAKPOINT);
+            }
+        }
+        panel.line += 1;
+
+        panel.text("Watchpoints", TITLE);
+        if debugger.watchpoints().is_empty() {
+            panel.text("none: 'watch' and 'port' in the terminal", TEXT);
+        }
+        // numbered like the 'unwatch' command of the terminal
+        for (i, watchpoint) in debugger.watchpoints().iter().enumerate() {
+            panel.text(&format!("{}: {}", i, watchpoint), BREAKPOINT);
+        }
+    }
+}
+
+fn debugger(machine: &mut SpaceInvaders) -> &mut Debugger {
+    machine.interpreter_mut().debugger_mut()
+}
+
+/// The text cursor of the panel.
+struct Panel<'a, 'b> {
+    canvas: &'a mut Canvas<'b>,
+    x: i32,
+    line: i32,
+}
+impl Panel<'_, '_> {
+    fn text(&mut self, text: &str, color: Rgb888) {
+        let style = MonoTextStyle::new(&FONT_6X10, color);
+        let position = Point::new(self.x, MARGIN + self.line * LINE_HEIGHT);
+        Text::with_baseline(text, position, style, Baseline::Top)
+            .draw(self.canvas)
+            .unwrap();
+        self.line += 1;
+    }
+
+    /// Fill the background of the next line.
+    fn highlight(&mut self, color: Rgb888) {
+        let y = MARGIN + self.line * LINE_HEIGHT;
+        let area = Rectangle::new(
+            Point::new(self.x - 2, y),
+            Size::new(PANEL_WIDTH - MARGIN as u32, LINE_HEIGHT as u32),
+        );
+        self.canvas.fill_solid(&area, color).unwrap();
+    }
+}
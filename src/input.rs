@@ -0,0 +1,374 @@
+//! Translate the keyboard and the gamepads to the inputs of the cabinet.
+//!
+//! The keymap is read from the `key.*` and `pad.*` entries of the config file. Each entry
+//! is a comma separated list of key (or button) names, like `key.p1_fire = Z, Space`.
+
+use std::collections::HashMap;
+
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use space_invaders_machine::{Input, Player};
+use winit::event::VirtualKeyCode;
+
+use crate::config::Config;
+
+macro_rules! names {
+    ($ty:ident: $($name:ident)*) => {
+        &[$((stringify!($name), $ty::$name)),*]
+    };
+}
+
+static KEY_NAMES: &[(&str, VirtualKeyCode)] = names!(VirtualKeyCode:
+    Key1 Key2 Key3 Key4 Key5 Key6 Key7 Key8 Key9 Key0
+    A B C D E F G H I J K L M N O P Q R S T U V W X Y Z
+    F1 F2 F3 F4 F6 F7 F8 F10 F11 F12
+    Insert Home Delete End PageDown PageUp Left Up Right Down Back Return Space Tab
+    Numpad0 Numpad1 Numpad2 Numpad3 Numpad4 Numpad5 Numpad6 Numpad7 Numpad8 Numpad9
+    NumpadAdd NumpadSubtract NumpadMultiply NumpadDivide NumpadEnter NumpadDecimal
+    Apostrophe Backslash Comma Equals Grave LBracket Minus Period RBracket Semicolon Slash
+    LAlt LControl LShift RAlt RControl RShift
+);
+
+static BUTTON_NAMES: &[(&str, Button)] = names!(Button:
+    South East North West C Z LeftTrigger LeftTrigger2 RightTrigger RightTrigger2
+    Select Start Mode LeftThumb RightThumb DPadUp DPadDown DPadLeft DPadRight
+);
+
+/// The keyboard inputs, with their config entry and default keys.
+const KEY_INPUTS: &[(&str, Input, &str)] = &[
+    ("key.coin", Input::Coin, "C"),
+    ("key.p1_start", Input::P1Start, "Return"),
+    ("key.p2_start", Input::P2Start, "Back"),
+    ("key.p1_left", Input::Left(Player::One), "Left"),
+    ("key.p1_right", Input::Right(Player::One), "Right"),
+    ("key.p1_up", Input::Up(Player::One), "Up"),
+    ("key.p1_down", Input::Down(Player::One), "Down"),
+    ("key.p1_fire", Input::Fire(Player::One), "Z"),
+    ("key.p2_left", Input::Left(Player::Two), "A"),
+    ("key.p2_right", Input::Right(Player::Two), "D"),
+    ("key.p2_up", Input::Up(Player::Two), "W"),
+    ("key.p2_down", Input::Down(Player::Two), "X"),
+    ("key.p2_fire", Input::Fire(Player::Two), "S"),
+    ("key.tilt", Input::Tilt, "T"),
+];
+
+/// A gamepad button, that is mapped to the controls of the player that owns the gamepad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PadInput {
+    Coin,
+    Start,
+    Left,
+    Right,
+    Up,
+    Down,
+    Fire,
+}
+impl PadInput {
+    fn input(self, player: Player) -> Input {
+        match (self, player) {
+            (PadInput::Coin, _) => Input::Coin,
+            (PadInput::Start, Player::One) => Input::P1Start,
+            (PadInput::Start, Player::Two) => Input::P2Start,
+            (PadInput::Left, _) => Input::Left(player),
+            (PadInput::Right, _) => Input::Right(player),
+            (PadInput::Up, _) => Input::Up(player),
+            (PadInput::Down, _) => Input::Down(player),
+            (PadInput::Fire, _) => Input::Fire(player),
+        }
+    }
+}
+
+const PAD_INPUTS: &[(&str, PadInput, &str)] = &[
+    ("pad.coin", PadInput::Coin, "Select"),
+    ("pad.start", PadInput::Start, "Start"),
+    ("pad.left", PadInput::Left, "DPadLeft"),
+    ("pad.right", PadInput::Right, "DPadRight"),
+    ("pad.up", PadInput::Up, "DPadUp"),
+    ("pad.down", PadInput::Down, "DPadDown"),
+    ("pad.fire", PadInput::Fire, "South, East"),
+];
+
+/// How far a stick must be pushed to move the cannon, or the cowboy.
+const AXIS_THRESHOLD: f32 = 0.5;
+
+fn parse_names<T: Copy>(names: &[(&str, T)], value: &str) -> Vec<T> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter_map(|name| match names.iter().find(|(n, _)| *n == name) {
+            Some(&(_, x)) => Some(x),
+            None => {
+                println!("unknown key name '{}'", name);
+                None
+            }
+        })
+        .collect()
+}
+
+pub struct Keymap {
+    keys: HashMap<VirtualKeyCode, Input>,
+    buttons: HashMap<Button, PadInput>,
+}
+impl Keymap {
+    /// Load the keymap from the config, writing the defaults for the missing entries.
+    pub fn from_config(config: &mut Config) -> Self {
+        let mut keys = HashMap::new();
+        for &(entry, input, default) in KEY_INPUTS {
+            let value = config.get_or_insert(entry, default);
+            for key in parse_names(KEY_NAMES, value) {
+                keys.insert(key, input);
+            }
+        }
+        let mut buttons = HashMap::new();
+        for &(entry, input, default) in PAD_INPUTS {
+            let value = config.get_or_insert(entry, default);
+            for button in parse_names(BUTTON_NAMES, value) {
+                buttons.insert(button, input);
+            }
+        }
+        Self { keys, buttons }
+    }
+
+    pub fn key(&self, key: VirtualKeyCode) -> Option<Input> {
+        self.keys.get(&key).copied()
+    }
+}
+
+/// A key, button or stick that holds a input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputSource {
+    Key(VirtualKeyCode),
+    Button(Player, Button),
+    Axis(Player, Axis),
+}
+
+/// The sources that hold each input. A input is pressed while any of its sources is, so a
+/// centered stick don't release a direction held on the keyboard.
+#[derive(Default)]
+pub struct HeldInputs {
+    held: Vec<(Input, InputSource)>,
+}
+impl HeldInputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Press or release 'input' from 'source', calling `set_input` if the input changed.
+    pub fn set(
+        &mut self,
+        input: Input,
+        source: InputSource,
+        pressed: bool,
+        mut set_input: impl FnMut(Input, bool),
+    ) {
+        let was_pressed = self.is_pressed(input);
+        self.held.retain(|&held| held != (input, source));
+        if pressed {
+            self.held.push((input, source));
+        }
+        if self.is_pressed(input) != was_pressed {
+            set_input(input, pressed);
+        }
+    }
+
+    /// Release the inputs held by the buttons and sticks of the gamepad of 'player',
+    /// calling `set_input` for each input released.
+    pub fn release_gamepad(&mut self, player: Player, mut set_input: impl FnMut(Input, bool)) {
+        let sources: Vec<(Input, InputSource)> = self
+            .held
+            .iter()
+            .filter(|(_, source)| match *source {
+                InputSource::Button(p, _) | InputSource::Axis(p, _) => p == player,
+                InputSource::Key(_) => false,
+            })
+            .copied()
+            .collect();
+        for (input, source) in sources {
+            self.set(input, source, false, &mut set_input);
+        }
+    }
+
+    fn is_pressed(&self, input: Input) -> bool {
+        self.held.iter().any(|&(held, _)| held == input)
+    }
+}
+
+/// The connected gamepads. The first one controls player 1, and the second player 2.
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+    /// The gamepad of each player. The slot of a disconnected gamepad stays empty until
+    /// another one connects, so the other player keeps its gamepad.
+    players: Vec<Option<GamepadId>>,
+}
+impl Gamepads {
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                println!("gamepads are not available: {}", e);
+                None
+            }
+        };
+        Self {
+            gilrs,
+            players: Vec::new(),
+        }
+    }
+
+    fn player(&mut self, id: GamepadId) -> Option<Player> {
+        let index = match self.players.iter().position(|&x| x == Some(id)) {
+            Some(index) => index,
+            None => match self.players.iter().position(Option::is_none) {
+                Some(index) => {
+                    self.players[index] = Some(id);
+                    index
+                }
+                None => {
+                    self.players.push(Some(id));
+                    self.players.len() - 1
+                }
+            },
+        };
+        match index {
+            0 => Some(Player::One),
+            1 => Some(Player::Two),
+            _ => None,
+        }
+    }
+
+    /// Process the pending gamepad events, calling `set_input` for each input change.
+    pub fn poll(
+        &mut self,
+        keymap: &Keymap,
+        held: &mut HeldInputs,
+        mut set_input: impl FnMut(Input, bool),
+    ) {
+        while let Some(event) = self.gilrs.as_mut().and_then(Gilrs::next_event) {
+            if event.event == EventType::Disconnected {
+                if let Some(index) = self.players.iter().position(|&x| x == Some(event.id)) {
+                    self.players[index] = None;
+                    match index {
+                        0 => held.release_gamepad(Player::One, &mut set_input),
+                        1 => held.release_gamepad(Player::Two, &mut set_input),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            let Some(player) = self.player(event.id) else {
+                continue;
+            };
+            match event.event {
+                EventType::ButtonPressed(button, _) | EventType::ButtonReleased(button, _) => {
+                    let pressed = matches!(event.event, EventType::ButtonPressed(..));
+                    if let Some(input) = keymap.buttons.get(&button) {
+                        let source = InputSource::Button(player, button);
+                        held.set(input.input(player), source, pressed, &mut set_input);
+                    }
+                }
+                EventType::AxisChanged(axis @ (Axis::LeftStickX | Axis::DPadX), value, _) => {
+                    let source = InputSource::Axis(player, axis);
+                    let left = value < -AXIS_THRESHOLD;
+                    let right = value > AXIS_THRESHOLD;
+                    held.set(Input::Left(player), source, left, &mut set_input);
+                    held.set(Input::Right(player), source, right, &mut set_input);
+                }
+                EventType::AxisChanged(axis @ (Axis::LeftStickY | Axis::DPadY), value, _) => {
+                    let source = InputSource::Axis(player, axis);
+                    let up = value > AXIS_THRESHOLD;
+                    let down = value < -AXIS_THRESHOLD;
+                    held.set(Input::Up(player), source, up, &mut set_input);
+                    held.set(Input::Down(player), source, down, &mut set_input);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn centered_stick_keeps_the_key_held() {
+        let mut held = HeldInputs::new();
+        let mut changes = Vec::new();
+        let left = Input::Left(Player::One);
+        let key = InputSource::Key(VirtualKeyCode::Left);
+        let stick = InputSource::Axis(Player::One, Axis::LeftStickX);
+
+        held.set(left, key, true, |input, pressed| {
+            changes.push((input, pressed))
+        });
+        // the stick is pushed and centered, or is noisy, while the key is held
+        held.set(left, stick, true, |input, pressed| {
+            changes.push((input, pressed))
+        });
+        held.set(left, stick, false, |input, pressed| {
+            changes.push((input, pressed))
+        });
+        held.set(left, stick, false, |input, pressed| {
+            changes.push((input, pressed))
+        });
+        assert_eq!(changes, [(left, true)]);
+
+        held.set(left, key, false, |input, pressed| {
+            changes.push((input, pressed))
+        });
+        assert_eq!(changes, [(left, true), (left, false)]);
+    }
+
+    #[test]
+    fn disconnected_gamepad_releases_its_inputs() {
+        let mut held = HeldInputs::new();
+        let mut changes = Vec::new();
+        let (left, fire) = (Input::Left(Player::One), Input::Fire(Player::One));
+        let key = InputSource::Key(VirtualKeyCode::Left);
+        let stick = InputSource::Axis(Player::One, Axis::LeftStickX);
+        let button = |player| InputSource::Button(player, Button::South);
+
+        held.set(left, key, true, |i, p| changes.push((i, p)));
+        held.set(left, stick, true, |i, p| changes.push((i, p)));
+        held.set(fire, button(Player::One), true, |i, p| changes.push((i, p)));
+        held.set(
+            Input::Fire(Player::Two),
+            button(Player::Two),
+            true,
+            |i, p| changes.push((i, p)),
+        );
+        changes.clear();
+
+        // the key still holds the direction, and the other gamepad its trigger
+        held.release_gamepad(Player::One, |i, p| changes.push((i, p)));
+        assert_eq!(changes, [(fire, false)]);
+        held.set(left, key, false, |i, p| changes.push((i, p)));
+        assert_eq!(changes, [(fire, false), (left, false)]);
+        assert!(held.is_pressed(Input::Fire(Player::Two)));
+    }
+
+    #[test]
+    fn each_player_is_held_apart() {
+        let mut held = HeldInputs::new();
+        let mut changes = Vec::new();
+        let fire = Input::Fire;
+        let button = |player| InputSource::Button(player, Button::South);
+
+        held.set(fire(Player::One), button(Player::One), true, |i, p| {
+            changes.push((i, p))
+        });
+        held.set(fire(Player::Two), button(Player::Two), true, |i, p| {
+            changes.push((i, p))
+        });
+        held.set(fire(Player::One), button(Player::One), false, |i, p| {
+            changes.push((i, p))
+        });
+        assert_eq!(
+            changes,
+            [
+                (fire(Player::One), true),
+                (fire(Player::Two), true),
+                (fire(Player::One), false)
+            ]
+        );
+    }
+}
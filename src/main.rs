@@ -1,11 +1,20 @@
 use std::env::args;
 use std::io;
 
+mod capture;
+mod config;
+#[cfg(feature = "debug")]
+mod debugger_console;
+#[cfg(feature = "debug")]
+mod debugger_panel;
+mod input;
+mod roms;
 mod space_invaders;
 mod test_machine;
 mod write_adapter;
 
 use intel8080::{dissasembler, interpreter};
+use space_invaders_machine::{ENTRIES, machines};
 
 use dissasembler::*;
 use write_adapter::WriteAdapter;
@@ -15,33 +24,106 @@ fn main() {
     let _ = args.next();
     let mut disassembly = false;
     let mut test = false;
-    let mut debug = false;
-    for arg in args {
+    let mut capture = false;
+    let mut options = space_invaders::Options::default();
+    while let Some(arg) = args.next() {
         if arg.starts_with("-debug") {
-            debug = true;
+            options.debug = true;
+        } else if arg.starts_with("-machine") {
+            let name = args.next().unwrap_or_default();
+            match machines::find(&name) {
+                Some(machine) => options.machine = Some(machine),
+                None => {
+                    println!("unknown machine '{}', the known machines are:", name);
+                    for machine in machines::MACHINES {
+                        println!("  {:10} {}", machine.name, machine.title);
+                    }
+                    return;
+                }
+            }
+        } else if arg.starts_with("-rom-path") {
+            options.rom_path = args.next();
+        } else if arg.starts_with("-record") {
+            options.record = args.next();
+        } else if arg.starts_with("-play") {
+            options.play = args.next();
+        } else if arg.starts_with("capture") {
+            capture = true;
+        } else if arg.starts_with("-frames") {
+            match args.next().and_then(|x| x.parse().ok()) {
+                Some(frames) => options.frames = Some(frames),
+                None => println!("-frames expects a number"),
+            }
+        } else if arg.starts_with("-png") {
+            options.png = args.next();
+        } else if arg.starts_with("-gif") {
+            options.gif = args.next();
+        } else if arg.starts_with("-synth") {
+            options.synth = true;
+        } else if arg.starts_with("-watchdog") {
+            options.watchdog = true;
+        } else if arg.starts_with("-wav") {
+            options.wav = args.next();
+        } else if arg.starts_with("-ships") {
+            match args.next().and_then(|x| x.parse().ok()) {
+                Some(ships @ 3..=6) => options.ships = Some(ships),
+                _ => println!("-ships expects a number from 3 to 6"),
+            }
+        } else if arg.starts_with("-extra-ship") {
+            match args.next().as_deref().and_then(config::parse_extra_ship) {
+                Some(extra_ship) => options.extra_ship = Some(extra_ship),
+                None => println!("-extra-ship expects 1000 or 1500"),
+            }
+        } else if arg.starts_with("-coin-info") {
+            match args.next().as_deref().and_then(config::parse_on_off) {
+                Some(coin_info) => options.coin_info = Some(coin_info),
+                None => println!("-coin-info expects on or off"),
+            }
+        } else if arg.starts_with("-overlay") {
+            options.overlay = args.next();
+        } else if arg.starts_with("-background") {
+            options.background = args.next();
+        } else if arg.starts_with("-scanlines") {
+            options.scanlines = true;
+        } else if arg.starts_with("-persistence") {
+            match args.next().and_then(|x| x.parse().ok()) {
+                Some(persistence @ 0.0..=1.0) => options.persistence = Some(persistence),
+                _ => println!("-persistence expects a number from 0 to 1"),
+            }
+        } else if arg.starts_with("-blur") {
+            options.blur = true;
         } else if arg.starts_with("test") {
             test = true;
+        } else if arg.starts_with("-gdb") {
+            match args.next().and_then(|x| x.parse().ok()) {
+                Some(port) => options.gdb = Some(port),
+                None => println!("-gdb expects a port number"),
+            }
         } else if arg.starts_with("-d") {
             disassembly = true;
         }
     }
-    if test {
+    if capture {
+        if let Err(e) = capture::run(&options) {
+            println!("capture failed: {}", e);
+        }
+    } else if test {
         if disassembly {
-            use test_machine::load_rom;
-            let mut rom = [0; 0x2000];
-            load_rom(&mut rom);
+            let rom = test_machine::load_rom();
             let mut stdout = WriteAdapter(io::stdout());
-            dissasembly(&mut stdout, &rom, &[0x0]).unwrap();
+            dissasembly(&mut stdout, &rom, &[intel8080::cpm::TPA_START]).unwrap();
         } else {
-            test_machine::main_loop(debug);
+            test_machine::main_loop(options.debug, options.gdb);
         }
     } else if disassembly {
-        use space_invaders::load_rom;
-        let mut rom = [0; 0x2000];
-        load_rom(&mut rom);
-        let mut stdout = WriteAdapter(io::stdout());
-        dissasembly(&mut stdout, &rom, &[0x0u16, 0x8, 0x10]).unwrap();
+        match options.load_rom() {
+            Ok(rom) => {
+                let mut stdout = WriteAdapter(io::stdout());
+                dissasembly(&mut stdout, &rom.image, &ENTRIES).unwrap();
+            }
+            Err(e) => println!("{}", e),
+        }
     } else {
-        space_invaders::main_loop(debug);
+        space_invaders::main_loop(options);
     }
 }
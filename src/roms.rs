@@ -0,0 +1,530 @@
+//! Load the ROM set of a machine, from a directory or a MAME style zip, checking each chip
+//! against the checksums of a good dump.
+
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use space_invaders_machine::machines::{MachineDef, RomFile};
+
+/// What is wrong with a chip of the ROM set.
+#[derive(Debug)]
+pub enum ChipProblem {
+    Missing,
+    BadSize {
+        expected: usize,
+        found: usize,
+    },
+    BadCrc32 {
+        expected: u32,
+        found: u32,
+    },
+    BadSha1 {
+        expected: &'static str,
+        found: String,
+    },
+    /// The checksums of a good dump are not known, so the chip is only checked by its size.
+    UnknownChecksums,
+}
+impl ChipProblem {
+    /// If the chip can still be used.
+    pub fn is_warning(&self) -> bool {
+        matches!(self, ChipProblem::UnknownChecksums)
+    }
+}
+
+/// The chips of a set with a problem, by name.
+pub type ChipProblems = Vec<(&'static str, ChipProblem)>;
+
+impl fmt::Display for ChipProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChipProblem::Missing => write!(f, "missing"),
+            ChipProblem::BadSize { expected, found } => {
+                write!(f, "has {} bytes, expected {}", found, expected)
+            }
+            ChipProblem::BadCrc32 { expected, found } => {
+                write!(
+                    f,
+                    "bad dump, CRC32 is {:08x}, expected {:08x}",
+                    found, expected
+                )
+            }
+            ChipProblem::BadSha1 { expected, found } => {
+                write!(f, "bad dump, SHA1 is {}, expected {}", found, expected)
+            }
+            ChipProblem::UnknownChecksums => {
+                write!(f, "checksums unknown, the dump can't be verified")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    /// None of the searched paths has the ROM set.
+    NotFound {
+        machine: &'static str,
+        searched: Vec<PathBuf>,
+    },
+    Io(PathBuf, io::Error),
+    Zip(PathBuf, zip::result::ZipError),
+    /// Some chips of the set are missing or bad.
+    BadChips {
+        path: PathBuf,
+        chips: ChipProblems,
+    },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::NotFound { machine, searched } => {
+                write!(
+                    f,
+                    "the ROM set of '{}' was not found, searched in:",
+                    machine
+                )?;
+                for path in searched {
+                    write!(f, "\n  {}", path.display())?;
+                }
+                Ok(())
+            }
+            RomError::Io(path, e) => write!(f, "failed to read '{}': {}", path.display(), e),
+            RomError::Zip(path, e) => write!(f, "failed to read '{}': {}", path.display(), e),
+            RomError::BadChips { path, chips } => {
+                write!(f, "bad ROM set in '{}':", path.display())?;
+                for (name, problem) in chips {
+                    write!(f, "\n  {}: {}", name, problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+/// A loaded ROM set.
+#[derive(Debug)]
+pub struct RomSet {
+    /// The ROM image, with each chip at its adress.
+    pub image: Vec<u8>,
+    /// The colour map of a Taito board, or None if the game has no colour PROMs or they are
+    /// missing or bad.
+    pub color_map: Option<Vec<u8>>,
+    /// The problems that don't stop the game: chips with unknown checksums, and missing
+    /// or bad colour PROMs.
+    pub warnings: ChipProblems,
+}
+
+/// Where the chips are read from.
+enum RomSource {
+    /// A directory with a file for each chip.
+    Dir(PathBuf),
+    Zip(PathBuf),
+}
+impl RomSource {
+    /// Find the ROM set in 'path': a zip file, or a directory with the chips or with a
+    /// `<machine>.zip`.
+    fn find(path: &Path, machine: &MachineDef) -> Option<Self> {
+        if path.is_file() {
+            return Some(RomSource::Zip(path.to_owned()));
+        }
+        if !path.is_dir() {
+            return None;
+        }
+        if machine.roms.iter().any(|rom| path.join(rom.name).is_file()) {
+            return Some(RomSource::Dir(path.to_owned()));
+        }
+        let zip = path.join(format!("{}.zip", machine.name));
+        zip.is_file().then_some(RomSource::Zip(zip))
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            RomSource::Dir(path) | RomSource::Zip(path) => path,
+        }
+    }
+
+    /// Read each chip, or None if it is missing.
+    fn read_chips(&self, roms: &[RomFile]) -> Result<Vec<Option<Vec<u8>>>, RomError> {
+        match self {
+            RomSource::Dir(dir) => roms
+                .iter()
+                .map(|rom| {
+                    let path = dir.join(rom.name);
+                    match fs::read(&path) {
+                        Ok(data) => Ok(Some(data)),
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                        Err(e) => Err(RomError::Io(path, e)),
+                    }
+                })
+                .collect(),
+            RomSource::Zip(path) => {
+                read_zip(path, roms).map_err(|e| RomError::Zip(path.clone(), e))
+            }
+        }
+    }
+}
+
+/// Read the chips from a zip. Like MAME, a chip is found by its name, ignoring the case
+/// and the directories, or else by its CRC32.
+fn read_zip(path: &Path, roms: &[RomFile]) -> Result<Vec<Option<Vec<u8>>>, zip::result::ZipError> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let mut entries = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        let name = entry.name().rsplit('/').next().unwrap_or("").to_lowercase();
+        entries.push((name, entry.crc32()));
+    }
+
+    let mut chips = Vec::with_capacity(roms.len());
+    for rom in roms {
+        let index = entries
+            .iter()
+            .position(|(name, _)| *name == rom.name.to_lowercase())
+            .or_else(|| entries.iter().position(|&(_, crc)| Some(crc) == rom.crc32));
+        let chip = match index {
+            Some(i) => {
+                let mut data = Vec::new();
+                archive.by_index(i)?.read_to_end(&mut data)?;
+                Some(data)
+            }
+            None => None,
+        };
+        chips.push(chip);
+    }
+    Ok(chips)
+}
+
+/// Check the size and the checksums of a chip.
+fn check_chip(rom: &RomFile, data: &[u8]) -> Result<(), ChipProblem> {
+    if data.len() != rom.len {
+        return Err(ChipProblem::BadSize {
+            expected: rom.len,
+            found: data.len(),
+        });
+    }
+    if let Some(expected) = rom.crc32 {
+        let found = crc32fast::hash(data);
+        if found != expected {
+            return Err(ChipProblem::BadCrc32 { expected, found });
+        }
+    }
+    if let Some(expected) = rom.sha1 {
+        let found = sha1_smol::Sha1::from(data).digest().to_string();
+        if found != expected {
+            return Err(ChipProblem::BadSha1 { expected, found });
+        }
+    }
+    Ok(())
+}
+
+/// The paths searched when none is given: `rom` in the working directory, and `rom` and
+/// the directory of the executable.
+fn search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("rom")];
+    if let Some(dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_owned))
+    {
+        paths.push(dir.join("rom"));
+        paths.push(dir);
+    }
+    paths
+}
+
+/// Load the ROM set of the machine. 'path' is a directory or a zip file; if None, the
+/// default paths are searched.
+pub fn load_rom(machine: &'static MachineDef, path: Option<&Path>) -> Result<RomSet, RomError> {
+    let searched = match path {
+        Some(path) => vec![path.to_owned()],
+        None => search_paths(),
+    };
+    let source = searched
+        .iter()
+        .find_map(|path| RomSource::find(path, machine))
+        .ok_or_else(|| RomError::NotFound {
+            machine: machine.name,
+            searched: searched.clone(),
+        })?;
+
+    let (image, problems) = load_chips(&source, machine.roms, machine.rom_len())?;
+    let (mut warnings, problems): (ChipProblems, ChipProblems) = problems
+        .into_iter()
+        .partition(|(_, problem)| problem.is_warning());
+    if !problems.is_empty() {
+        return Err(RomError::BadChips {
+            path: source.path().to_owned(),
+            chips: problems,
+        });
+    }
+
+    // without its colour PROMs, the game runs in black and white
+    let (color_map, problems) = load_chips(&source, machine.color_proms, machine.color_map_len())?;
+    let color_map = (!machine.color_proms.is_empty()
+        && problems.iter().all(|(_, problem)| problem.is_warning()))
+    .then_some(color_map);
+    warnings.extend(problems);
+    Ok(RomSet {
+        image,
+        color_map,
+        warnings,
+    })
+}
+
+/// Read and check the chips, returning the image of `len` bytes with each usable chip at
+/// its adress, and the problems of the chips.
+fn load_chips(
+    source: &RomSource,
+    roms: &[RomFile],
+    len: usize,
+) -> Result<(Vec<u8>, ChipProblems), RomError> {
+    let mut image = vec![0; len];
+    let mut problems = Vec::new();
+    for (rom, chip) in roms.iter().zip(source.read_chips(roms)?) {
+        let Some(data) = chip else {
+            problems.push((rom.name, ChipProblem::Missing));
+            continue;
+        };
+        match check_chip(rom, &data) {
+            Ok(()) => {
+                let start = rom.adress as usize;
+                image[start..start + rom.len].copy_from_slice(&data);
+                if rom.crc32.is_none() && rom.sha1.is_none() {
+                    problems.push((rom.name, ChipProblem::UnknownChecksums));
+                }
+            }
+            Err(problem) => problems.push((rom.name, problem)),
+        }
+    }
+    Ok((image, problems))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use space_invaders_machine::machines::INVADERS;
+    use std::io::Write;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    /// The chips of Space Invaders in the `rom` directory of the repository.
+    fn invaders_chips() -> Vec<(&'static str, Vec<u8>)> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("rom");
+        INVADERS
+            .roms
+            .iter()
+            .map(|rom| (rom.name, fs::read(dir.join(rom.name)).unwrap()))
+            .collect()
+    }
+
+    /// A empty directory for a test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("roms-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_zip(path: &Path, files: &[(String, &[u8])]) {
+        let mut zip = ZipWriter::new(fs::File::create(path).unwrap());
+        for (name, data) in files {
+            zip.start_file(name.as_str(), SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// The chips of Space Invaders one after the other, as they are in the image.
+    fn concat(chips: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        chips.iter().flat_map(|(_, data)| data.clone()).collect()
+    }
+
+    fn bad_chips(result: Result<RomSet, RomError>) -> ChipProblems {
+        match result {
+            Err(RomError::BadChips { chips, .. }) => chips,
+            other => panic!("expected bad chips, found {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn check_chip_verifies_the_size_and_checksums() {
+        let chips = invaders_chips();
+        let (rom, (_, data)) = (&INVADERS.roms[0], &chips[0]);
+        assert!(check_chip(rom, data).is_ok());
+
+        assert!(matches!(
+            check_chip(rom, &data[..0x400]),
+            Err(ChipProblem::BadSize {
+                expected: 0x800,
+                found: 0x400
+            })
+        ));
+
+        let mut bad = data.clone();
+        bad[0x123] ^= 0x01;
+        assert!(matches!(
+            check_chip(rom, &bad),
+            Err(ChipProblem::BadCrc32 {
+                expected: 0x734f5ad8,
+                ..
+            })
+        ));
+
+        let only_sha1 = RomFile {
+            crc32: None,
+            ..*rom
+        };
+        assert!(matches!(
+            check_chip(&only_sha1, &bad),
+            Err(ChipProblem::BadSha1 { .. })
+        ));
+
+        // a chip without checksums is only checked by its size
+        let unknown = RomFile {
+            crc32: None,
+            sha1: None,
+            ..*rom
+        };
+        assert!(check_chip(&unknown, &bad).is_ok());
+    }
+
+    #[test]
+    fn directory_with_a_missing_chip() {
+        let dir = temp_dir("missing");
+        for (name, data) in &invaders_chips()[..3] {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        let chips = bad_chips(load_rom(&INVADERS, Some(&dir)));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(chips.len(), 1);
+        assert!(matches!(chips[0], ("invaders.e", ChipProblem::Missing)));
+    }
+
+    #[test]
+    fn zip_chips_are_found_by_name() {
+        let chips = invaders_chips();
+        let dir = temp_dir("zip-name");
+        // the names ignore the case and the directories
+        let files: Vec<(String, &[u8])> = chips
+            .iter()
+            .map(|(name, data)| (format!("set/{}", name.to_uppercase()), &data[..]))
+            .collect();
+        write_zip(&dir.join("invaders.zip"), &files);
+
+        let image = load_rom(&INVADERS, Some(&dir)).unwrap().image;
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(image, concat(&chips));
+    }
+
+    #[test]
+    fn zip_chips_are_found_by_crc() {
+        let chips = invaders_chips();
+        let dir = temp_dir("zip-crc");
+        let path = dir.join("renamed.zip");
+        let files: Vec<(String, &[u8])> = chips
+            .iter()
+            .enumerate()
+            .map(|(i, (_, data))| (format!("chip{}.bin", i), &data[..]))
+            .collect();
+        write_zip(&path, &files);
+        let image = load_rom(&INVADERS, Some(&path)).unwrap().image;
+
+        // a bad chip with another name is not found
+        let mut bad = chips[3].1.clone();
+        bad[0] ^= 0xff;
+        let mut files = files;
+        files[3].1 = &bad;
+        write_zip(&path, &files);
+        let problems = bad_chips(load_rom(&INVADERS, Some(&path)));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(image, concat(&chips));
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(problems[0], ("invaders.e", ChipProblem::Missing)));
+    }
+
+    #[test]
+    fn missing_color_proms_are_a_warning() {
+        const fn prom(name: &'static str, adress: u16) -> RomFile {
+            RomFile {
+                name,
+                adress,
+                len: 0x400,
+                crc32: None,
+                sha1: None,
+            }
+        }
+        static COLORED: MachineDef = MachineDef {
+            color_proms: &[prom("color.1", 0x000), prom("color.2", 0x400)],
+            ..INVADERS
+        };
+
+        let dir = temp_dir("proms");
+        for (name, data) in &invaders_chips() {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        fs::write(dir.join("color.1"), [0x01; 0x400]).unwrap();
+        let without = load_rom(&COLORED, Some(&dir)).unwrap();
+        fs::write(dir.join("color.2"), [0x02; 0x400]).unwrap();
+        let with = load_rom(&COLORED, Some(&dir)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // the game runs in black and white
+        assert_eq!(without.image, concat(&invaders_chips()));
+        assert!(without.color_map.is_none());
+        assert!(matches!(
+            without.warnings[..],
+            [
+                ("color.1", ChipProblem::UnknownChecksums),
+                ("color.2", ChipProblem::Missing)
+            ]
+        ));
+
+        let color_map = with.color_map.unwrap();
+        assert_eq!(color_map[..0x400], [0x01; 0x400]);
+        assert_eq!(color_map[0x400..], [0x02; 0x400]);
+        assert_eq!(with.warnings.len(), 2);
+    }
+
+    #[test]
+    fn unknown_checksums_are_a_warning() {
+        const fn unknown(rom: RomFile) -> RomFile {
+            RomFile {
+                crc32: None,
+                sha1: None,
+                ..rom
+            }
+        }
+        static UNKNOWN: MachineDef = MachineDef {
+            roms: &[
+                INVADERS.roms[0],
+                INVADERS.roms[1],
+                unknown(INVADERS.roms[2]),
+                INVADERS.roms[3],
+            ],
+            ..INVADERS
+        };
+
+        let dir = temp_dir("unknown");
+        for (name, data) in &invaders_chips() {
+            fs::write(dir.join(name), data).unwrap();
+        }
+        let rom = load_rom(&UNKNOWN, Some(&dir)).unwrap();
+        let known = load_rom(&INVADERS, Some(&dir)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // the chip is loaded, and reported
+        assert_eq!(rom.image, concat(&invaders_chips()));
+        assert!(matches!(
+            rom.warnings[..],
+            [("invaders.f", ChipProblem::UnknownChecksums)]
+        ));
+        assert!(known.warnings.is_empty());
+    }
+}
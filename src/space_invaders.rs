@@ -1,9 +1,21 @@
-use std::sync::mpsc::{Sender, channel};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
-use intel8080::{IODevices, Memory, interpreter};
 use rodio::Source;
+use space_invaders_machine::{
+    DipSwitches, ExtraShip, SpaceInvaders,
+    audio::{self, SamplePlayer},
+    machines::{self, MachineDef},
+    movie::Movie,
+    presentation::{Overlay, Presentation},
+    synth::Synth,
+};
 
-use std::{io::Cursor, thread};
+use std::io::Cursor;
 
 use winit::{
     dpi::{LogicalPosition, LogicalSize, PhysicalSize},
@@ -13,8 +25,18 @@ use winit::{
 
 use pixels::{Pixels, SurfaceTexture};
 
-const SCREEN_WIDTH: u32 = 224;
-const SCREEN_HEIGHT: u32 = 256;
+use crate::{
+    config::{CONFIG_PATH, Config, parse_on_off},
+    input::{Gamepads, HeldInputs, InputSource, Keymap},
+    roms::{self, RomError, RomSet},
+};
+#[cfg(feature = "debug")]
+use {
+    crate::{debugger_console::Console, debugger_panel::DebuggerPanel},
+    intel8080::gdb::GdbStub,
+};
+
+const SAVE_STATE_PATH: &str = "space_invaders.sav";
 
 static SOUND_BANK: [&[u8]; 9] = [
     include_bytes!("../sound/0.wav"),
@@ -28,231 +50,278 @@ static SOUND_BANK: [&[u8]; 9] = [
     include_bytes!("../sound/8.wav"),
 ];
 
-enum AudioMessage {
-    Play(u8),
-    StartUfo,
-    StopUfo,
+/// Decode the recorded samples, for the sample player.
+fn load_sound_bank() -> Vec<Vec<f32>> {
+    SOUND_BANK
+        .iter()
+        .map(|wav| {
+            let decoder = rodio::Decoder::new(Cursor::new(*wav)).unwrap();
+            let sample_rate = decoder.sample_rate();
+            let pcm: Vec<i16> = decoder.collect();
+            audio::resample(&pcm, sample_rate)
+        })
+        .collect()
 }
 
-struct SpaceInvadersDevices {
-    shift_register: u16,
-    shift_amount: u8,
-    read_ports: [u8; 3],
+/// The samples mixed by the emulation, waiting to be played.
+type SampleQueue = Arc<Mutex<VecDeque<f32>>>;
+
+/// The maximum number of queued samples. When the emulation runs faster than the audio
+/// output, the oldest ones are dropped to keep the latency low.
+const MAX_QUEUED_SAMPLES: usize = audio::SAMPLE_RATE as usize / 10;
 
-    wport3: u8,
-    wport5: u8,
-    audio_channel: Sender<AudioMessage>,
+/// Plays the samples from the queue, or silence when it is empty.
+struct QueueSource {
+    queue: SampleQueue,
+    /// The samples taken from the queue, to not lock it for each sample.
+    local: VecDeque<f32>,
 }
-impl SpaceInvadersDevices {
-    fn new(ports: [u8; 3]) -> Self {
-        let (sx, rx) = channel();
-
-        thread::spawn(move || {
-            let device = rodio::default_output_device().unwrap();
-            let ufo = rodio::Sink::new(&device);
-            ufo.pause();
-            ufo.append(
-                rodio::Decoder::new(Cursor::new(SOUND_BANK[0]))
-                    .unwrap()
-                    .repeat_infinite(),
-            );
+impl Iterator for QueueSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.local.is_empty() {
+            let mut queue = self.queue.lock().unwrap();
+            let len = queue.len().min(512);
+            self.local.extend(queue.drain(..len));
+        }
+        Some(self.local.pop_front().unwrap_or(0.0))
+    }
+}
+impl Source for QueueSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
 
-            while let Ok(msg) = rx.recv() {
-                match msg {
-                    AudioMessage::Play(index) => {
-                        rodio::play_raw(
-                            &device,
-                            rodio::Decoder::new(Cursor::new(SOUND_BANK[index as usize]))
-                                .unwrap()
-                                .convert_samples(),
-                        );
-                    }
-                    AudioMessage::StartUfo => {
-                        ufo.play();
-                    }
-                    AudioMessage::StopUfo => {
-                        ufo.pause();
-                    }
-                }
-            }
-        });
+    fn channels(&self) -> u16 {
+        1
+    }
 
-        Self {
-            shift_register: 0,
-            shift_amount: 0,
-            read_ports: ports,
+    fn sample_rate(&self) -> u32 {
+        audio::SAMPLE_RATE
+    }
 
-            wport3: 0,
-            wport5: 0,
-            audio_channel: sx,
-        }
+    fn total_duration(&self) -> Option<std::time::Duration> {
+        None
     }
+}
+
+/// Start playing the audio output, returning the queue that feeds it. Without a output
+/// device, the queue is not played, and the game runs without sound.
+fn start_audio_output() -> SampleQueue {
+    let queue = SampleQueue::default();
+    let Some(device) = rodio::default_output_device() else {
+        println!("no audio output device, the sound is not played");
+        return queue;
+    };
+    rodio::play_raw(
+        &device,
+        QueueSource {
+            queue: queue.clone(),
+            local: VecDeque::new(),
+        },
+    );
+    queue
+}
+
+/// The command line options of the game.
+#[derive(Default)]
+pub struct Options {
+    pub debug: bool,
+    /// Listen for a GDB client on this port of localhost.
+    pub gdb: Option<u16>,
+    /// The game to run. Space Invaders if None.
+    pub machine: Option<&'static MachineDef>,
+    /// The directory or zip file with the ROM set, instead of the default paths.
+    pub rom_path: Option<String>,
+    /// Record the inputs since power on to this movie file, saved when the window closes.
+    pub record: Option<String>,
+    /// Replay the inputs of this movie file.
+    pub play: Option<String>,
+    /// Synthesize the sounds, instead of playing the recorded samples.
+    pub synth: bool,
+    /// Reset the machine if the ROM stops writing to the watchdog port.
+    pub watchdog: bool,
+    /// Capture the audio to this WAV file, saved when the window closes.
+    pub wav: Option<String>,
+    /// The number of frames to run in the capture mode.
+    pub frames: Option<u32>,
+    /// Write each frame as a PNG in this directory, in the capture mode.
+    pub png: Option<String>,
+    /// Write the frames as a animated GIF, in the capture mode.
+    pub gif: Option<String>,
+    /// Dip switches overrides, saved to the config file.
+    pub ships: Option<u8>,
+    pub extra_ship: Option<ExtraShip>,
+    pub coin_info: Option<bool>,
+    /// Presentation overrides, for this run only.
+    pub overlay: Option<String>,
+    pub background: Option<String>,
+    pub scanlines: bool,
+    pub persistence: Option<f32>,
+    pub blur: bool,
+}
 
-    fn start_ufo(&mut self) {
-        self.audio_channel.send(AudioMessage::StartUfo).unwrap();
+impl Options {
+    pub fn machine(&self) -> &'static MachineDef {
+        self.machine.unwrap_or(&machines::INVADERS)
     }
-    fn stop_ufo(&mut self) {
-        self.audio_channel.send(AudioMessage::StopUfo).unwrap();
+
+    pub fn load_rom(&self) -> Result<RomSet, RomError> {
+        roms::load_rom(self.machine(), self.rom_path.as_deref().map(Path::new))
     }
+}
 
-    fn play_sound(&mut self, index: u8) {
-        self.audio_channel.send(AudioMessage::Play(index)).unwrap();
+/// Load the dip switches from the config, applying the overrides given in the command
+/// line.
+fn load_dip_switches(config: &mut Config, options: &Options) -> DipSwitches {
+    let mut dip_switches = config.dip_switches();
+    if let Some(ships) = options.ships {
+        let _ = dip_switches.set_ships(ships);
+    }
+    if let Some(extra_ship) = options.extra_ship {
+        dip_switches.extra_ship = extra_ship;
     }
+    if let Some(coin_info) = options.coin_info {
+        dip_switches.coin_info = coin_info;
+    }
+
+    if options.ships.is_some() || options.extra_ship.is_some() || options.coin_info.is_some() {
+        config.set_dip_switches(dip_switches);
+    }
+    dip_switches
 }
-impl IODevices for SpaceInvadersDevices {
-    fn read(&mut self, device: u8) -> u8 {
-        match device {
-            i @ 0..=2 => self.read_ports[i as usize],
-            3 => (self.shift_register >> (8 - self.shift_amount)) as u8,
-            _ => 0,
-        }
+
+/// Load a RGBA image with the size of the screen, as (width, height).
+fn load_image(path: &str, (width, height): (u32, u32)) -> io::Result<Box<[u8]>> {
+    let mut decoder = png::Decoder::new(fs::File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(io::Error::other)?;
+    if (info.width, info.height) != (width, height) {
+        return Err(io::Error::other(format!(
+            "the image is {}x{}, expected {}x{}",
+            info.width, info.height, width, height
+        )));
     }
+    let pixels = &buf[..info.buffer_size()];
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xff])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        _ => pixels.iter().flat_map(|&p| [p, p, p, 0xff]).collect(),
+    };
+    Ok(rgba.into_boxed_slice())
+}
 
-    fn write(&mut self, device: u8, value: u8) {
-        match device {
-            2 => self.shift_amount = value & 0b111,
-            3 => {
-                // sound
-                let check_bit = |byte: u8, i: u8| byte & (0b1 << i) != 0;
-
-                if check_bit(value, 0) && !check_bit(self.wport3, 0) {
-                    self.start_ufo();
-                } else if !check_bit(value, 0) && check_bit(self.wport3, 0) {
-                    self.stop_ufo();
-                }
+/// Load the presentation from the config, applying the overrides given in the command
+/// line.
+pub fn load_presentation(config: &mut Config, options: &Options) -> Presentation {
+    let mut presentation = Presentation::for_machine(options.machine());
+    let size = presentation.screen_size();
 
-                if check_bit(value, 1) && !check_bit(self.wport3, 1) {
-                    self.play_sound(1);
-                }
-                if check_bit(value, 2) && !check_bit(self.wport3, 2) {
-                    self.play_sound(2);
-                }
-                if check_bit(value, 3) && !check_bit(self.wport3, 3) {
-                    self.play_sound(3);
-                }
-                self.wport3 = value;
+    let overlay = match &options.overlay {
+        Some(overlay) => overlay.clone(),
+        None => config.get_or_insert("video.overlay", "classic").to_string(),
+    };
+    presentation.overlay = match overlay.as_str() {
+        "mono" => Overlay::Monochrome,
+        "classic" => Overlay::Classic,
+        "deluxe" => Overlay::Deluxe,
+        path => match load_image(path, size) {
+            Ok(image) => Overlay::Image(image),
+            Err(e) => {
+                println!("failed to load overlay '{}': {}", path, e);
+                Overlay::Classic
             }
-            4 => self.shift_register = (self.shift_register >> 8) | ((value as u16) << 8),
-            5 => {
-                // sound
-                let check_bit = |byte: u8, i: u8| byte & (0b1 << i) != 0;
+        },
+    };
 
-                if check_bit(value, 0) && !check_bit(self.wport3, 0) {
-                    self.play_sound(4);
-                }
-                if check_bit(value, 1) && !check_bit(self.wport3, 1) {
-                    self.play_sound(5);
-                }
-                if check_bit(value, 2) && !check_bit(self.wport3, 2) {
-                    self.play_sound(6);
-                }
-                if check_bit(value, 3) && !check_bit(self.wport3, 3) {
-                    self.play_sound(7);
-                }
-                if check_bit(value, 4) && !check_bit(self.wport3, 4) {
-                    self.play_sound(8);
-                }
-                self.wport5 = value;
-            }
-            _ => (),
-        };
+    let background = match &options.background {
+        Some(background) => background.clone(),
+        None => config.get_or_insert("video.background", "").to_string(),
+    };
+    if !background.is_empty() {
+        match load_image(&background, size) {
+            Ok(image) => presentation.background = Some(image),
+            Err(e) => println!("failed to load background '{}': {}", background, e),
+        }
     }
+
+    let scanlines = config.get_or_insert("video.scanlines", "off");
+    presentation.scanlines = options.scanlines || parse_on_off(scanlines) == Some(true);
+    let blur = config.get_or_insert("video.blur", "off");
+    presentation.blur = options.blur || parse_on_off(blur) == Some(true);
+    let persistence = config.get_or_insert("video.persistence", "0").parse().ok();
+    presentation.persistence = options.persistence.or(persistence).unwrap_or(0.0);
+
+    presentation
 }
 
-struct SpaceInvadersMemory {
-    memory: [u8; 0x4000],
+pub fn load_config() -> Config {
+    Config::load(CONFIG_PATH).unwrap_or_else(|e| {
+        println!("failed to load '{}': {}", CONFIG_PATH, e);
+        Config::default()
+    })
 }
-impl Memory for SpaceInvadersMemory {
-    #[inline]
-    fn read(&self, mut adress: u16) -> u8 {
-        if adress > 0x4000 {
-            adress = (adress % 0x2000) + 0x2000;
-        }
-        if (adress as usize) < self.memory.len() {
-            self.memory[adress as usize]
-        } else {
-            #[cfg(feature = "debug")]
-            println!("Reading out of memory! At {:04x}!", adress);
-            0
-        }
+
+/// Create the machine with the dip switches, sound and movie given by the options.
+pub fn create_machine(options: &Options, config: &mut Config) -> Result<SpaceInvaders, RomError> {
+    let rom = options.load_rom()?;
+    for (name, problem) in &rom.warnings {
+        println!("{}: {}", name, problem);
     }
 
-    fn write(&mut self, mut adress: u16, value: u8) {
-        if adress > 0x4000 {
-            adress = (adress % 0x2000) + 0x2000;
-        }
-        if (adress as usize) < self.memory.len() {
-            if adress >= 0x2000 {
-                // adress < 0x2000 is ROM
-                self.memory[adress as usize] = value;
-            } else {
-                #[cfg(feature = "debug")]
-                println!("Writing to ROM?");
-            }
-        } else {
-            #[cfg(feature = "debug")]
-            println!("Writing out of memory! At {:04x}!", adress);
-        }
+    let mut machine = SpaceInvaders::with_machine(options.machine(), &rom.image);
+    if let Some(color_map) = &rom.color_map {
+        machine.set_color_map(color_map);
     }
+    machine.set_dip_switches(load_dip_switches(config, options));
+    machine.set_watchdog(options.watchdog);
 
-    fn get_rom(&mut self) -> Vec<u8> {
-        let mut rom = Vec::with_capacity(0x2000);
-        for i in 0..0x2000 {
-            rom.push(self.memory[i]);
-        }
-        rom
+    if options.synth {
+        machine.enable_audio(Box::new(Synth::new(audio::SAMPLE_RATE)));
+    } else {
+        machine.enable_audio(Box::new(SamplePlayer::new(load_sound_bank())));
     }
-}
 
-pub fn load_rom(buf: &mut [u8]) {
-    use std::fs;
-    use std::io::Read;
-    let mut andress = 0;
-    for e in ['h', 'g', 'f', 'e'].iter() {
-        let mut file = fs::File::open(format!("rom/invaders.{}", e)).unwrap();
-        file.read_exact(&mut buf[andress..andress + 0x800]).unwrap();
-        andress += 0x800;
+    if let Some(path) = &options.play {
+        match Movie::load(path).and_then(|movie| machine.play_movie(movie)) {
+            Ok(()) => println!("playing movie '{}'", path),
+            Err(e) => println!("failed to play movie '{}': {}", path, e),
+        }
+    } else if options.record.is_some() {
+        machine.start_recording();
     }
+
+    Ok(machine)
 }
 
-fn render_screen(screen: &mut [u8], memory: &[u8]) {
-    for x in 0..SCREEN_WIDTH {
-        for y in 0..SCREEN_HEIGHT {
-            let i = (x * SCREEN_HEIGHT + y) as usize;
-            if i / 8 >= memory.len() {
-                println!(
-                    "x: {}, y: {}, i: {}, i/8: {}, memory.len(): {}",
-                    x,
-                    y,
-                    i,
-                    i / 8,
-                    memory.len()
-                );
-                return;
-            }
-            let m = memory[i / 8];
-            let c = if (m >> (i % 8)) & 0x1 != 0 { 0xff } else { 0x0 };
-            let p = ((SCREEN_HEIGHT - y - 1) * SCREEN_WIDTH + x) as usize * 4;
-            screen[p] = if y >= 72 || (y < 16 && !(16..102).contains(&x)) {
-                c
-            } else {
-                0
-            };
-            screen[p + 1] = if !(192..224).contains(&y) { c } else { 0 };
-            screen[p + 2] =
-                if (72..192).contains(&y) || y >= 224 || (y < 16 && !(16..102).contains(&x)) {
-                    c
-                } else {
-                    0
-                };
-            screen[p + 3] = 0xff;
+/// Listen for a GDB client on 'port' of localhost.
+#[cfg(feature = "debug")]
+pub fn start_gdb_stub(port: u16) -> Option<GdbStub> {
+    match GdbStub::bind(("127.0.0.1", port)) {
+        Ok(stub) => {
+            println!("gdb: listening on 127.0.0.1:{}", port);
+            Some(stub)
+        }
+        Err(e) => {
+            println!("gdb: failed to listen on port {}: {}", port, e);
+            None
         }
     }
 }
 
-pub fn main_loop(debug: bool) {
+pub fn main_loop(options: Options) {
     #[cfg(not(feature = "debug"))]
-    let _ = debug;
+    let _ = (options.debug, options.gdb);
 
     let event_loop = EventLoop::new();
 
@@ -261,121 +330,176 @@ pub fn main_loop(debug: bool) {
     //     .build(&event_loop)
     //     .unwrap();
 
-    let (window, p_width, p_height, _) = create_window("SPACE INVADERS!!", &event_loop);
+    let title = format!("{}!!", options.machine().title.to_uppercase());
+    let mut config = load_config();
+    let keymap = Keymap::from_config(&mut config);
+    let mut presentation = load_presentation(&mut config, &options);
 
-    let surface_texture = SurfaceTexture::new(p_width, p_height, &window);
+    let screen_size = presentation.screen_size();
+    let (window, p_width, p_height, _) = create_window(&title, screen_size, &event_loop);
 
-    let mut pixels = Pixels::new(SCREEN_WIDTH, SCREEN_HEIGHT, surface_texture).unwrap();
+    let surface_texture = SurfaceTexture::new(p_width, p_height, &window);
+    let mut gamepads = Gamepads::new();
+    let mut held = HeldInputs::new();
 
-    let ports = [0b0000_1111, 0b0000_1000, 0b0000_0000];
+    let (width, height) = (presentation.width(), presentation.height());
+    let mut pixels = Pixels::new(width, height, surface_texture).unwrap();
 
-    let mut memory = [0; 0x4000];
-    load_rom(&mut memory);
+    let mut machine = match create_machine(&options, &mut config) {
+        Ok(machine) => machine,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut playing = machine.is_playing_movie();
 
-    let mut interpreter = interpreter::Interpreter::new(
-        SpaceInvadersDevices::new(ports),
-        SpaceInvadersMemory { memory },
-        &[0x0u16, 0x8, 0x10],
-        // debug,
-    );
-    #[cfg(feature = "debug")]
+    if config.is_modified()
+        && let Err(e) = config.save(CONFIG_PATH)
     {
-        if debug {
-            interpreter.enter_debug_mode();
-        }
+        println!("failed to save '{}': {}", CONFIG_PATH, e);
+    }
+    #[cfg(feature = "debug")]
+    let mut panel = DebuggerPanel::new();
+    #[cfg(feature = "debug")]
+    let mut gdb = options.gdb.and_then(start_gdb_stub);
+    #[cfg(feature = "debug")]
+    let mut console = Console::spawn();
+    #[cfg(feature = "debug")]
+    if options.debug {
+        machine.interpreter_mut().memory.set_log_faults(true);
+        panel.open(&mut machine);
+        let (w, h) = panel.frame_size(width, height);
+        pixels.resize_buffer(w, h);
+    }
+
+    if options.wav.is_some() {
+        machine.start_audio_capture();
     }
+    let audio = start_audio_output();
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
 
         match event {
             Event::RedrawRequested(window_id) if window_id == window.id() => {
-                render_screen(pixels.get_frame(), &interpreter.memory.memory[0x2400..]);
+                let screen = presentation.present(&machine);
+                #[cfg(feature = "debug")]
+                if panel.is_visible() {
+                    panel.draw(&machine, screen, (width, height), pixels.get_frame());
+                } else {
+                    pixels.get_frame().copy_from_slice(screen);
+                }
+                #[cfg(not(feature = "debug"))]
+                pixels.get_frame().copy_from_slice(screen);
                 pixels.render().unwrap();
-                interpreter.run(2_000_000 / 120);
-                interpreter.interrupt(0b11010111); // RST 2 (0xd7)
-                interpreter.run(2_000_000 / 120);
-                interpreter.interrupt(0b11001111); // RST 1 (0xcf)
+                #[cfg(feature = "debug")]
+                if let Some(gdb) = &mut gdb {
+                    gdb.poll(machine.interpreter_mut());
+                }
+                #[cfg(feature = "debug")]
+                console.poll(machine.interpreter_mut());
+                if !machine.interpreter().is_stopped() {
+                    machine.step_frame();
+                }
+                #[cfg(feature = "debug")]
+                for message in machine.interpreter_mut().debugger_mut().drain_log() {
+                    println!("{}", message);
+                }
+                #[cfg(feature = "debug")]
+                if !panel.is_visible() {
+                    panel.update(&mut machine);
+                    if panel.is_visible() {
+                        resize(&window, &mut pixels, panel.frame_size(width, height));
+                    }
+                } else {
+                    panel.update(&mut machine);
+                }
+                if machine.watchdog_fired() {
+                    println!("watchdog fired, the machine was reset");
+                }
+                if playing && !machine.is_playing_movie() {
+                    playing = false;
+                    println!("movie ended");
+                }
+                machine.drain_sound_events().for_each(drop);
+                let mut queue = audio.lock().unwrap();
+                queue.extend(machine.drain_samples());
+                let excess = queue.len().saturating_sub(MAX_QUEUED_SAMPLES);
+                queue.drain(..excess);
+            }
+            Event::MainEventsCleared => {
+                gamepads.poll(&keymap, &mut held, |input, pressed| {
+                    machine.set_input(input, pressed)
+                });
+                window.request_redraw()
             }
-            Event::MainEventsCleared => window.request_redraw(),
             Event::WindowEvent { event, window_id } if window_id == window.id() => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                WindowEvent::KeyboardInput {
-                    input:
-                        KeyboardInput {
-                            virtual_keycode: Some(key),
-                            state: ElementState::Pressed,
-                            ..
-                        },
-                    is_synthetic: false,
-                    ..
-                } => match key {
-                    // PRESSED
-                    VirtualKeyCode::Left => {
-                        // LEFT
-                        interpreter.devices.read_ports[1] |= 0b0010_0000; // P1
-                        interpreter.devices.read_ports[2] |= 0b0010_0000; // P2
+                WindowEvent::Resized(size) => pixels.resize_surface(size.width, size.height),
+                WindowEvent::CloseRequested => {
+                    if let (Some(path), Some(movie)) = (&options.record, machine.stop_recording()) {
+                        match movie.save(path) {
+                            Ok(()) => println!("movie saved to '{}'", path),
+                            Err(e) => println!("failed to save movie: {}", e),
+                        }
                     }
-                    VirtualKeyCode::Right => {
-                        // RIGHT
-                        interpreter.devices.read_ports[1] |= 0b0100_0000; // P1
-                        interpreter.devices.read_ports[2] |= 0b0100_0000; // P2
+                    if let (Some(path), Some(samples)) =
+                        (&options.wav, machine.stop_audio_capture())
+                    {
+                        match audio::write_wav(path, &samples) {
+                            Ok(()) => println!("audio saved to '{}'", path),
+                            Err(e) => println!("failed to save audio: {}", e),
+                        }
                     }
-                    VirtualKeyCode::Z => {
-                        // SHOOT
-                        interpreter.devices.read_ports[1] |= 0b0001_0000; // P1
-                        interpreter.devices.read_ports[2] |= 0b0001_0000; // P2
-                    }
-                    VirtualKeyCode::C => {
-                        interpreter.devices.read_ports[1] |= 0b0000_0001;
-                    } // COIN
-                    VirtualKeyCode::Return => {
-                        interpreter.devices.read_ports[1] |= 0b0000_0100;
-                    } // P1 START
-                    VirtualKeyCode::Back => {
-                        interpreter.devices.read_ports[1] |= 0b0000_0010;
-                    } // P2 START
-                    #[cfg(feature = "debug")]
-                    VirtualKeyCode::Escape => interpreter.enter_debug_mode(),
-                    _ => (),
-                },
+                    *control_flow = ControlFlow::Exit
+                }
                 WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
                             virtual_keycode: Some(key),
-                            state: ElementState::Released,
+                            state,
                             ..
                         },
                     is_synthetic: false,
                     ..
-                } => match key {
-                    // RELEASED
-                    VirtualKeyCode::Left => {
-                        // LEFT
-                        interpreter.devices.read_ports[1] &= !0b0010_0000; // P1
-                        interpreter.devices.read_ports[2] &= !0b0010_0000; // P2
-                    }
-                    VirtualKeyCode::Right => {
-                        // RIGHT
-                        interpreter.devices.read_ports[1] &= !0b0100_0000; // P1
-                        interpreter.devices.read_ports[2] &= !0b0100_0000; // P2
-                    }
-                    VirtualKeyCode::Z => {
-                        // SHOOT
-                        interpreter.devices.read_ports[1] &= !0b0001_0000; // P1
-                        interpreter.devices.read_ports[2] &= !0b0001_0000; // P2
+                } => {
+                    let pressed = state == ElementState::Pressed;
+                    match key {
+                        VirtualKeyCode::F5 if pressed => {
+                            match machine.save_state_to_file(SAVE_STATE_PATH) {
+                                Ok(()) => println!("state saved to '{}'", SAVE_STATE_PATH),
+                                Err(e) => println!("failed to save state: {}", e),
+                            }
+                        }
+                        VirtualKeyCode::F9 if pressed => {
+                            match machine.load_state_from_file(SAVE_STATE_PATH) {
+                                Ok(()) => println!("state loaded from '{}'", SAVE_STATE_PATH),
+                                Err(e) => println!("failed to load state: {}", e),
+                            }
+                        }
+                        #[cfg(feature = "debug")]
+                        VirtualKeyCode::Escape if pressed => {
+                            if panel.is_visible() {
+                                panel.close(&mut machine);
+                            } else {
+                                panel.open(&mut machine);
+                            }
+                            resize(&window, &mut pixels, panel.frame_size(width, height));
+                        }
+                        #[cfg(feature = "debug")]
+                        _ if pressed && panel.is_visible() && panel.key(key, &mut machine) => {}
+                        _ => {
+                            if let Some(input) = keymap.key(key) {
+                                held.set(
+                                    input,
+                                    InputSource::Key(key),
+                                    pressed,
+                                    |input, pressed| machine.set_input(input, pressed),
+                                );
+                            }
+                        }
                     }
-                    VirtualKeyCode::C => {
-                        interpreter.devices.read_ports[1] &= !0b0000_0001;
-                    } // COIN
-                    VirtualKeyCode::Return => {
-                        interpreter.devices.read_ports[1] &= !0b0000_0100;
-                    } // P1 START
-                    VirtualKeyCode::Back => {
-                        interpreter.devices.read_ports[1] &= !0b0000_0010;
-                    } // P2 START
-                    _ => (),
-                },
+                }
                 _ => (),
             },
             _ => (),
@@ -383,8 +507,18 @@ pub fn main_loop(debug: bool) {
     });
 }
 
+/// Resize the buffer of the window, keeping the height of the window and the aspect ratio
+/// of the buffer.
+#[cfg(feature = "debug")]
+fn resize(window: &winit::window::Window, pixels: &mut Pixels, (width, height): (u32, u32)) {
+    pixels.resize_buffer(width, height);
+    let size = window.inner_size();
+    window.set_inner_size(PhysicalSize::new(size.height * width / height, size.height));
+}
+
 fn create_window(
     title: &str,
+    (width, height): (u32, u32),
     event_loop: &EventLoop<()>,
 ) -> (winit::window::Window, u32, u32, f64) {
     // Create a hidden window so we can estimate a good default window size
@@ -396,8 +530,8 @@ fn create_window(
     let hidpi_factor = window.scale_factor();
 
     // Get dimensions
-    let width = SCREEN_WIDTH as f64;
-    let height = SCREEN_HEIGHT as f64;
+    let width = width as f64;
+    let height = height as f64;
     let default_size;
     match window.current_monitor() {
         Some(monitor) => {
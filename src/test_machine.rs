@@ -1,91 +1,89 @@
-use crate::interpreter;
-use intel8080::{IODevices, Memory};
+use std::io::Write;
 
-struct TestDevices;
-impl IODevices for TestDevices {
-    fn read(&mut self, _: u8) -> u8 {
-        0
-    }
+use crate::interpreter::Interpreter;
+use intel8080::{
+    Memory,
+    cpm::{self, CpmDevices, CpmMemory},
+};
+#[cfg(feature = "debug")]
+use {
+    crate::{debugger_console, interpreter::StopReason, space_invaders::start_gdb_stub},
+    intel8080::gdb::GdbStub,
+};
 
-    fn write(&mut self, device: u8, value: u8) {
-        match device {
-            0 => print!("CPU HAS FAILED    ERROR EXIT="),
-            1 => print!("CPU IS OPERATIONAL"),
-            2 => print!("{}", value as char),
-            3 => {
-                println!();
-                std::process::exit(0)
-            }
-            _ => (),
-        };
-    }
-}
+/// The Microcosm cpu diagnostic, a CP/M program.
+const TEST_PROGRAM: &str = "rom/cpu_tests/TST8080.COM";
 
-struct TestMemory {
-    memory: [u8; 0x4000],
-}
-impl Memory for TestMemory {
-    fn read(&self, adress: u16) -> u8 {
-        if (adress as usize) < self.memory.len() {
-            self.memory[adress as usize]
-        } else {
-            println!("Read out of memory! At 0x{:04x}!", adress);
-            0
-        }
-    }
-
-    fn write(&mut self, adress: u16, value: u8) {
-        if adress < 0x05a4 {
-            println!("overwriting code at 0x{:04x}!", adress);
-        } else if (adress as usize) < self.memory.len() {
-            self.memory[adress as usize] = value;
-        } else {
-            println!("Write out of memory! At 0x{:04x}!", adress);
-        }
+/// The memory seen by the test program, with the CP/M page zero and BDOS, for the
+/// disassembly.
+pub fn load_rom() -> Vec<u8> {
+    match cpm::create_interpreter(&read_program()) {
+        Ok(mut interpreter) => interpreter.memory.get_rom(),
+        Err(e) => panic!("{}: {}", TEST_PROGRAM, e),
     }
+}
 
-    fn get_rom(&mut self) -> Vec<u8> {
-        self.memory.to_vec()
-    }
+fn read_program() -> Vec<u8> {
+    std::fs::read(TEST_PROGRAM).unwrap_or_else(|e| panic!("Failed to read {}: {}", TEST_PROGRAM, e))
 }
 
-pub fn load_rom(memory: &mut [u8]) {
-    use std::fs;
-    use std::io::Read;
-    let mut file = fs::File::open("rom/test.com").unwrap();
-    let mut offset = 0;
-    loop {
-        match file.read(&mut memory[offset..]) {
-            Ok(0) => break,
-            Ok(n) => offset += n,
-            Err(e) => panic!("Failed to read file: {}", e),
-        }
-        if offset >= memory.len() {
-            break;
-        }
+/// Print the console output of the program, returning true if it has exited.
+fn print_output(interpreter: &mut Interpreter<CpmMemory, CpmDevices>) -> bool {
+    print!("{}", interpreter.devices.take_output());
+    std::io::stdout().flush().unwrap();
+    if interpreter.devices.has_exited() {
+        println!();
+        return true;
     }
+    false
 }
 
-pub fn main_loop(debug: bool) {
+pub fn main_loop(debug: bool, gdb: Option<u16>) {
     #[cfg(not(feature = "debug"))]
-    let _ = debug;
-
-    let mut memory = [0; 0x4000];
-    load_rom(&mut memory);
+    let _ = (debug, gdb);
 
-    let mut interpreter = interpreter::Interpreter::new(
-        TestDevices,
-        TestMemory { memory },
-        &[0x0],
-        // debug
-    );
+    let mut interpreter = match cpm::create_interpreter(&read_program()) {
+        Ok(interpreter) => interpreter,
+        Err(e) => {
+            println!("{}: {}", TEST_PROGRAM, e);
+            return;
+        }
+    };
 
     #[cfg(feature = "debug")]
     {
         if debug {
             interpreter.enter_debug_mode();
         }
+        let mut gdb = gdb.and_then(start_gdb_stub);
+        loop {
+            if let Some(gdb) = &mut gdb {
+                gdb.poll(&mut interpreter);
+            }
+            let reason = interpreter.run(100_000);
+            for message in interpreter.debugger_mut().drain_log() {
+                println!("{}", message);
+            }
+            if print_output(&mut interpreter) {
+                return;
+            }
+            if reason == StopReason::ClockReached {
+                continue;
+            }
+            if gdb.as_ref().is_some_and(GdbStub::is_attached) {
+                // the client resumes the cpu
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            } else {
+                debugger_console::prompt(&mut interpreter);
+            }
+        }
     }
 
-    interpreter.run_forever();
+    #[cfg(not(feature = "debug"))]
+    loop {
+        interpreter.run(100_000);
+        if print_output(&mut interpreter) {
+            return;
+        }
+    }
 }